@@ -0,0 +1,182 @@
+use crate::exif::ReadAt;
+use crate::logger;
+use crate::bytes::*;
+
+/// Box of an ISO Base Media file (MP4, QuickTime, HEIF, CR3): its type and where the payload lies.
+pub struct BMFFBox {
+    pub box_type: [u8; 4],
+    /// extended type of 'uuid' boxes
+    pub uuid: Option<[u8; 16]>,
+    /// file offset of the payload, after the header
+    pub offset: u64,
+    /// payload size
+    pub size: u64,
+}
+
+/// 'ftyp' box.
+pub struct FileType {
+    pub major_brand: String,
+    pub minor_version: u32,
+    pub compatible_brands: Vec<String>,
+}
+
+/// Big endian fields of a box payload, read in order.
+pub(crate) struct BoxReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+/// 'uuid' box holding an XMP packet.
+pub(crate) const XMP_UUID: [u8; 16] = [
+    0xBE, 0x7A, 0xCF, 0xCB, 0x97, 0xA9, 0x42, 0xE8, 0x9C, 0x71, 0x99, 0x94, 0x91, 0xE3, 0xAF, 0xAC,
+];
+
+impl BMFFBox {
+    pub fn type_name(&self) -> String {
+        String::from_utf8_lossy(&self.box_type).to_string()
+    }
+
+    pub(crate) fn children<S: ReadAt>(&self, src: &mut S) -> Vec<BMFFBox> {
+        read_boxes(src, self.offset, self.offset + self.size)
+    }
+
+    /// Children of a full box, after the version, flags and `skip` more bytes.
+    pub(crate) fn full_children<S: ReadAt>(&self, src: &mut S, skip: u64) -> Vec<BMFFBox> {
+        read_boxes(src, self.offset + 4 + skip, self.offset + self.size)
+    }
+
+    pub(crate) fn data<S: ReadAt>(&self, src: &mut S) -> Option<Vec<u8>> {
+        src.read_at(self.offset, usize::try_from(self.size).ok()?)
+    }
+}
+
+impl FileType {
+    pub fn load(data: &[u8]) -> Option<FileType> {
+        if data.len() < 8 {
+            logger::debug("short ftyp");
+            return None;
+        }
+        Some(FileType {
+            major_brand: brand(&data[0..4]),
+            minor_version: be32(data, 4),
+            compatible_brands: data[8..].chunks_exact(4).map(brand).collect(),
+        })
+    }
+
+    pub fn is(&self, brand: &str) -> bool {
+        self.major_brand == brand || self.compatible_brands.iter().any(|b| b == brand)
+    }
+}
+
+impl<'a> BoxReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> BoxReader<'a> {
+        BoxReader {
+            data,
+            pos: 0,
+        }
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        self.bytes(4).map(|b| be32(b, 0))
+    }
+
+    /// Unsigned field of 0, 2, 4 or 8 bytes, as in 'iloc'.
+    pub(crate) fn uint(&mut self, size: usize) -> Option<u64> {
+        let bytes = self.bytes(size)?;
+        Some(bytes.iter().fold(0u64, |v, &b| v << 8 | b as u64))
+    }
+
+    /// NUL terminated UTF-8 string.
+    pub(crate) fn string(&mut self) -> Option<String> {
+        let rest = self.data.get(self.pos..)?;
+        let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        self.pos += (end + 1).min(rest.len());
+        Some(String::from_utf8_lossy(&rest[..end]).to_string())
+    }
+
+    /// Version and flags of a full box.
+    pub(crate) fn full_box(&mut self) -> Option<(u8, u32)> {
+        let v = self.u32()?;
+        Some(((v >> 24) as u8, v & 0x00FFFFFF))
+    }
+}
+
+/// Boxes between `start` and `end`, without descending into them.
+pub(crate) fn read_boxes<S: ReadAt>(src: &mut S, start: u64, end: u64) -> Vec<BMFFBox> {
+    let mut boxes = Vec::new();
+    let mut offset = start;
+    while offset + 8 <= end {
+        let head = match src.read_at(offset, 8) {
+            Some(head) => head,
+            None => {
+                break;
+            }
+        };
+        let mut header:u64 = 8;
+        let size = match be32(&head, 0) {
+            // up to the end of the enclosing box or file
+            0 => end - offset,
+            1 => {
+                header = 16;
+                match src.read_at(offset + 8, 8) {
+                    Some(large) => u64::from_be_bytes([large[0], large[1], large[2], large[3], large[4], large[5], large[6], large[7]]),
+                    None => {
+                        break;
+                    }
+                }
+            }
+            size => size as u64,
+        };
+        let box_type = [head[4], head[5], head[6], head[7]];
+        let uuid = if &box_type == b"uuid" {
+            // after the size fields
+            let uuid_offset = offset + header;
+            header += 16;
+            src.read_at(uuid_offset, 16).map(|u| {
+                let mut uuid = [0; 16];
+                uuid.copy_from_slice(&u);
+                uuid
+            })
+        } else {
+            None
+        };
+        if size < header || offset.saturating_add(size) > end {
+            logger::debug(format!("broken box '{}'", String::from_utf8_lossy(&box_type)).as_str());
+            break;
+        }
+        boxes.push(BMFFBox {
+            box_type,
+            uuid,
+            offset: offset + header,
+            size: size - header,
+        });
+        offset += size;
+    }
+    boxes
+}
+
+pub(crate) fn find<'a>(boxes: &'a [BMFFBox], box_type: &[u8; 4]) -> Option<&'a BMFFBox> {
+    boxes.iter().find(|b| &b.box_type == box_type)
+}
+
+pub(crate) fn find_uuid<'a>(boxes: &'a [BMFFBox], uuid: &[u8; 16]) -> Option<&'a BMFFBox> {
+    boxes.iter().find(|b| b.uuid.as_ref() == Some(uuid))
+}
+
+fn brand(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).to_string()
+}
@@ -0,0 +1,167 @@
+use std::io::prelude::*;
+use crate::icc::*;
+use crate::exif::*;
+use crate::logger;
+use crate::bytes::*;
+
+/// Windows or OS/2 bitmap: the file header and the DIB header with its color space.
+pub struct BMP {
+    /// DIB header size: 12, 40, 52, 56, 64, 108 or 124
+    pub header_size: u32,
+    pub width: i32,
+    /// negative for top-down bitmaps
+    pub height: i32,
+    pub bit_depth: u16,
+    pub compression: u32,
+    /// pixels per metre
+    pub resolution: (i32, i32),
+    pub colors_used: u32,
+    /// LCS color space type of V4 and V5 headers, e.g. "sRGB" or "MBED"
+    pub color_space: Option<String>,
+    /// embedded profile of a V5 header
+    pub icc: Option<ICC>,
+    /// file name of a linked profile
+    pub linked_profile: Option<String>,
+}
+
+// profiles are small
+const MAX_PROFILE: u32 = 1 << 24;
+
+impl BMP {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<BMP> {
+        let mut head = [0; 18];
+        f.read_exact(&mut head).ok()?;
+        if &head[0..2] != b"BM" {
+            logger::debug("not BMP");
+            return None;
+        }
+        let header_size = le32(&head, 14);
+        let mut dib = vec![0; header_size.clamp(12, 124) as usize];
+        dib[0..4].copy_from_slice(&head[14..18]);
+        f.read_exact(&mut dib[4..]).ok()?;
+        BMP::load_dib(f, &dib, 14)
+    }
+
+    // DIB header at `offset` of the file, profiles are located from there
+    fn load_dib<R: Read + Seek>(f: &mut R, dib: &[u8], offset: u64) -> Option<BMP> {
+        let header_size = le32(dib, 0);
+        let mut bmp = BMP {
+            header_size,
+            width: 0,
+            height: 0,
+            bit_depth: 0,
+            compression: 0,
+            resolution: (0, 0),
+            colors_used: 0,
+            color_space: None,
+            icc: None,
+            linked_profile: None,
+        };
+
+        if header_size == 12 {
+            // BITMAPCOREHEADER: 16-bit width and height
+            bmp.width = le16(dib, 4) as i32;
+            bmp.height = le16(dib, 6) as i32;
+            bmp.bit_depth = le16(dib, 10);
+            return Some(bmp);
+        }
+        if header_size < 40 || dib.len() < 40 {
+            logger::debug("unknown DIB header");
+            return None;
+        }
+        bmp.width = le32(dib, 4) as i32;
+        bmp.height = le32(dib, 8) as i32;
+        bmp.bit_depth = le16(dib, 14);
+        bmp.compression = le32(dib, 16);
+        bmp.resolution = (le32(dib, 24) as i32, le32(dib, 28) as i32);
+        bmp.colors_used = le32(dib, 32);
+
+        if header_size >= 108 && dib.len() >= 108 {
+            let cs_type = dib[56..60].iter().rev().map(|&b| b as char).collect::<String>();
+            bmp.color_space = Some(cs_type);
+        }
+        if header_size >= 124 && dib.len() >= 124 {
+            // profile offset from the start of the DIB header, and size
+            let profile_offset = le32(dib, 112) as u64;
+            let profile_size = le32(dib, 116);
+            if profile_size > 0 && profile_size <= MAX_PROFILE {
+                // checked against the file size before allocating
+                let profile = SeekReader::new(&mut *f)
+                    .and_then(|mut src| src.read_at(offset + profile_offset, profile_size as usize));
+                if let Some(profile) = profile {
+                    match bmp.color_space.as_deref() {
+                        Some("MBED") => {
+                            bmp.icc = ICC::load(&profile);
+                        }
+                        Some("LINK") => {
+                            let end = profile.iter().position(|&b| b == 0).unwrap_or(profile.len());
+                            bmp.linked_profile = Some(String::from_utf8_lossy(&profile[..end]).to_string());
+                        }
+                        _ => {}
+                    }
+                } else {
+                    logger::debug("short profile");
+                }
+            }
+        }
+
+        Some(bmp)
+    }
+
+    pub fn header_name(&self) -> &'static str {
+        match self.header_size {
+            12 => "BITMAPCOREHEADER",
+            40 => "BITMAPINFOHEADER",
+            52 => "BITMAPV2INFOHEADER",
+            56 => "BITMAPV3INFOHEADER",
+            64 => "OS22XBITMAPHEADER",
+            108 => "BITMAPV4HEADER",
+            124 => "BITMAPV5HEADER",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn compression_name(&self) -> &'static str {
+        match self.compression {
+            0 => "None",
+            1 => "RLE8",
+            2 => "RLE4",
+            3 => "Bitfields",
+            4 => "JPEG",
+            5 => "PNG",
+            6 => "Alpha bitfields",
+            11 => "CMYK",
+            12 => "CMYK RLE8",
+            13 => "CMYK RLE4",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("Header".to_string(), self.header_name().to_string()),
+            ("ImageWidth".to_string(), self.width.to_string()),
+            ("ImageHeight".to_string(), self.height.unsigned_abs().to_string()),
+            ("BitDepth".to_string(), self.bit_depth.to_string()),
+        ];
+        if self.header_size >= 40 {
+            entries.push(("Compression".to_string(), self.compression_name().to_string()));
+            entries.push(("TopDown".to_string(), if self.height < 0 { "Yes" } else { "No" }.to_string()));
+            if self.resolution.0 > 0 && self.resolution.1 > 0 {
+                // pixels per metre to dpi
+                let dpi = |ppm: i32| (ppm as f64 * 0.0254).round();
+                entries.push(("Resolution".to_string(), format!("{}x{} dpi", dpi(self.resolution.0), dpi(self.resolution.1))));
+            }
+            if self.colors_used > 0 {
+                entries.push(("ColorsUsed".to_string(), self.colors_used.to_string()));
+            }
+        }
+        if let Some(color_space) = &self.color_space {
+            entries.push(("ColorSpace".to_string(), color_space.clone()));
+        }
+        if let Some(linked_profile) = &self.linked_profile {
+            entries.push(("LinkedProfile".to_string(), linked_profile.clone()));
+        }
+        entries
+    }
+}
@@ -0,0 +1,737 @@
+// Brotli (RFC 7932) decoder for the compressed metadata boxes of JPEG XL.
+use crate::logger;
+
+// metadata never gets near this, compressed bombs do
+const MAX_OUTPUT: usize = 1 << 26;
+
+// RFC 7932 Appendix A, words of 4 to 24 bytes grouped by length
+const DICTIONARY: &[u8] = include_bytes!("brotli_dictionary.bin");
+// bits of the word index for each word length
+const DICTIONARY_BITS: [u32; 25] = [0, 0, 0, 0, 10, 10, 11, 11, 10, 10, 10, 10, 10, 9, 9, 8, 7, 7, 8, 7, 7, 6, 6, 5, 5];
+
+// (base, extra bits) of the insert length, copy length and block length codes
+const INSERT_LENGTH: [(usize, u32); 24] = [
+    (0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0), (6, 1), (8, 1), (10, 2), (14, 2), (18, 3), (26, 3),
+    (34, 4), (50, 4), (66, 5), (98, 5), (130, 6), (194, 7), (322, 8), (578, 9), (1090, 10), (2114, 12), (6210, 14), (22594, 24),
+];
+const COPY_LENGTH: [(usize, u32); 24] = [
+    (2, 0), (3, 0), (4, 0), (5, 0), (6, 0), (7, 0), (8, 0), (9, 0), (10, 1), (12, 1), (14, 2), (18, 2),
+    (22, 3), (30, 3), (38, 4), (54, 4), (70, 5), (102, 5), (134, 6), (198, 7), (326, 8), (582, 9), (1094, 10), (2118, 24),
+];
+const BLOCK_LENGTH: [(u32, u32); 26] = [
+    (1, 2), (5, 2), (9, 2), (13, 2), (17, 3), (25, 3), (33, 3), (41, 3), (49, 4), (65, 4), (81, 4), (97, 4), (113, 5),
+    (145, 5), (177, 5), (209, 5), (241, 6), (305, 6), (369, 7), (497, 8), (753, 9), (1265, 10), (2289, 11), (4337, 12), (8433, 13), (16625, 24),
+];
+// first insert and copy length codes of each 64 command codes; the first 128 reuse the last distance
+const INSERT_CELL: [usize; 11] = [0, 0, 0, 0, 8, 8, 0, 16, 8, 16, 16];
+const COPY_CELL: [usize; 11] = [0, 8, 0, 8, 0, 8, 16, 0, 16, 8, 16];
+// order of the code length code lengths in a complex prefix code
+const CODE_LENGTH_ORDER: [usize; 18] = [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+// context of UTF-8 text from the last two bytes: RFC 7932 section 7.1, Lut0 and Lut1
+const UTF8_LAST: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 4, 0, 0, 4, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    8, 12, 16, 12, 12, 20, 12, 16, 24, 28, 12, 12, 32, 12, 36, 12,
+    44, 44, 44, 44, 44, 44, 44, 44, 44, 44, 32, 32, 24, 40, 28, 12,
+    12, 48, 52, 52, 52, 48, 52, 52, 52, 48, 52, 52, 52, 52, 52, 48,
+    52, 52, 52, 52, 52, 48, 52, 52, 52, 52, 52, 24, 12, 28, 12, 12,
+    12, 56, 60, 60, 60, 56, 60, 60, 60, 56, 60, 60, 60, 60, 60, 56,
+    60, 60, 60, 60, 60, 56, 60, 60, 60, 60, 60, 24, 12, 28, 12, 0,
+    0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1,
+    0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1,
+    2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3,
+    2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3,
+    2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3,
+    2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3, 2, 3,
+];
+const UTF8_SECOND_LAST: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1,
+    1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1,
+    1, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 1, 1, 1, 1, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+];
+// Lut2, for signed integers
+const SIGNED: [u8; 256] = [
+    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+    3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+    3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+    4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
+    5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
+    5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
+    6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 7,
+];
+
+// RFC 7932 Appendix B: prefix, transform and suffix of each of the 121 word transforms
+const TRANSFORMS: [(&[u8], Transform, &[u8]); 121] = [
+    (b"", IDENTITY, b""), (b"", IDENTITY, b" "), (b" ", IDENTITY, b" "),
+    (b"", OMIT_FIRST(1), b""), (b"", UPPERCASE_FIRST, b" "), (b"", IDENTITY, b" the "),
+    (b" ", IDENTITY, b""), (b"s ", IDENTITY, b" "), (b"", IDENTITY, b" of "),
+    (b"", UPPERCASE_FIRST, b""), (b"", IDENTITY, b" and "), (b"", OMIT_FIRST(2), b""),
+    (b"", OMIT_LAST(1), b""), (b", ", IDENTITY, b" "), (b"", IDENTITY, b", "),
+    (b" ", UPPERCASE_FIRST, b" "), (b"", IDENTITY, b" in "), (b"", IDENTITY, b" to "),
+    (b"e ", IDENTITY, b" "), (b"", IDENTITY, b"\""), (b"", IDENTITY, b"."),
+    (b"", IDENTITY, b"\">"), (b"", IDENTITY, b"\n"), (b"", OMIT_LAST(3), b""),
+    (b"", IDENTITY, b"]"), (b"", IDENTITY, b" for "), (b"", OMIT_FIRST(3), b""),
+    (b"", OMIT_LAST(2), b""), (b"", IDENTITY, b" a "), (b"", IDENTITY, b" that "),
+    (b" ", UPPERCASE_FIRST, b""), (b"", IDENTITY, b". "), (b".", IDENTITY, b""),
+    (b" ", IDENTITY, b", "), (b"", OMIT_FIRST(4), b""), (b"", IDENTITY, b" with "),
+    (b"", IDENTITY, b"'"), (b"", IDENTITY, b" from "), (b"", IDENTITY, b" by "),
+    (b"", OMIT_FIRST(5), b""), (b"", OMIT_FIRST(6), b""), (b" the ", IDENTITY, b""),
+    (b"", OMIT_LAST(4), b""), (b"", IDENTITY, b". The "), (b"", UPPERCASE_ALL, b""),
+    (b"", IDENTITY, b" on "), (b"", IDENTITY, b" as "), (b"", IDENTITY, b" is "),
+    (b"", OMIT_LAST(7), b""), (b"", OMIT_LAST(1), b"ing "), (b"", IDENTITY, b"\n\t"),
+    (b"", IDENTITY, b":"), (b" ", IDENTITY, b". "), (b"", IDENTITY, b"ed "),
+    (b"", OMIT_FIRST(9), b""), (b"", OMIT_FIRST(7), b""), (b"", OMIT_LAST(6), b""),
+    (b"", IDENTITY, b"("), (b"", UPPERCASE_FIRST, b", "), (b"", OMIT_LAST(8), b""),
+    (b"", IDENTITY, b" at "), (b"", IDENTITY, b"ly "), (b" the ", IDENTITY, b" of "),
+    (b"", OMIT_LAST(5), b""), (b"", OMIT_LAST(9), b""), (b" ", UPPERCASE_FIRST, b", "),
+    (b"", UPPERCASE_FIRST, b"\""), (b".", IDENTITY, b"("), (b"", UPPERCASE_ALL, b" "),
+    (b"", UPPERCASE_FIRST, b"\">"), (b"", IDENTITY, b"=\""), (b" ", IDENTITY, b"."),
+    (b".com/", IDENTITY, b""), (b" the ", IDENTITY, b" of the "), (b"", UPPERCASE_FIRST, b"'"),
+    (b"", IDENTITY, b". This "), (b"", IDENTITY, b","), (b".", IDENTITY, b" "),
+    (b"", UPPERCASE_FIRST, b"("), (b"", UPPERCASE_FIRST, b"."), (b"", IDENTITY, b" not "),
+    (b" ", IDENTITY, b"=\""), (b"", IDENTITY, b"er "), (b" ", UPPERCASE_ALL, b" "),
+    (b"", IDENTITY, b"al "), (b" ", UPPERCASE_ALL, b""), (b"", IDENTITY, b"='"),
+    (b"", UPPERCASE_ALL, b"\""), (b"", UPPERCASE_FIRST, b". "), (b" ", IDENTITY, b"("),
+    (b"", IDENTITY, b"ful "), (b" ", UPPERCASE_FIRST, b". "), (b"", IDENTITY, b"ive "),
+    (b"", IDENTITY, b"less "), (b"", UPPERCASE_ALL, b"'"), (b"", IDENTITY, b"est "),
+    (b" ", UPPERCASE_FIRST, b"."), (b"", UPPERCASE_ALL, b"\">"), (b" ", IDENTITY, b"='"),
+    (b"", UPPERCASE_FIRST, b","), (b"", IDENTITY, b"ize "), (b"", UPPERCASE_ALL, b"."),
+    (b"\xC2\xA0", IDENTITY, b""), (b" ", IDENTITY, b","), (b"", UPPERCASE_FIRST, b"=\""),
+    (b"", UPPERCASE_ALL, b"=\""), (b"", IDENTITY, b"ous "), (b"", UPPERCASE_ALL, b", "),
+    (b"", UPPERCASE_FIRST, b"='"), (b" ", UPPERCASE_FIRST, b","), (b" ", UPPERCASE_ALL, b"=\""),
+    (b" ", UPPERCASE_ALL, b", "), (b"", UPPERCASE_ALL, b","), (b"", UPPERCASE_ALL, b"("),
+    (b"", UPPERCASE_ALL, b". "), (b" ", UPPERCASE_ALL, b"."), (b"", UPPERCASE_ALL, b"='"),
+    (b" ", UPPERCASE_ALL, b". "), (b" ", UPPERCASE_FIRST, b"=\""), (b" ", UPPERCASE_ALL, b"='"),
+    (b" ", UPPERCASE_FIRST, b"='"),
+];
+
+enum Transform {
+    IDENTITY,
+    OMIT_LAST(usize),
+    OMIT_FIRST(usize),
+    UPPERCASE_FIRST,
+    UPPERCASE_ALL,
+}
+use Transform::*;
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+// canonical prefix code: number of codes of each length and the symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+// block types of one category (literals, commands or distances) and the count left in the current block
+struct BlockTypes {
+    count: usize,
+    codes: Option<(Huffman, Huffman)>,
+    current: usize,
+    previous: usize,
+    remaining: u32,
+}
+
+/// Decompresses a Brotli stream.
+pub(crate) fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let mut bits = Bits {
+        data,
+        pos: 0,
+    };
+    let window = window_bits(&mut bits)?;
+    let max_backward = (1usize << window) - 16;
+    // oldest first
+    let mut distances = [16, 15, 11, 4];
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        if last && bits.bits(1)? == 1 {
+            break;
+        }
+        let nibbles = bits.bits(2)?;
+        if nibbles == 3 {
+            // metadata, skipped
+            if bits.bits(1)? != 0 {
+                logger::debug("invalid Brotli metadata block");
+                return None;
+            }
+            let skip_bytes = bits.bits(2)?;
+            let skip = if skip_bytes == 0 { 0 } else { bits.bits(8 * skip_bytes)? as usize + 1 };
+            bits.align()?;
+            bits.bytes(skip)?;
+        } else {
+            let length = bits.bits(4 * (nibbles + 4))? as usize + 1;
+            if out.len() + length > MAX_OUTPUT {
+                logger::debug("decompressed data too large");
+                return None;
+            }
+            if !last && bits.bits(1)? == 1 {
+                bits.align()?;
+                out.extend_from_slice(bits.bytes(length)?);
+            } else {
+                meta_block(&mut bits, &mut out, length, max_backward, &mut distances)?;
+            }
+        }
+        if last {
+            break;
+        }
+    }
+    Some(out)
+}
+
+fn window_bits(bits: &mut Bits) -> Option<u32> {
+    if bits.bits(1)? == 0 {
+        return Some(16);
+    }
+    let n = bits.bits(3)?;
+    if n != 0 {
+        return Some(17 + n);
+    }
+    match bits.bits(3)? {
+        0 => Some(17),
+        1 => {
+            logger::debug("large Brotli window");
+            None
+        }
+        n => Some(8 + n),
+    }
+}
+
+fn meta_block(bits: &mut Bits, out: &mut Vec<u8>, length: usize, max_backward: usize, distances: &mut [usize; 4]) -> Option<()> {
+    let mut literal_types = BlockTypes::read(bits)?;
+    let mut command_types = BlockTypes::read(bits)?;
+    let mut distance_types = BlockTypes::read(bits)?;
+    let postfix = bits.bits(2)?;
+    let direct = (bits.bits(4)? << postfix) as usize;
+    let mut modes = Vec::with_capacity(literal_types.count);
+    for _ in 0..literal_types.count {
+        modes.push(bits.bits(2)?);
+    }
+    let literal_trees = count(bits)?;
+    let literal_map = context_map(bits, 64 * literal_types.count, literal_trees)?;
+    let distance_trees = count(bits)?;
+    let distance_map = context_map(bits, 4 * distance_types.count, distance_trees)?;
+    let literal_codes = (0..literal_trees).map(|_| read_code(bits, 256)).collect::<Option<Vec<_>>>()?;
+    let command_codes = (0..command_types.count).map(|_| read_code(bits, 704)).collect::<Option<Vec<_>>>()?;
+    let distance_alphabet = 16 + direct + (48 << postfix);
+    let distance_codes = (0..distance_trees).map(|_| read_code(bits, distance_alphabet)).collect::<Option<Vec<_>>>()?;
+
+    let end = out.len() + length;
+    while out.len() < end {
+        let command = command_codes[command_types.next(bits)?].decode(bits)? as usize;
+        let cell = command >> 6;
+        let (base, extra) = INSERT_LENGTH[INSERT_CELL[cell] + (command >> 3 & 7)];
+        let insert = base + bits.bits(extra)? as usize;
+        let (base, extra) = COPY_LENGTH[COPY_CELL[cell] + (command & 7)];
+        let copy = base + bits.bits(extra)? as usize;
+        if out.len() + insert > end {
+            logger::debug("Brotli insert beyond the meta-block");
+            return None;
+        }
+        for _ in 0..insert {
+            let block_type = literal_types.next(bits)?;
+            let p1 = if !out.is_empty() { out[out.len() - 1] as usize } else { 0 };
+            let p2 = if out.len() > 1 { out[out.len() - 2] as usize } else { 0 };
+            let context = match modes[block_type] {
+                0 => p1 & 0x3F,
+                1 => p1 >> 2,
+                2 => (UTF8_LAST[p1] | UTF8_SECOND_LAST[p2]) as usize,
+                _ => (SIGNED[p1] << 3 | SIGNED[p2]) as usize,
+            };
+            let tree = literal_map[64 * block_type + context] as usize;
+            out.push(literal_codes.get(tree)?.decode(bits)? as u8);
+        }
+        if out.len() == end {
+            break;
+        }
+
+        // distance code 0, the last distance, leaves the distance ring as it is
+        let code = if command < 128 {
+            0
+        } else {
+            let block_type = distance_types.next(bits)?;
+            let context = copy.min(5) - 2;
+            let tree = distance_map[4 * block_type + context] as usize;
+            distance_codes.get(tree)?.decode(bits)? as usize
+        };
+        let distance = distance(code, bits, postfix, direct, distances)?;
+        let max_distance = out.len().min(max_backward);
+        if distance > max_distance {
+            let word = dictionary_word(copy, distance - max_distance - 1)?;
+            if out.len() + word.len() > end {
+                logger::debug("Brotli word beyond the meta-block");
+                return None;
+            }
+            out.extend_from_slice(&word);
+        } else {
+            if code != 0 {
+                distances.rotate_left(1);
+                distances[3] = distance;
+            }
+            if out.len() + copy > end {
+                logger::debug("Brotli copy beyond the meta-block");
+                return None;
+            }
+            // byte by byte, the copy may overlap its own output
+            let start = out.len() - distance;
+            for k in 0..copy {
+                out.push(out[start + k]);
+            }
+        }
+    }
+    Some(())
+}
+
+fn distance(code: usize, bits: &mut Bits, postfix: u32, direct: usize, distances: &[usize; 4]) -> Option<usize> {
+    match code {
+        0..=3 => Some(distances[3 - code]),
+        4..=15 => {
+            // the last or second to last distance, -1, +1, -2, +2, -3, +3
+            let base = if code < 10 { distances[3] } else { distances[2] };
+            let delta = [-1, 1, -2, 2, -3, 3][(code - 4) % 6];
+            let distance = base as i64 + delta;
+            if distance <= 0 {
+                logger::debug("invalid Brotli distance");
+                return None;
+            }
+            Some(distance as usize)
+        }
+        _ if code < 16 + direct => Some(code - 15),
+        _ => {
+            let code = code - 16 - direct;
+            let extra = 1 + (code >> (postfix + 1)) as u32;
+            let high = code >> postfix;
+            let low = code & ((1 << postfix) - 1);
+            let offset = ((2 + (high & 1)) << extra) - 4;
+            Some(((offset + bits.bits(extra)? as usize) << postfix) + low + direct + 1)
+        }
+    }
+}
+
+// a word of the static dictionary, transformed
+fn dictionary_word(length: usize, index: usize) -> Option<Vec<u8>> {
+    if !(4..=24).contains(&length) {
+        logger::debug("invalid Brotli dictionary reference");
+        return None;
+    }
+    let bits = DICTIONARY_BITS[length];
+    let (prefix, transform, suffix) = match TRANSFORMS.get(index >> bits) {
+        Some(transform) => transform,
+        None => {
+            logger::debug("invalid Brotli transform");
+            return None;
+        }
+    };
+    let offset = (4..length).map(|l| l << DICTIONARY_BITS[l]).sum::<usize>() + (index & ((1 << bits) - 1)) * length;
+    let word = &DICTIONARY[offset..offset + length];
+
+    let mut out = prefix.to_vec();
+    let start = out.len();
+    match transform {
+        IDENTITY | UPPERCASE_FIRST | UPPERCASE_ALL => out.extend_from_slice(word),
+        OMIT_LAST(n) => out.extend_from_slice(&word[..length.saturating_sub(*n)]),
+        OMIT_FIRST(n) => out.extend_from_slice(&word[(*n).min(length)..]),
+    }
+    match transform {
+        UPPERCASE_FIRST => {
+            uppercase(&mut out[start..]);
+        }
+        UPPERCASE_ALL => {
+            let mut i = start;
+            while i < out.len() {
+                i += uppercase(&mut out[i..]);
+            }
+        }
+        _ => {}
+    }
+    out.extend_from_slice(suffix);
+    Some(out)
+}
+
+// the RFC's rough UTF-8 upper casing of one character; returns its length
+fn uppercase(word: &mut [u8]) -> usize {
+    if word[0] < 0xC0 {
+        if word[0].is_ascii_lowercase() {
+            word[0] ^= 32;
+        }
+        return 1;
+    }
+    if word[0] < 0xE0 {
+        if let Some(b) = word.get_mut(1) {
+            *b ^= 32;
+        }
+        return 2;
+    }
+    if let Some(b) = word.get_mut(2) {
+        *b ^= 5;
+    }
+    3
+}
+
+// NBLTYPES, NTREESL and NTREESD: 1 to 256
+fn count(bits: &mut Bits) -> Option<usize> {
+    if bits.bits(1)? == 0 {
+        return Some(1);
+    }
+    let n = bits.bits(3)?;
+    Some(1 + (1 << n) + bits.bits(n)? as usize)
+}
+
+fn context_map(bits: &mut Bits, size: usize, trees: usize) -> Option<Vec<u8>> {
+    if trees < 2 {
+        return Some(vec![0; size]);
+    }
+    // run lengths of zeros are coded with the symbols 1 to max_run
+    let max_run = if bits.bits(1)? == 1 { bits.bits(4)? as usize + 1 } else { 0 };
+    let code = read_code(bits, trees + max_run)?;
+    let mut map = Vec::with_capacity(size);
+    while map.len() < size {
+        let symbol = code.decode(bits)? as usize;
+        if symbol == 0 {
+            map.push(0);
+        } else if symbol <= max_run {
+            let run = (1 << symbol) + bits.bits(symbol as u32)? as usize;
+            if map.len() + run > size {
+                logger::debug("invalid Brotli context map");
+                return None;
+            }
+            map.resize(map.len() + run, 0);
+        } else {
+            map.push((symbol - max_run) as u8);
+        }
+    }
+    // inverse move-to-front transform
+    if bits.bits(1)? == 1 {
+        let mut values:Vec<u8> = (0..=255).collect();
+        for value in map.iter_mut() {
+            let v = values.remove(*value as usize);
+            values.insert(0, v);
+            *value = v;
+        }
+    }
+    if map.iter().any(|&tree| tree as usize >= trees) {
+        logger::debug("invalid Brotli context map");
+        return None;
+    }
+    Some(map)
+}
+
+fn read_code(bits: &mut Bits, alphabet: usize) -> Option<Huffman> {
+    let skip = bits.bits(2)? as usize;
+    if skip == 1 {
+        return read_simple_code(bits, alphabet);
+    }
+
+    // code lengths of the code length alphabet, until their codes fill the code space
+    let mut code_lengths = [0u8; 18];
+    let mut space = 32;
+    let mut used = 0;
+    for &symbol in &CODE_LENGTH_ORDER[skip..] {
+        let length = match bits.bits(2)? {
+            0 => 0,
+            1 => 4,
+            2 => 3,
+            _ => match bits.bits(1)? {
+                0 => 2,
+                _ => if bits.bits(1)? == 0 { 1 } else { 5 },
+            },
+        };
+        code_lengths[symbol] = length;
+        if length != 0 {
+            space -= 32 >> length;
+            used += 1;
+            if space <= 0 {
+                break;
+            }
+        }
+    }
+    if used != 1 && space != 0 {
+        logger::debug("invalid Brotli code length code");
+        return None;
+    }
+    let length_code = Huffman::new(&code_lengths);
+
+    // 16 repeats the last non-zero length, 17 repeats zero; repeats in a row extend each other
+    let mut lengths = vec![0u8; alphabet];
+    let mut symbol = 0;
+    let mut previous = 8;
+    let mut repeat = 0;
+    let mut repeat_length = 0;
+    let mut space:i32 = 32768;
+    while symbol < alphabet && space > 0 {
+        let code = length_code.decode(bits)? as u8;
+        if code < 16 {
+            repeat = 0;
+            lengths[symbol] = code;
+            symbol += 1;
+            if code != 0 {
+                previous = code;
+                space -= 32768 >> code;
+            }
+            continue;
+        }
+        let (extra, length) = if code == 16 { (2, previous) } else { (3, 0) };
+        if repeat_length != length {
+            repeat = 0;
+            repeat_length = length;
+        }
+        let old = repeat;
+        if repeat > 0 {
+            repeat = (repeat - 2) << extra;
+        }
+        repeat += bits.bits(extra)? as usize + 3;
+        let delta = repeat - old;
+        if symbol + delta > alphabet {
+            logger::debug("too many Brotli code lengths");
+            return None;
+        }
+        lengths[symbol..symbol + delta].fill(length);
+        symbol += delta;
+        if length != 0 {
+            space -= (delta as i32) << (15 - length);
+        }
+    }
+    if space != 0 {
+        logger::debug("invalid Brotli prefix code");
+        return None;
+    }
+    Some(Huffman::new(&lengths))
+}
+
+// up to four symbols with implied code lengths
+fn read_simple_code(bits: &mut Bits, alphabet: usize) -> Option<Huffman> {
+    let count = bits.bits(2)? as usize + 1;
+    let width = usize::BITS - (alphabet - 1).leading_zeros();
+    let mut symbols = Vec::with_capacity(count);
+    for _ in 0..count {
+        let symbol = bits.bits(width)? as usize;
+        if symbol >= alphabet || symbols.contains(&symbol) {
+            logger::debug("invalid Brotli simple prefix code");
+            return None;
+        }
+        symbols.push(symbol);
+    }
+    let code_lengths:&[u8] = match count {
+        1 => &[0],
+        2 => &[1, 1],
+        3 => &[1, 2, 2],
+        _ => if bits.bits(1)? == 0 { &[2, 2, 2, 2] } else { &[1, 2, 3, 3] },
+    };
+    if count == 1 {
+        return Some(Huffman {
+            counts: [0; 16],
+            symbols: vec![symbols[0] as u16],
+        });
+    }
+    let mut lengths = vec![0u8; alphabet];
+    for (&symbol, &length) in symbols.iter().zip(code_lengths) {
+        lengths[symbol] = length;
+    }
+    Some(Huffman::new(&lengths))
+}
+
+impl BlockTypes {
+    fn read(bits: &mut Bits) -> Option<BlockTypes> {
+        let count = count(bits)?;
+        let mut types = BlockTypes {
+            count,
+            codes: None,
+            current: 0,
+            previous: 1,
+            remaining: 0,
+        };
+        if count >= 2 {
+            let type_code = read_code(bits, count + 2)?;
+            let length_code = read_code(bits, 26)?;
+            types.remaining = block_length(bits, &length_code)?;
+            types.codes = Some((type_code, length_code));
+        }
+        Some(types)
+    }
+
+    // the type of the next symbol, switching blocks when the current one is used up
+    fn next(&mut self, bits: &mut Bits) -> Option<usize> {
+        if let Some((type_code, length_code)) = &self.codes {
+            if self.remaining == 0 {
+                let block_type = match type_code.decode(bits)? as usize {
+                    0 => self.previous,
+                    1 => self.current + 1,
+                    n => n - 2,
+                };
+                self.previous = self.current;
+                self.current = if block_type >= self.count { block_type - self.count } else { block_type };
+                self.remaining = block_length(bits, length_code)?;
+            }
+            self.remaining -= 1;
+        }
+        Some(self.current)
+    }
+}
+
+fn block_length(bits: &mut Bits, code: &Huffman) -> Option<u32> {
+    let (base, extra) = BLOCK_LENGTH[code.decode(bits)? as usize];
+    Some(base + bits.bits(extra)?)
+}
+
+impl<'a> Bits<'a> {
+    // least significant bit first
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        let mut v = 0;
+        for i in 0..n {
+            let byte = *self.data.get(self.pos / 8)?;
+            v |= ((byte >> (self.pos % 8)) as u32 & 1) << i;
+            self.pos += 1;
+        }
+        Some(v)
+    }
+
+    // to the next byte boundary, over zero padding
+    fn align(&mut self) -> Option<()> {
+        let padding = (8 - self.pos % 8) % 8;
+        if self.bits(padding as u32)? != 0 {
+            logger::debug("invalid Brotli padding");
+            return None;
+        }
+        Some(())
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let start = self.pos / 8;
+        let bytes = self.data.get(start..start.checked_add(len)?)?;
+        self.pos += len * 8;
+        Some(bytes)
+    }
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        symbols.truncate(counts.iter().sum::<u16>() as usize);
+        Huffman {
+            counts,
+            symbols,
+        }
+    }
+
+    // one bit at a time, codes are stored most significant bit first;
+    // a code of a single symbol takes no bits
+    fn decode(&self, bits: &mut Bits) -> Option<u16> {
+        if self.symbols.len() == 1 {
+            return Some(self.symbols[0]);
+        }
+        let mut code:i32 = 0;
+        let mut first:i32 = 0;
+        let mut index:i32 = 0;
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        logger::debug("invalid Brotli prefix code");
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // "line 0: the quick brown fox\n" up to "line 39: ...", at quality 5 with a 1 KiB window and at quality 1
+    const LINES: &str = "a1e8230020d033b7ef2b6921faeefcb7282808b2814551060720538b35bc41e021438e0e18b405d6fddcedacc37e7671babf7abcfd6bf5ea7fc9358aaaa34e3aeba2ab6e3de3ace5c89123478e1c3972300cc3300cc3300ccbb22ccbb22c2b02";
+    const LINES_FAST: &str = "8b3e020080aaaaaaeaff6e97931c8ec6c4b6df8e666006a66006aa6006666046a15ecabc7738acc240a0e8809b40d94f67ca6bd4533cfb1ad79b9623e6bb7c39b6f257ff90068d358da6d5749a5e3368468de2d4ef3a9427529f488122158a942852a3489122558a1021428408112244881021b2c8228b2cb2c8228b2cb2c8de03";
+    // an XMP packet at quality 11, mostly dictionary words
+    const XMP: &str = "1bda002064754ed9816e0712d5f2f8e7c200a06e2ee4ac22a255c47078797e786887e7bcdaafdb44101f5f55a465f504d1a7fbcc376f737de55f451d9f67cbd58d72040916dc5135268d491239cab3439c7bf6ba4738a4165a032138ffe5ca3f584e5ee3c6a7b5ba2a796df731c9ac4a010108854a305295a6059975af18e9fe9199da4c0427064986418a00";
+
+    fn lines() -> String {
+        (0..40).map(|i| format!("line {}: the quick brown fox\n", i)).collect()
+    }
+
+    #[test]
+    fn empty_stream() {
+        assert_eq!(decompress(&[0x06]).unwrap(), b"");
+    }
+
+    #[test]
+    fn uncompressed_meta_block() {
+        let stream = unhex("0b158054686520717569636b2062726f776e20666f78206a756d7073206f76657220746865206c617a7920646f6703");
+        assert_eq!(decompress(&stream).unwrap(), b"The quick brown fox jumps over the lazy dog");
+    }
+
+    #[test]
+    fn compressed_meta_blocks() {
+        let stream = unhex(LINES);
+        assert_eq!(window_bits(&mut Bits { data: &stream, pos: 0 }), Some(10));
+        assert_eq!(decompress(&stream).unwrap(), lines().as_bytes());
+        assert_eq!(decompress(&unhex(LINES_FAST)).unwrap(), lines().as_bytes());
+    }
+
+    #[test]
+    fn dictionary_words() {
+        let xmp = decompress(&unhex(XMP)).unwrap();
+        assert!(xmp.starts_with(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">"));
+        assert!(xmp.ends_with(b"dc:title=\"The house by the river\"/></rdf:RDF></x:xmpmeta>"));
+    }
+
+    #[test]
+    fn broken_streams() {
+        for hex in [LINES, LINES_FAST, XMP] {
+            let stream = unhex(hex);
+            assert!(decompress(&stream[..stream.len() / 2]).is_none());
+            assert!(decompress(&stream[..stream.len() - 1]).is_none());
+        }
+        assert!(decompress(&[]).is_none());
+        // large window, an extension of RFC 7932
+        assert!(decompress(&[0x11, 0x03]).is_none());
+    }
+}
@@ -0,0 +1,23 @@
+// Fixed-size integers at a byte offset, for the formats whose byte order does not vary;
+// TIFF-based streams use `read_2bytes` and friends in exif.rs with their ENDIAN.
+// The offset must be in range.
+
+pub(crate) fn be16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset+1]])
+}
+
+pub(crate) fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset+1], data[offset+2], data[offset+3]])
+}
+
+pub(crate) fn le16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset+1]])
+}
+
+pub(crate) fn le24(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset+1], data[offset+2], 0])
+}
+
+pub(crate) fn le32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset+1], data[offset+2], data[offset+3]])
+}
@@ -0,0 +1,98 @@
+use std::io::prelude::*;
+use crate::bmff::*;
+use crate::exif::*;
+use crate::xmp::*;
+use crate::logger;
+
+/// Canon CR3: ISO Base Media file whose 'moov' holds the TIFF structured CMT boxes.
+pub struct CR3 {
+    pub file_type: FileType,
+    /// IFD0 (CMT1), Exif IFD (CMT2) and GPS IFD (CMT4) values
+    pub exif: Option<EXIF>,
+    pub xmp: Option<XMP>,
+}
+
+// 'uuid' box of the Canon metadata in 'moov', in CR3 and MOV files
+pub(crate) const CANON_UUID: [u8; 16] = [
+    0x85, 0xC0, 0xB6, 0x87, 0x82, 0x0F, 0x11, 0xE0, 0x81, 0x11, 0xF4, 0xCE, 0x46, 0x2B, 0x6A, 0x48,
+];
+
+impl CR3 {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<CR3> {
+        let mut src = SeekReader::new(f)?;
+        CR3::load_from(&mut src)
+    }
+
+    pub fn load_data(data: &[u8]) -> Option<CR3> {
+        CR3::load_from(&mut &data[..])
+    }
+
+    fn load_from<S: ReadAt>(src: &mut S) -> Option<CR3> {
+        let size = src.size();
+        let boxes = read_boxes(src, 0, size);
+        let file_type = FileType::load(&find(&boxes, b"ftyp")?.data(src)?)?;
+        if file_type.major_brand != "crx " {
+            logger::debug("not CR3");
+            return None;
+        }
+
+        let mut cr3 = CR3 {
+            file_type,
+            exif: None,
+            xmp: None,
+        };
+
+        if let Some(xmp) = find_uuid(&boxes, &XMP_UUID).and_then(|b| b.data(src)) {
+            cr3.xmp = XMP::load(&xmp);
+        }
+
+        let moov = find(&boxes, b"moov")?.children(src);
+        match find_uuid(&moov, &CANON_UUID) {
+            Some(canon) => {
+                cr3.exif = load_cmt(src, canon);
+            }
+            None => {
+                logger::debug("no Canon metadata box");
+            }
+        }
+
+        Some(cr3)
+    }
+}
+
+/// IFD0 (CMT1), Exif IFD (CMT2) and GPS IFD (CMT4) of the Canon metadata box.
+pub(crate) fn load_cmt<S: ReadAt>(src: &mut S, canon: &BMFFBox) -> Option<EXIF> {
+    let mut values:Option<EXIF> = None;
+    for cmt in canon.children(src) {
+        let data = match cmt.data(src) {
+            Some(data) => data,
+            None => {
+                continue;
+            }
+        };
+        let exif = match &cmt.box_type {
+            b"CMT1" | b"CMT2" => EXIF::load(&data),
+            b"CMT3" => {
+                // Canon MakerNote, not decoded
+                if let Some(exif) = &mut values {
+                    exif.values.insert("MakerNote".to_string(), format!("({} bytes)", data.len()));
+                }
+                continue;
+            }
+            b"CMT4" => EXIF::load_gps(&data),
+            _ => {
+                continue;
+            }
+        };
+        match (&mut values, exif) {
+            (Some(values), Some(exif)) => {
+                values.merge(exif);
+            }
+            (None, exif) => {
+                values = exif;
+            }
+            _ => {}
+        }
+    }
+    values
+}
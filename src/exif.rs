@@ -1,433 +1,908 @@
 use std::collections::HashMap;
+use std::io::prelude::*;
+use std::io::SeekFrom;
 use crate::logger;
 
 pub struct EXIF {
     byte_order: ENDIAN,
+    big_tiff: bool,
     pub values: HashMap<String, String>,
+    // IFD offsets already read, so that pointers back to them are not followed again
+    visited: Vec<u64>,
 }
 
-enum ENDIAN {
+pub(crate) enum ENDIAN {
     BIG_ENDIAN, LITTLE_ENDIAN
 }
 
+/// IFD entry as stored; `data` holds the value bytes in the byte order of the stream.
+#[derive(Clone)]
+pub struct IfdEntry {
+    pub tag: u16,
+    pub ifd_type: u16,
+    pub count: u64,
+    pub data: Vec<u8>,
+}
+
+pub struct Ifd {
+    pub entries: Vec<IfdEntry>,
+    /// offset of the next IFD, 0 at the end of the chain
+    pub next: u64,
+}
+
+/// TIFF header: classic (42) or BigTIFF (43), or a vendor magic number.
+pub(crate) struct TiffHeader {
+    pub byte_order: ENDIAN,
+    pub magic: u16,
+    pub big_tiff: bool,
+    /// offset of IFD0
+    pub offset: u64,
+}
+
+/// Random access to a TIFF stream, in memory or in a file.
+pub(crate) trait ReadAt {
+    fn read_at(&mut self, offset: u64, len: usize) -> Option<Vec<u8>>;
+    fn size(&self) -> u64;
+}
+
+/// Reads a file on demand, so that large (BigTIFF) files need not be loaded.
+pub(crate) struct SeekReader<R: Read + Seek> {
+    f: R,
+    len: u64,
+}
+
 impl EXIF {
     pub fn load(data:&[u8]) -> Option<EXIF> {
+        EXIF::load_from(&mut &data[..])
+    }
+
+    pub(crate) fn load_from<S: ReadAt>(src: &mut S) -> Option<EXIF> {
+        let header = read_header(src)?;
         let mut exif = EXIF {
-            byte_order: ENDIAN::LITTLE_ENDIAN,
+            byte_order: header.byte_order,
+            big_tiff: header.big_tiff,
             values: HashMap::new(),
+            visited: Vec::new(),
         };
-    
-        // byte order
-        exif.byte_order = match data[0] {
-            0x49 => {
-                logger::debug("Intel");
-                ENDIAN::LITTLE_ENDIAN
-            }
-            0x4D => {
-                logger::debug("Motorola");
-                ENDIAN::BIG_ENDIAN
-            }
-            _ => {
-                logger::debug("unknown byte order");
-                return None;
-            }
+        exif.load_ifd(src, header.offset);
+
+        Some(exif)
+    }
+
+    /// TIFF stream whose IFD0 is a GPS IFD, as in CR3 CMT4.
+    pub fn load_gps(data:&[u8]) -> Option<EXIF> {
+        let mut src = data;
+        let header = read_header(&mut src)?;
+        let mut exif = EXIF {
+            byte_order: header.byte_order,
+            big_tiff: header.big_tiff,
+            values: HashMap::new(),
+            visited: Vec::new(),
         };
-        let offset = read_4bytes(data, 4, &exif.byte_order);
-        exif.load_ifd(data, offset.unwrap() as usize);
+        exif.load_gps_ifd(&mut src, header.offset);
 
         Some(exif)
     }
 
-    fn load_ifd(&mut self, data:&[u8], offset:usize) -> Option<usize> {
-        let mut cnt:usize = 0;
-        let mut ifd_offset:usize = offset;
+    /// Adds the values of `other` that are not set yet.
+    pub fn merge(&mut self, other: EXIF) {
+        for (key, value) in other.values {
+            self.values.entry(key).or_insert(value);
+        }
+    }
 
-        let ifd_cnt = read_2bytes(data, ifd_offset, &self.byte_order);
-        if ifd_cnt.is_none() {
+    fn load_ifd<S: ReadAt>(&mut self, src: &mut S, offset:u64) -> Option<u64> {
+        if !self.visit(offset) {
             return None;
         }
-        ifd_offset += 2;
+        let ifd = read_ifd(src, offset, &self.byte_order, self.big_tiff)?;
+        for entry in &ifd.entries {
+            self.tag(entry, src);
+        }
 
-        cnt += 2;
+        Some(ifd.next)
+    }
 
-        for _ in 0..ifd_cnt.unwrap() {
-            let tag = read_2bytes(data, ifd_offset, &self.byte_order);
-            if tag.is_none() {
-                return None;
-            }
-            ifd_offset += 2;
+    fn visit(&mut self, offset: u64) -> bool {
+        if self.visited.contains(&offset) {
+            logger::debug(format!("IFD at {:X} already read", offset).as_str());
+            return false;
+        }
+        self.visited.push(offset);
+        true
+    }
 
-            let ifd_type = read_2bytes(data, ifd_offset, &self.byte_order);
-            if ifd_type.is_none() {
-                return None;
+    fn tag<S: ReadAt>(&mut self, entry: &IfdEntry, src: &mut S) {
+        match entry.tag {
+            0x00FE => {
+                self.tag_int(entry, "NewSubfileType");
             }
-            ifd_offset += 2;
-
-            let count = read_4bytes(data, ifd_offset, &self.byte_order);
-            if count.is_none() {
-                return None;
+            0x0100 => {
+                self.tag_int(entry, "ImageWidth");
             }
-            ifd_offset += 4;
-
-            let value = read_4bytes(data, ifd_offset, &self.byte_order);
-            if value.is_none() {
-                return None;
+            0x0101 => {
+                self.tag_int(entry, "ImageLength");
+            }
+            0x0102 => {
+                self.tag_int(entry, "BitsPerSample");
+            }
+            0x0103 => {
+                self.tag_int(entry, "Compression");
+            }
+            0x0106 => {
+                self.tag_int(entry, "PhotometricInterpretation");
+            }
+            0x010E => {
+                self.tag_string(entry, "ImageDescription");
+            }
+            0x0111 | 0x0117 | 0x0144 | 0x0145 => {
+                // StripOffsets, StripByteCounts, TileOffsets, TileByteCounts: see TIFF images
+            }
+            0x014A => {
+                // SubIFDs: see TIFF images
+            }
+            0x0115 => {
+                self.tag_int(entry, "SamplesPerPixel");
+            }
+            0x0116 => {
+                self.tag_int(entry, "RowsPerStrip");
+            }
+            0x011C => {
+                self.tag_int(entry, "PlanarConfiguration");
+            }
+            0x0142 => {
+                self.tag_int(entry, "TileWidth");
+            }
+            0x0143 => {
+                self.tag_int(entry, "TileLength");
+            }
+            0x0153 => {
+                self.tag_int(entry, "SampleFormat");
+            }
+            0x013E => {
+                self.tag_rational(entry, "WhitePoint");
+            }
+            0x013F => {
+                self.tag_rational(entry, "PrimaryChromaticities");
+            }
+            0x0201 => {
+                self.tag_int(entry, "JPEGInterchangeFormat");
+            }
+            0x0202 => {
+                self.tag_int(entry, "JPEGInterchangeFormatLength");
+            }
+            0x0211 => {
+                self.tag_rational(entry, "YCbCrCoefficients");
+            }
+            0x0212 => {
+                self.tag_int(entry, "YCbCrSubSampling");
             }
-            ifd_offset += 4;
-
-            self.tag(tag.unwrap(), ifd_type.unwrap(), count.unwrap(), value.unwrap(), data);
-        }
-
-        Some(cnt)
-    }
-
-    fn tag(&mut self, tag: u16, ifd_type: u16, count: u32, value: u32, data:&[u8]) {
-        match tag {
             0x010F => {
-                self.tag_string(ifd_type, count, value, data, "Make");
+                self.tag_string(entry, "Make");
             }
             0x0110 => {
-                self.tag_string(ifd_type, count, value, data, "Model");
+                self.tag_string(entry, "Model");
             }
             0x0112 => {
-                self.values.insert("Orientation".to_string(), value.to_string());
+                self.tag_int(entry, "Orientation");
             }
             0x11A => {
-                self.tag_rational(ifd_type, count, value, data, "XResolution");
+                self.tag_rational(entry, "XResolution");
             }
             0x11B => {
-                self.tag_rational(ifd_type, count, value, data, "YResolution");
+                self.tag_rational(entry, "YResolution");
             }
             0x128 => {
-                self.values.insert("ResolutionUnit".to_string(), value.to_string());
+                self.tag_int(entry, "ResolutionUnit");
             }
             0x131 => {
-                self.tag_string(ifd_type, count, value, data, "Software");
+                self.tag_string(entry, "Software");
             }
             0x132 => {
-                self.tag_string(ifd_type, count, value, data, "DateTime");
+                self.tag_string(entry, "DateTime");
             }
             0x13B => {
-                self.tag_string(ifd_type, count, value, data, "Artist");
+                self.tag_string(entry, "Artist");
             }
             0x213 => {
-                self.values.insert("YCbCrPositioning".to_string(), value.to_string());
+                self.tag_int(entry, "YCbCrPositioning");
             }
             0x8298 => {
-                self.tag_string(ifd_type, count, value, data, "Copyright");
+                self.tag_string(entry, "Copyright");
             }
             0x829A => {
-                self.tag_rational(ifd_type, count, value, data, "ExposureTime");
+                self.tag_rational(entry, "ExposureTime");
             }
             0x829D => {
-                self.tag_rational(ifd_type, count, value, data, "FNumber");
+                self.tag_rational(entry, "FNumber");
             }
             0x8769 => {
-                self.load_ifd(data, value as usize);
+                if let Some(offset) = entry.u64_value(&self.byte_order) {
+                    self.load_ifd(src, offset);
+                }
             }
             0x8822 => {
-                self.values.insert("ExposureProgram".to_string(), value.to_string());
+                self.tag_int(entry, "ExposureProgram");
             }
             0x8825 => {
-                // TODO: GPSInfo
+                if let Some(offset) = entry.u64_value(&self.byte_order) {
+                    self.load_gps_ifd(src, offset);
+                }
             }
             0x8827 => {
-                self.values.insert("PhotographicSensivility".to_string(), value.to_string());
+                self.tag_int(entry, "PhotographicSensivility");
             }
             0x8830 => {
-                self.values.insert("SensitivityType".to_string(), value.to_string());
+                self.tag_int(entry, "SensitivityType");
             }
             0x9000 => {
-                // TODO: ExifVersion
+                self.tag_version(entry, "ExifVersion");
             }
             0x9003 => {
-                self.tag_string(ifd_type, count, value, data, "DateTimeOriginal");
+                self.tag_string(entry, "DateTimeOriginal");
             }
             0x9004 => {
-                self.tag_string(ifd_type, count, value, data, "DateTimeDigitized");
+                self.tag_string(entry, "DateTimeDigitized");
             }
             0x9010 => {
-                self.tag_string(ifd_type, count, value, data, "OffsetTime");
+                self.tag_string(entry, "OffsetTime");
             }
             0x9101 => {
                 // TODO: ComponentsConfiguration
                 // self.values.insert("ComponentsConfiguration".to_string(), format!("{:08X}", value));
             }
             0x9102 => {
-                self.tag_rational(ifd_type, count, value, data, "CompressedBitsPerPixel");
+                self.tag_rational(entry, "CompressedBitsPerPixel");
             }
             0x9201 => {
-                self.tag_srational(ifd_type, count, value, data, "ShutterSpeedValue");
+                self.tag_srational(entry, "ShutterSpeedValue");
             }
             0x9202 => {
-                self.tag_rational(ifd_type, count, value, data, "ApertureValue");
+                self.tag_rational(entry, "ApertureValue");
             }
             0x9203 => {
-                self.tag_srational(ifd_type, count, value, data, "BrightnessValue");
+                self.tag_srational(entry, "BrightnessValue");
             }
             0x9204 => {
-                self.tag_srational(ifd_type, count, value, data, "ExposureBiasValue");
+                self.tag_srational(entry, "ExposureBiasValue");
             }
             0x9205 => {
-                self.tag_rational(ifd_type, count, value, data, "MaxApertureValue");
+                self.tag_rational(entry, "MaxApertureValue");
             }
             0x9207 => {
-                self.values.insert("MeteringMode".to_string(), value.to_string());
+                self.tag_int(entry, "MeteringMode");
             }
             0x9208 => {
-                self.values.insert("LightSource".to_string(), value.to_string());
+                self.tag_int(entry, "LightSource");
             }
             0x9209 => {
-                self.values.insert("Flash".to_string(), value.to_string());
+                self.tag_int(entry, "Flash");
             }
             0x920A => {
-                self.tag_rational(ifd_type, count, value, data, "FocalLength");
+                self.tag_rational(entry, "FocalLength");
             }
             0x9214 => {
-                self.values.insert("SubjectArea".to_string(), value.to_string());
+                self.tag_int(entry, "SubjectArea");
             }
             0x927C => {
-                // perhaps MakerNote is string?
-                self.values.insert("MakerNote".to_string(), value.to_string());
+                self.values.insert("MakerNote".to_string(), format!("({} bytes)", entry.data.len()));
             }
             0x9286 => {
-                // perhaps UserComment is string?
-                self.values.insert("UserComment".to_string(), value.to_string());
+                self.tag_comment(entry, "UserComment");
             }
             0x9290 => {
-                self.tag_string(ifd_type, count, value, data, "SubSecTime");
+                self.tag_string(entry, "SubSecTime");
             }
             0x9291 => {
-                self.tag_string(ifd_type, count, value, data, "SubSecTimeOriginal");
+                self.tag_string(entry, "SubSecTimeOriginal");
             }
             0x9292 => {
-                self.tag_string(ifd_type, count, value, data, "SubSecTimeDigitized");
+                self.tag_string(entry, "SubSecTimeDigitized");
             }
             0xA000 => {
-                self.values.insert("FlashPixVersion".to_string(), value.to_string());
+                self.tag_version(entry, "FlashPixVersion");
             }
             0xA001 => {
-                self.values.insert("ColorSpace".to_string(), value.to_string());
+                self.tag_int(entry, "ColorSpace");
             }
             0xA002 => {
-                self.values.insert("PixelXDimension".to_string(), value.to_string());
+                self.tag_int(entry, "PixelXDimension");
             }
             0xA003 => {
-                self.values.insert("PixelYDimension".to_string(), value.to_string());
+                self.tag_int(entry, "PixelYDimension");
             }
             0xA005  => {
                 // TODO: InteroperabilityIFDPointer
             }
             0xA20E => {
-                self.tag_rational(ifd_type, count, value, data, "FocalPlaneXResolution");
+                self.tag_rational(entry, "FocalPlaneXResolution");
             }
             0xA20F => {
-                self.tag_rational(ifd_type, count, value, data, "FocalPlaneYResolution");
+                self.tag_rational(entry, "FocalPlaneYResolution");
             }
             0xA210 => {
-                self.values.insert("FocalPlaneResolutionUnit".to_string(), value.to_string());
+                self.tag_int(entry, "FocalPlaneResolutionUnit");
             }
             0xA217 => {
-                self.values.insert("SensingMethod".to_string(), value.to_string());
+                self.tag_int(entry, "SensingMethod");
             }
             0xA300 => {
-                self.values.insert("FileSource".to_string(), value.to_string());
+                self.tag_int(entry, "FileSource");
             }
             0xA301 => {
-                self.values.insert("SceneType".to_string(), value.to_string());
+                self.tag_int(entry, "SceneType");
             }
             0xA302 => {
-                self.values.insert("CFAPattern".to_string(), value.to_string());
+                self.tag_int(entry, "CFAPattern");
             }
             0xA401 => {
-                self.values.insert("CustomRendered".to_string(), value.to_string());
+                self.tag_int(entry, "CustomRendered");
             }
             0xA402 => {
-                self.values.insert("ExposureMode".to_string(), value.to_string());
+                self.tag_int(entry, "ExposureMode");
             }
             0xA403 => {
-                self.values.insert("WhiteBalance".to_string(), value.to_string());
+                self.tag_int(entry, "WhiteBalance");
             }
             0xA404 => {
-                self.tag_rational(ifd_type, count, value, data, "DigitalZoomRatio");
+                self.tag_rational(entry, "DigitalZoomRatio");
             }
             0xA405 => {
-                self.values.insert("FocalLengthIn35mmFilm".to_string(), value.to_string());
+                self.tag_int(entry, "FocalLengthIn35mmFilm");
             }
             0xA406 => {
-                self.values.insert("SceneCaptureType".to_string(), value.to_string());
+                self.tag_int(entry, "SceneCaptureType");
             }
             0xA407 => {
-                self.values.insert("GainControl".to_string(), value.to_string());
+                self.tag_int(entry, "GainControl");
             }
             0xA408 => {
-                self.values.insert("Contrast".to_string(), value.to_string());
+                self.tag_int(entry, "Contrast");
             }
             0xA409 => {
-                self.values.insert("Saturation".to_string(), value.to_string());
+                self.tag_int(entry, "Saturation");
             }
             0xA40A => {
-                self.values.insert("Sharpness".to_string(), value.to_string());
+                self.tag_int(entry, "Sharpness");
             }
             0xA40C => {
-                self.values.insert("SubjectDistanceRange".to_string(), value.to_string());
+                self.tag_int(entry, "SubjectDistanceRange");
             }
             0xA431 => {
-                self.tag_string(ifd_type, count, value, data, "BodySerialNumber");
+                self.tag_string(entry, "BodySerialNumber");
             }
             0xA432 => {
-                self.tag_rational(ifd_type, count, value, data, "LensSpecification");
+                self.tag_rational(entry, "LensSpecification");
             }
             0xA433 => {
-                self.tag_string(ifd_type, count, value, data, "LensMake");
+                self.tag_string(entry, "LensMake");
             }
             0xA434 => {
-                self.tag_string(ifd_type, count, value, data, "LensModel");
+                self.tag_string(entry, "LensModel");
+            }
+            0xC612 => {
+                self.tag_dotted(entry, "DNGVersion");
+            }
+            0xC613 => {
+                self.tag_dotted(entry, "DNGBackwardVersion");
+            }
+            0xC614 => {
+                self.tag_string(entry, "UniqueCameraModel");
+            }
+            0xC621 => {
+                self.tag_srational(entry, "ColorMatrix1");
+            }
+            0xC622 => {
+                self.tag_srational(entry, "ColorMatrix2");
+            }
+            0xC623 => {
+                self.tag_srational(entry, "CameraCalibration1");
+            }
+            0xC624 => {
+                self.tag_srational(entry, "CameraCalibration2");
+            }
+            0xC628 => {
+                // RATIONAL, or SHORT in some writers
+                if entry.ifd_type == 5 {
+                    self.tag_rational(entry, "AsShotNeutral");
+                } else {
+                    self.tag_int(entry, "AsShotNeutral");
+                }
+            }
+            0xC629 => {
+                self.tag_rational(entry, "AsShotWhiteXY");
+            }
+            0xC62A => {
+                self.tag_srational(entry, "BaselineExposure");
+            }
+            0xC62F => {
+                self.tag_string(entry, "CameraSerialNumber");
+            }
+            0xC65A => {
+                self.tag_int(entry, "CalibrationIlluminant1");
+            }
+            0xC65B => {
+                self.tag_int(entry, "CalibrationIlluminant2");
+            }
+            0xC68B => {
+                self.tag_string(entry, "OriginalRawFileName");
             }
             0x214 => {
-                self.tag_rational(ifd_type, count, value+0, data, "ReferenceBlackWhite0");
-                self.tag_rational(ifd_type, count, value+8, data, "ReferenceBlackWhite1");
-                self.tag_rational(ifd_type, count, value+16, data, "ReferenceBlackWhite2");
-                self.tag_rational(ifd_type, count, value+24, data, "ReferenceBlackWhite3");
-                self.tag_rational(ifd_type, count, value+32, data, "ReferenceBlackWhite4");
-                self.tag_rational(ifd_type, count, value+40, data, "ReferenceBlackWhite5");
+                for i in 0..6 {
+                    if let Some(rational) = self.rational_at(entry, i) {
+                        self.values.insert(format!("ReferenceBlackWhite{}", i), rational);
+                    }
+                }
+            }
+            _ => {
+                unknown_tag(entry);
+            }
+        }
+    }
+
+    fn load_gps_ifd<S: ReadAt>(&mut self, src: &mut S, offset:u64) -> Option<u64> {
+        if !self.visit(offset) {
+            return None;
+        }
+        let ifd = read_ifd(src, offset, &self.byte_order, self.big_tiff)?;
+        for entry in &ifd.entries {
+            self.gps_tag(entry);
+        }
+
+        Some(ifd.next)
+    }
+
+    fn gps_tag(&mut self, entry: &IfdEntry) {
+        match entry.tag {
+            0x00 => {
+                self.tag_dotted(entry, "GPSVersionID");
+            }
+            0x01 => {
+                self.tag_string(entry, "GPSLatitudeRef");
+            }
+            0x02 => {
+                self.tag_rational(entry, "GPSLatitude");
+            }
+            0x03 => {
+                self.tag_string(entry, "GPSLongitudeRef");
+            }
+            0x04 => {
+                self.tag_rational(entry, "GPSLongitude");
+            }
+            0x05 => {
+                self.tag_int(entry, "GPSAltitudeRef");
+            }
+            0x06 => {
+                self.tag_rational(entry, "GPSAltitude");
+            }
+            0x07 => {
+                self.tag_rational(entry, "GPSTimeStamp");
+            }
+            0x08 => {
+                self.tag_string(entry, "GPSSatellites");
+            }
+            0x09 => {
+                self.tag_string(entry, "GPSStatus");
+            }
+            0x0A => {
+                self.tag_string(entry, "GPSMeasureMode");
+            }
+            0x0B => {
+                self.tag_rational(entry, "GPSDOP");
+            }
+            0x0C => {
+                self.tag_string(entry, "GPSSpeedRef");
+            }
+            0x0D => {
+                self.tag_rational(entry, "GPSSpeed");
+            }
+            0x0E => {
+                self.tag_string(entry, "GPSTrackRef");
+            }
+            0x0F => {
+                self.tag_rational(entry, "GPSTrack");
+            }
+            0x10 => {
+                self.tag_string(entry, "GPSImgDirectionRef");
+            }
+            0x11 => {
+                self.tag_rational(entry, "GPSImgDirection");
+            }
+            0x12 => {
+                self.tag_string(entry, "GPSMapDatum");
+            }
+            0x13 => {
+                self.tag_string(entry, "GPSDestLatitudeRef");
+            }
+            0x14 => {
+                self.tag_rational(entry, "GPSDestLatitude");
+            }
+            0x15 => {
+                self.tag_string(entry, "GPSDestLongitudeRef");
+            }
+            0x16 => {
+                self.tag_rational(entry, "GPSDestLongitude");
+            }
+            0x17 => {
+                self.tag_string(entry, "GPSDestBearingRef");
+            }
+            0x18 => {
+                self.tag_rational(entry, "GPSDestBearing");
+            }
+            0x19 => {
+                self.tag_string(entry, "GPSDestDistanceRef");
+            }
+            0x1A => {
+                self.tag_rational(entry, "GPSDestDistance");
+            }
+            0x1B => {
+                self.tag_comment(entry, "GPSProcessingMethod");
+            }
+            0x1C => {
+                self.tag_comment(entry, "GPSAreaInformation");
+            }
+            0x1D => {
+                self.tag_string(entry, "GPSDateStamp");
+            }
+            0x1E => {
+                self.tag_int(entry, "GPSDifferential");
+            }
+            0x1F => {
+                self.tag_rational(entry, "GPSHPositioningError");
             }
             _ => {
-                logger::debug("unknown tag");
-                logger::debug(format!("tag: {:04X}", tag).as_str());
-                logger::debug(format!("type: {:04X}", ifd_type).as_str());
-                logger::debug(format!("count: {}", count).as_str());
-                logger::debug(format!("value: {}", value).as_str());
+                unknown_tag(entry);
             }
         }
     }
 
-    fn tag_string(&mut self, ifd_type: u16, count: u32, value: u32, data:&[u8], tag_name:&str) {
-        if ifd_type != 2 {
+    fn tag_string(&mut self, entry: &IfdEntry, tag_name:&str) {
+        if entry.ifd_type != 2 {
             logger::debug("invalid type");
             return;
         }
-        if count <= 4 {
+        let end = entry.data.iter().position(|&b| b == 0).unwrap_or(entry.data.len());
+        self.values.insert(tag_name.to_string(), String::from_utf8_lossy(&entry.data[..end]).to_string());
+    }
+
+    // BYTE, SHORT or LONG, several values separated by spaces
+    fn tag_int(&mut self, entry: &IfdEntry, tag_name:&str) {
+        let values:Vec<String> = entry.u32_values(&self.byte_order).iter().map(|v| v.to_string()).collect();
+        if values.is_empty() {
+            logger::debug("invalid type");
             return;
         }
-        let offset = value as usize;
-        let data_slice = &data[offset..offset+count as usize];
-        self.values.insert(tag_name.to_string(), String::from_utf8_lossy(data_slice).to_string());
+        self.values.insert(tag_name.to_string(), values.join(" "));
     }
 
-    fn tag_rational(&mut self, ifd_type: u16, count: u32, value: u32, data:&[u8], tag_name:&str) {
-        if ifd_type != 5 {
+    fn tag_rational(&mut self, entry: &IfdEntry, tag_name:&str) {
+        if entry.ifd_type != 5 {
             logger::debug("invalid type");
             return;
         }
-        let offset = value as usize;
-        let top = read_4bytes(data, offset, &self.byte_order);
-        let bottom = read_4bytes(data, offset+4, &self.byte_order);
-        self.values.insert(tag_name.to_string(), format!("{}/{}", top.unwrap(), bottom.unwrap()));
+        self.tag_rationals(entry, tag_name);
     }
 
-    fn tag_srational(&mut self, ifd_type: u16, count: u32, value: u32, data:&[u8], tag_name:&str) {
-        if ifd_type != 10 {
+    fn tag_srational(&mut self, entry: &IfdEntry, tag_name:&str) {
+        if entry.ifd_type != 10 {
             logger::debug("invalid type");
             return;
         }
-        let offset = value as usize;
-        let top = read_4bytes_signed(data, offset, &self.byte_order);
-        let bottom = read_4bytes_signed(data, offset+4, &self.byte_order);
-        self.values.insert(tag_name.to_string(), format!("{}/{}", top.unwrap(), bottom.unwrap()));
+        self.tag_rationals(entry, tag_name);
     }
-}
 
-fn read_2bytes(data: &[u8], offset: usize, endian: &ENDIAN) -> Option<u16> {
-    let mut r:u16 = 0;
+    fn tag_rationals(&mut self, entry: &IfdEntry, tag_name:&str) {
+        let values:Vec<String> = (0..entry.count as usize).filter_map(|i| self.rational_at(entry, i)).collect();
+        if !values.is_empty() {
+            self.values.insert(tag_name.to_string(), values.join(" "));
+        }
+    }
 
-    match endian {
-        ENDIAN::BIG_ENDIAN => {
-            r = data[offset] as u16;
-            r <<= 8;
-            r |= data[offset+1] as u16;
+    fn rational_at(&self, entry: &IfdEntry, index: usize) -> Option<String> {
+        match entry.ifd_type {
+            5 => {
+                let top = read_4bytes(&entry.data, index*8, &self.byte_order)?;
+                let bottom = read_4bytes(&entry.data, index*8+4, &self.byte_order)?;
+                Some(format!("{}/{}", top, bottom))
+            }
+            10 => {
+                let top = read_4bytes_signed(&entry.data, index*8, &self.byte_order)?;
+                let bottom = read_4bytes_signed(&entry.data, index*8+4, &self.byte_order)?;
+                Some(format!("{}/{}", top, bottom))
+            }
+            _ => None,
         }
-        ENDIAN::LITTLE_ENDIAN =>{
-            r = data[offset+1] as u16;
-            r <<= 8;
-            r |= data[offset] as u16;
+    }
+
+    // 4 ASCII digits stored as UNDEFINED, e.g. "0232"
+    fn tag_version(&mut self, entry: &IfdEntry, tag_name:&str) {
+        self.values.insert(tag_name.to_string(), String::from_utf8_lossy(&entry.data).to_string());
+    }
+
+    // 4 BYTEs, e.g. "1.4.0.0"
+    fn tag_dotted(&mut self, entry: &IfdEntry, tag_name:&str) {
+        let version:Vec<String> = entry.data.iter().map(|b| b.to_string()).collect();
+        self.values.insert(tag_name.to_string(), version.join("."));
+    }
+
+    // UNDEFINED with an 8 byte character code header
+    fn tag_comment(&mut self, entry: &IfdEntry, tag_name:&str) {
+        if entry.data.len() < 8 {
+            return;
         }
-        _ => {
+        let (code, text) = entry.data.split_at(8);
+        let comment = match code {
+            b"UNICODE\0" => {
+                let units:Vec<u16> = text.chunks_exact(2).map(|c| read_2bytes(c, 0, &self.byte_order).unwrap_or(0)).collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => String::from_utf8_lossy(text).to_string(),
+        };
+        self.values.insert(tag_name.to_string(), comment.trim_end_matches(['\0', ' ']).to_string());
+    }
+}
+
+fn unknown_tag(entry: &IfdEntry) {
+    logger::debug("unknown tag");
+    logger::debug(format!("tag: {:04X}", entry.tag).as_str());
+    logger::debug(format!("type: {:04X}", entry.ifd_type).as_str());
+    logger::debug(format!("count: {}", entry.count).as_str());
+}
+
+impl Ifd {
+    pub fn get(&self, tag: u16) -> Option<&IfdEntry> {
+        self.entries.iter().find(|e| e.tag == tag)
+    }
+}
+
+impl IfdEntry {
+    /// BYTE, SHORT, LONG and LONG8 values widened to u64.
+    pub(crate) fn u64_values(&self, endian: &ENDIAN) -> Vec<u64> {
+        let size = type_size(self.ifd_type);
+        (0..self.count as usize).map_while(|i| {
+            match self.ifd_type {
+                1 | 7 => self.data.get(i).map(|&b| b as u64),
+                3 => read_2bytes(&self.data, i * size, endian).map(|v| v as u64),
+                4 | 9 | 13 => read_4bytes(&self.data, i * size, endian).map(|v| v as u64),
+                16..=18 => read_8bytes(&self.data, i * size, endian),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    pub(crate) fn u64_value(&self, endian: &ENDIAN) -> Option<u64> {
+        self.u64_values(endian).first().copied()
+    }
+
+    pub(crate) fn u32_values(&self, endian: &ENDIAN) -> Vec<u32> {
+        self.u64_values(endian).iter().map(|&v| v as u32).collect()
+    }
+
+    pub(crate) fn u32_value(&self, endian: &ENDIAN) -> Option<u32> {
+        self.u64_value(endian).map(|v| v as u32)
+    }
+}
+
+impl ReadAt for &[u8] {
+    fn read_at(&mut self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        let offset = usize::try_from(offset).ok()?;
+        self.get(offset..offset.checked_add(len)?).map(|d| d.to_vec())
+    }
+
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl<R: Read + Seek> SeekReader<R> {
+    pub(crate) fn new(mut f: R) -> Option<SeekReader<R>> {
+        let len = f.seek(SeekFrom::End(0)).ok()?;
+        Some(SeekReader {
+            f,
+            len,
+        })
+    }
+}
+
+impl<R: Read + Seek> ReadAt for SeekReader<R> {
+    fn read_at(&mut self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        // checked before allocating, counts in broken files can be huge
+        if offset.checked_add(len as u64)? > self.len {
             return None;
         }
+        let mut buff = vec![0; len];
+        self.f.seek(SeekFrom::Start(offset)).ok()?;
+        self.f.read_exact(&mut buff).ok()?;
+        Some(buff)
     }
 
-    Some(r)
+    fn size(&self) -> u64 {
+        self.len
+    }
 }
 
-fn read_4bytes(data: &[u8], offset: usize, endian: &ENDIAN) -> Option<u32> {
-    let mut r:u32 = 0;
+/// "II"/"MM", magic number and offset of IFD0 (BigTIFF: byte size 8, 0, 8-byte offset).
+pub(crate) fn read_header<S: ReadAt>(src: &mut S) -> Option<TiffHeader> {
+    let head = src.read_at(0, 8)?;
 
-    match endian {
-        ENDIAN::BIG_ENDIAN => {
-            r = data[offset] as u32;
-            r <<= 8;
-            r |= data[offset+1] as u32;
-            r <<= 8;
-            r |= data[offset+2] as u32;
-            r <<= 8;
-            r |= data[offset+3] as u32;
+    // byte order
+    let byte_order = match head[0] {
+        0x49 => {
+            logger::debug("Intel");
+            ENDIAN::LITTLE_ENDIAN
         }
-        ENDIAN::LITTLE_ENDIAN => {
-            r = data[offset+3] as u32;
-            r <<= 8;
-            r |= data[offset+2] as u32;
-            r <<= 8;
-            r |= data[offset+1] as u32;
-            r <<= 8;
-            r |= data[offset] as u32;
+        0x4D => {
+            logger::debug("Motorola");
+            ENDIAN::BIG_ENDIAN
         }
         _ => {
+            logger::debug("unknown byte order");
+            return None;
+        }
+    };
+    let magic = read_2bytes(&head, 2, &byte_order)?;
+
+    if magic == 43 {
+        logger::debug("BigTIFF");
+        let head = src.read_at(0, 16)?;
+        if read_2bytes(&head, 4, &byte_order)? != 8 {
+            logger::debug("unsupported BigTIFF offset size");
             return None;
         }
+        return Some(TiffHeader {
+            offset: read_8bytes(&head, 8, &byte_order)?,
+            byte_order,
+            magic,
+            big_tiff: true,
+        });
     }
 
-    Some(r)
+    Some(TiffHeader {
+        offset: read_4bytes(&head, 4, &byte_order)? as u64,
+        byte_order,
+        magic,
+        big_tiff: false,
+    })
+}
+
+pub(crate) fn type_size(ifd_type: u16) -> usize {
+    match ifd_type {
+        1 | 2 | 6 | 7 => 1,     // BYTE, ASCII, SBYTE, UNDEFINED
+        3 | 8 => 2,             // SHORT, SSHORT
+        4 | 9 | 11 | 13 => 4,   // LONG, SLONG, FLOAT, IFD
+        5 | 10 | 12 => 8,       // RATIONAL, SRATIONAL, DOUBLE
+        16..=18 => 8,           // LONG8, SLONG8, IFD8
+        _ => 0,
+    }
+}
+
+/// Reads the IFD at `offset`, with values that do not fit in the entry fetched from their offset.
+/// Classic TIFF entries are 12 bytes with a 4-byte value field, BigTIFF ones 20 bytes with 8.
+pub(crate) fn read_ifd<S: ReadAt>(src: &mut S, offset: u64, endian: &ENDIAN, big_tiff: bool) -> Option<Ifd> {
+    let (count_size, entry_size, field_size) = if big_tiff {
+        (8, 20, 8)
+    } else {
+        (2, 12, 4)
+    };
+
+    let head = src.read_at(offset, count_size)?;
+    let cnt = if big_tiff {
+        read_8bytes(&head, 0, endian)?
+    } else {
+        read_2bytes(&head, 0, endian)? as u64
+    };
+    let table_size = usize::try_from(cnt).ok()?.checked_mul(entry_size)?;
+    let table = src.read_at(offset + count_size as u64, table_size + field_size)
+        .or_else(|| src.read_at(offset + count_size as u64, table_size))?;
+
+    let mut ifd = Ifd {
+        entries: Vec::with_capacity(cnt as usize),
+        next: 0,
+    };
+    for raw in table.chunks_exact(entry_size).take(cnt as usize) {
+        let tag = read_2bytes(raw, 0, endian)?;
+        let ifd_type = read_2bytes(raw, 2, endian)?;
+        let (count, field) = if big_tiff {
+            (read_8bytes(raw, 4, endian)?, &raw[12..20])
+        } else {
+            (read_4bytes(raw, 4, endian)? as u64, &raw[8..12])
+        };
+
+        let size = (type_size(ifd_type) as u64).saturating_mul(count);
+        let value = if size <= field_size as u64 {
+            Some(field[..size as usize].to_vec())
+        } else {
+            let value_offset = if big_tiff {
+                read_8bytes(field, 0, endian)?
+            } else {
+                read_4bytes(field, 0, endian)? as u64
+            };
+            usize::try_from(size).ok().and_then(|size| src.read_at(value_offset, size))
+        };
+        match value {
+            Some(data) => {
+                ifd.entries.push(IfdEntry {
+                    tag,
+                    ifd_type,
+                    count,
+                    data,
+                });
+            }
+            None => {
+                logger::debug(format!("invalid value offset of tag {:04X}", tag).as_str());
+            }
+        }
+    }
+    ifd.next = if big_tiff {
+        read_8bytes(&table, table_size, endian).unwrap_or(0)
+    } else {
+        read_4bytes(&table, table_size, endian).unwrap_or(0) as u64
+    };
+
+    Some(ifd)
 }
 
-fn read_4bytes_signed(data: &[u8], offset: usize, endian: &ENDIAN) -> Option<i32> {
-    let mut r:u32 = 0;
+pub(crate) fn put_2bytes(out: &mut Vec<u8>, v: u16, endian: &ENDIAN) {
+    match endian {
+        ENDIAN::BIG_ENDIAN => out.extend_from_slice(&v.to_be_bytes()),
+        ENDIAN::LITTLE_ENDIAN => out.extend_from_slice(&v.to_le_bytes()),
+    }
+}
 
+pub(crate) fn put_4bytes(out: &mut Vec<u8>, v: u32, endian: &ENDIAN) {
     match endian {
+        ENDIAN::BIG_ENDIAN => out.extend_from_slice(&v.to_be_bytes()),
+        ENDIAN::LITTLE_ENDIAN => out.extend_from_slice(&v.to_le_bytes()),
+    }
+}
+
+pub(crate) fn read_2bytes(data: &[u8], offset: usize, endian: &ENDIAN) -> Option<u16> {
+    let b = data.get(offset..offset+2)?;
+    let r:u16 = match endian {
         ENDIAN::BIG_ENDIAN => {
-            r = data[offset] as u32;
-            r <<= 8;
-            r |= data[offset+1] as u32;
-            r <<= 8;
-            r |= data[offset+2] as u32;
-            r <<= 8;
-            r |= data[offset+3] as u32;
+            (b[0] as u16) << 8 | b[1] as u16
         }
-        ENDIAN::LITTLE_ENDIAN => {
-            r = data[offset+3] as u32;
-            r <<= 8;
-            r |= data[offset+2] as u32;
-            r <<= 8;
-            r |= data[offset+1] as u32;
-            r <<= 8;
-            r |= data[offset] as u32;
+        ENDIAN::LITTLE_ENDIAN =>{
+            (b[1] as u16) << 8 | b[0] as u16
         }
-        _ => {
-            return None;
+    };
+
+    Some(r)
+}
+
+pub(crate) fn read_4bytes(data: &[u8], offset: usize, endian: &ENDIAN) -> Option<u32> {
+    let b = data.get(offset..offset+4)?;
+    let r:u32 = match endian {
+        ENDIAN::BIG_ENDIAN => {
+            (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
         }
+        ENDIAN::LITTLE_ENDIAN => {
+            (b[3] as u32) << 24 | (b[2] as u32) << 16 | (b[1] as u32) << 8 | b[0] as u32
+        }
+    };
+
+    Some(r)
+}
+
+pub(crate) fn read_8bytes(data: &[u8], offset: usize, endian: &ENDIAN) -> Option<u64> {
+    let hi_lo = (read_4bytes(data, offset, endian)? as u64, read_4bytes(data, offset+4, endian)? as u64);
+    match endian {
+        ENDIAN::BIG_ENDIAN => Some(hi_lo.0 << 32 | hi_lo.1),
+        ENDIAN::LITTLE_ENDIAN => Some(hi_lo.1 << 32 | hi_lo.0),
     }
+}
 
-    Some(r as i32)
+pub(crate) fn read_4bytes_signed(data: &[u8], offset: usize, endian: &ENDIAN) -> Option<i32> {
+    read_4bytes(data, offset, endian).map(|r| r as i32)
 }
 
+#[allow(dead_code)]
 fn dump(data: &[u8]) {
-    let mut cnt:usize = 0;
-    for d in data {
+    for (cnt, d) in data.iter().enumerate() {
         print!("{:02X} ", d);
-        cnt += 1;
-        if cnt % 16 == 0 {
-            println!("");
+        if (cnt + 1) % 16 == 0 {
+            println!();
         }
     }
-    println!("");
-}
\ No newline at end of file
+    println!();
+}
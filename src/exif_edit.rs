@@ -0,0 +1,468 @@
+use crate::exif::*;
+use crate::logger;
+
+/// IFDs of an Exif stream.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExifIfd {
+    IFD0, EXIF, GPS, INTEROP, IFD1
+}
+
+/// Exif entries as stored, for editing and writing back as a classic TIFF stream:
+/// IFD0, the Exif, GPS and Interoperability IFDs, and IFD1 with its JPEG thumbnail.
+/// The pointer and thumbnail location tags are not kept; `write()` makes them anew.
+pub struct ExifEdit {
+    byte_order: ENDIAN,
+    pub ifd0: Vec<IfdEntry>,
+    pub exif: Vec<IfdEntry>,
+    pub gps: Vec<IfdEntry>,
+    pub interop: Vec<IfdEntry>,
+    pub ifd1: Vec<IfdEntry>,
+    pub thumbnail: Option<Vec<u8>>,
+    // offset of the MakerNote value in the loaded stream, whose own offsets may depend on it
+    maker_note_offset: Option<u32>,
+}
+
+const EXIF_POINTER: u16 = 0x8769;
+const GPS_POINTER: u16 = 0x8825;
+const INTEROP_POINTER: u16 = 0xA005;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const THUMBNAIL_LENGTH: u16 = 0x0202;
+const MAKER_NOTE: u16 = 0x927C;
+
+const ASCII: u16 = 2;
+const BYTE: u16 = 1;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+
+impl ExifEdit {
+    pub fn new(big_endian: bool) -> ExifEdit {
+        ExifEdit {
+            byte_order: if big_endian { ENDIAN::BIG_ENDIAN } else { ENDIAN::LITTLE_ENDIAN },
+            ifd0: Vec::new(),
+            exif: Vec::new(),
+            gps: Vec::new(),
+            interop: Vec::new(),
+            ifd1: Vec::new(),
+            thumbnail: None,
+            maker_note_offset: None,
+        }
+    }
+
+    /// Reads a TIFF stream, as found after "Exif\0\0" in APP1.
+    pub fn load(tiff: &[u8]) -> Option<ExifEdit> {
+        let mut src = tiff;
+        let header = read_header(&mut src)?;
+        if header.big_tiff {
+            logger::debug("BigTIFF is not written");
+            return None;
+        }
+        let endian = header.byte_order;
+        let ifd0 = read_ifd(&mut src, header.offset, &endian, false)?;
+        let pointer = |ifd: &Ifd, tag: u16| ifd.get(tag).and_then(|entry| entry.u64_value(&endian));
+
+        let exif_offset = pointer(&ifd0, EXIF_POINTER);
+        let exif = exif_offset.and_then(|offset| read_ifd(&mut src, offset, &endian, false));
+        let gps = pointer(&ifd0, GPS_POINTER).and_then(|offset| read_ifd(&mut src, offset, &endian, false));
+        let interop = exif.as_ref()
+            .and_then(|ifd| pointer(ifd, INTEROP_POINTER))
+            .and_then(|offset| read_ifd(&mut src, offset, &endian, false));
+        let ifd1 = if ifd0.next != 0 { read_ifd(&mut src, ifd0.next, &endian, false) } else { None };
+
+        let mut edit = ExifEdit::new(matches!(endian, ENDIAN::BIG_ENDIAN));
+        if let Some(ifd1) = &ifd1 {
+            if ifd1.get(0x0111).is_some() {
+                logger::debug("uncompressed thumbnail dropped");
+            } else if let (Some(offset), Some(length)) = (pointer(ifd1, THUMBNAIL_OFFSET), pointer(ifd1, THUMBNAIL_LENGTH)) {
+                edit.thumbnail = offset.checked_add(length)
+                    .and_then(|end| tiff.get(offset as usize..end as usize))
+                    .map(|data| data.to_vec());
+                edit.ifd1 = without(ifd1.entries.clone(), &[THUMBNAIL_OFFSET, THUMBNAIL_LENGTH]);
+            } else {
+                edit.ifd1 = ifd1.entries.clone();
+            }
+        }
+        edit.maker_note_offset = exif_offset.and_then(|offset| value_offset(tiff, offset, MAKER_NOTE, &endian));
+        edit.ifd0 = without(ifd0.entries, &[EXIF_POINTER, GPS_POINTER]);
+        edit.exif = without(exif.map(|ifd| ifd.entries).unwrap_or_default(), &[INTEROP_POINTER]);
+        edit.gps = gps.map(|ifd| ifd.entries).unwrap_or_default();
+        edit.interop = interop.map(|ifd| ifd.entries).unwrap_or_default();
+        Some(edit)
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        matches!(self.byte_order, ENDIAN::BIG_ENDIAN)
+    }
+
+    pub fn entries(&self, ifd: ExifIfd) -> &[IfdEntry] {
+        match ifd {
+            ExifIfd::IFD0 => &self.ifd0,
+            ExifIfd::EXIF => &self.exif,
+            ExifIfd::GPS => &self.gps,
+            ExifIfd::INTEROP => &self.interop,
+            ExifIfd::IFD1 => &self.ifd1,
+        }
+    }
+
+    fn entries_mut(&mut self, ifd: ExifIfd) -> &mut Vec<IfdEntry> {
+        match ifd {
+            ExifIfd::IFD0 => &mut self.ifd0,
+            ExifIfd::EXIF => &mut self.exif,
+            ExifIfd::GPS => &mut self.gps,
+            ExifIfd::INTEROP => &mut self.interop,
+            ExifIfd::IFD1 => &mut self.ifd1,
+        }
+    }
+
+    pub fn get(&self, ifd: ExifIfd, tag: u16) -> Option<&IfdEntry> {
+        self.entries(ifd).iter().find(|e| e.tag == tag)
+    }
+
+    /// Adds the entry, or replaces the one with the same tag; its data must be in the byte order of the stream.
+    pub fn set(&mut self, ifd: ExifIfd, entry: IfdEntry) {
+        let entries = self.entries_mut(ifd);
+        match entries.iter_mut().find(|e| e.tag == entry.tag) {
+            Some(e) => {
+                *e = entry;
+            }
+            None => {
+                entries.push(entry);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, ifd: ExifIfd, tag: u16) -> Option<IfdEntry> {
+        let entries = self.entries_mut(ifd);
+        let index = entries.iter().position(|e| e.tag == tag)?;
+        Some(entries.remove(index))
+    }
+
+    pub fn set_ascii(&mut self, ifd: ExifIfd, tag: u16, value: &str) {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        self.set(ifd, IfdEntry {
+            tag,
+            ifd_type: ASCII,
+            count: data.len() as u64,
+            data,
+        });
+    }
+
+    pub fn set_bytes(&mut self, ifd: ExifIfd, tag: u16, values: &[u8]) {
+        self.set(ifd, IfdEntry {
+            tag,
+            ifd_type: BYTE,
+            count: values.len() as u64,
+            data: values.to_vec(),
+        });
+    }
+
+    pub fn set_rationals(&mut self, ifd: ExifIfd, tag: u16, values: &[(u32, u32)]) {
+        let mut data = Vec::new();
+        for &(numerator, denominator) in values {
+            put_4bytes(&mut data, numerator, &self.byte_order);
+            put_4bytes(&mut data, denominator, &self.byte_order);
+        }
+        self.set(ifd, IfdEntry {
+            tag,
+            ifd_type: RATIONAL,
+            count: values.len() as u64,
+            data,
+        });
+    }
+
+    pub fn set_image_description(&mut self, description: &str) {
+        self.set_ascii(ExifIfd::IFD0, 0x010E, description);
+    }
+
+    pub fn set_artist(&mut self, artist: &str) {
+        self.set_ascii(ExifIfd::IFD0, 0x013B, artist);
+    }
+
+    pub fn set_copyright(&mut self, copyright: &str) {
+        self.set_ascii(ExifIfd::IFD0, 0x8298, copyright);
+    }
+
+    /// "YYYY:MM:DD HH:MM:SS"
+    pub fn set_date_time_original(&mut self, date_time: &str) {
+        self.set_ascii(ExifIfd::EXIF, 0x9003, date_time);
+    }
+
+    /// Position in decimal degrees, north and east positive, and altitude in metres.
+    pub fn set_gps(&mut self, latitude: f64, longitude: f64, altitude: Option<f64>) {
+        if self.get(ExifIfd::GPS, 0x0000).is_none() {
+            self.set_bytes(ExifIfd::GPS, 0x0000, &[2, 3, 0, 0]);
+        }
+        self.set_ascii(ExifIfd::GPS, 0x0001, if latitude < 0.0 { "S" } else { "N" });
+        self.set_rationals(ExifIfd::GPS, 0x0002, &degrees(latitude));
+        self.set_ascii(ExifIfd::GPS, 0x0003, if longitude < 0.0 { "W" } else { "E" });
+        self.set_rationals(ExifIfd::GPS, 0x0004, &degrees(longitude));
+        match altitude {
+            Some(altitude) => {
+                self.set_bytes(ExifIfd::GPS, 0x0005, &[(altitude < 0.0) as u8]);
+                self.set_rationals(ExifIfd::GPS, 0x0006, &[((altitude.abs() * 1000.0).round() as u32, 1000)]);
+            }
+            None => {
+                self.remove(ExifIfd::GPS, 0x0005);
+                self.remove(ExifIfd::GPS, 0x0006);
+            }
+        }
+    }
+
+    /// Classic TIFF stream: IFD0, Exif, Interoperability, GPS and IFD1, each followed by the
+    /// values that do not fit in its entries, then the MakerNote and the thumbnail. Entry data is
+    /// copied as is. A loaded MakerNote keeps its offset, the IFDs are laid out around it.
+    pub fn write(&self) -> Vec<u8> {
+        let endian = &self.byte_order;
+        let mut ifd0 = classic(without(self.ifd0.clone(), &[EXIF_POINTER, GPS_POINTER]));
+        let mut exif = classic(without(self.exif.clone(), &[INTEROP_POINTER]));
+        let interop = classic(self.interop.clone());
+        let gps = classic(self.gps.clone());
+        let mut ifd1 = classic(without(self.ifd1.clone(), &[THUMBNAIL_OFFSET, THUMBNAIL_LENGTH]));
+
+        // pointers are filled in once the offsets are known
+        let has_exif = !exif.is_empty() || !interop.is_empty();
+        if has_exif {
+            ifd0.push(long(EXIF_POINTER, 0, endian));
+        }
+        if !gps.is_empty() {
+            ifd0.push(long(GPS_POINTER, 0, endian));
+        }
+        if !interop.is_empty() {
+            exif.push(long(INTEROP_POINTER, 0, endian));
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            ifd1.push(long(THUMBNAIL_OFFSET, 0, endian));
+            ifd1.push(long(THUMBNAIL_LENGTH, thumbnail.len() as u32, endian));
+        }
+
+        let maker_note = exif.iter().find(|e| e.tag == MAKER_NOTE && e.data.len() > 4).map(|e| e.data.clone());
+        let mut blocks = vec![(ExifIfd::IFD0, ifd0)];
+        if has_exif {
+            blocks.push((ExifIfd::EXIF, exif));
+        }
+        if !interop.is_empty() {
+            blocks.push((ExifIfd::INTEROP, interop));
+        }
+        if !gps.is_empty() {
+            blocks.push((ExifIfd::GPS, gps));
+        }
+        if !ifd1.is_empty() || self.thumbnail.is_some() {
+            blocks.push((ExifIfd::IFD1, ifd1));
+        }
+
+        // a loaded MakerNote stays where it was, since its own offsets may be absolute
+        // within the stream; the blocks that would overlap it go after it
+        let pinned = match (&maker_note, self.maker_note_offset) {
+            (Some(data), Some(offset)) if offset >= 8 => Some((offset as usize, offset as usize + data.len())),
+            _ => None,
+        };
+        let place = |at: usize, size: usize| match pinned {
+            Some((start, end)) if at < end && at + size > start => end + end % 2,
+            _ => at,
+        };
+        let mut offsets = Vec::new();
+        let mut end = 8;
+        for (ifd, entries) in &blocks {
+            let size = ifd_size(entries, if *ifd == ExifIfd::EXIF { Some(MAKER_NOTE) } else { None });
+            let at = place(end, size);
+            offsets.push((*ifd, at));
+            end = at + size;
+        }
+        let offset_of = |ifd: ExifIfd| offsets.iter().find(|(i, _)| *i == ifd).map(|(_, offset)| *offset as u32);
+        let maker_note_at = match (pinned, &maker_note) {
+            (Some((start, _)), _) => start,
+            (None, Some(_)) => end,
+            (None, None) => 0,
+        };
+        if let Some(data) = &maker_note {
+            end = end.max(maker_note_at + data.len());
+            end += end % 2;
+        }
+        let thumbnail_at = end as u32;
+
+        let mut out = Vec::new();
+        match endian {
+            ENDIAN::BIG_ENDIAN => out.extend_from_slice(b"MM"),
+            ENDIAN::LITTLE_ENDIAN => out.extend_from_slice(b"II"),
+        }
+        put_2bytes(&mut out, 42, endian);
+        put_4bytes(&mut out, offset_of(ExifIfd::IFD0).unwrap_or(8), endian);
+        for (ifd, mut entries) in blocks {
+            for entry in entries.iter_mut() {
+                let value = match entry.tag {
+                    EXIF_POINTER if ifd == ExifIfd::IFD0 => offset_of(ExifIfd::EXIF),
+                    GPS_POINTER if ifd == ExifIfd::IFD0 => offset_of(ExifIfd::GPS),
+                    INTEROP_POINTER if ifd == ExifIfd::EXIF => offset_of(ExifIfd::INTEROP),
+                    THUMBNAIL_OFFSET if ifd == ExifIfd::IFD1 => Some(thumbnail_at),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    entry.data.clear();
+                    put_4bytes(&mut entry.data, value, endian);
+                }
+            }
+            let next = if ifd == ExifIfd::IFD0 { offset_of(ExifIfd::IFD1).unwrap_or(0) } else { 0 };
+            let external = if ifd == ExifIfd::EXIF && maker_note.is_some() { Some((MAKER_NOTE, maker_note_at as u32)) } else { None };
+            out.resize(offset_of(ifd).unwrap_or(0) as usize, 0);
+            write_ifd(&mut out, entries, endian, next, external);
+        }
+        if let Some(data) = maker_note {
+            out.resize(out.len().max(maker_note_at + data.len()), 0);
+            out[maker_note_at..maker_note_at + data.len()].copy_from_slice(&data);
+        }
+        out.resize(thumbnail_at as usize, 0);
+        if let Some(thumbnail) = &self.thumbnail {
+            out.extend_from_slice(thumbnail);
+        }
+        out
+    }
+}
+
+fn without(entries: Vec<IfdEntry>, tags: &[u16]) -> Vec<IfdEntry> {
+    entries.into_iter().filter(|e| !tags.contains(&e.tag)).collect()
+}
+
+// classic TIFF lacks the LONG8 types
+fn classic(entries: Vec<IfdEntry>) -> Vec<IfdEntry> {
+    entries.into_iter().filter(|e| {
+        if (16..=18).contains(&e.ifd_type) {
+            logger::debug(format!("tag {:04X} dropped: LONG8 in classic TIFF", e.tag).as_str());
+            return false;
+        }
+        true
+    }).collect()
+}
+
+fn long(tag: u16, value: u32, endian: &ENDIAN) -> IfdEntry {
+    let mut data = Vec::new();
+    put_4bytes(&mut data, value, endian);
+    IfdEntry {
+        tag,
+        ifd_type: LONG,
+        count: 1,
+        data,
+    }
+}
+
+// degrees, minutes and seconds to 1/1000
+fn degrees(value: f64) -> [(u32, u32); 3] {
+    let total = (value.abs() * 3_600_000.0).round() as u64;
+    [
+        ((total / 3_600_000) as u32, 1),
+        ((total / 60_000 % 60) as u32, 1),
+        ((total % 60_000) as u32, 1000),
+    ]
+}
+
+// value offset field of `tag` in the classic IFD at `offset`
+fn value_offset(tiff: &[u8], offset: u64, tag: u16, endian: &ENDIAN) -> Option<u32> {
+    let offset = usize::try_from(offset).ok()?;
+    let count = read_2bytes(tiff, offset, endian)? as usize;
+    (0..count).map(|i| offset + 2 + i * 12)
+        .find(|&entry| read_2bytes(tiff, entry, endian) == Some(tag))
+        .and_then(|entry| read_4bytes(tiff, entry + 8, endian))
+}
+
+fn ifd_size(entries: &[IfdEntry], external: Option<u16>) -> usize {
+    let values:usize = entries.iter()
+        .filter(|e| e.data.len() > 4 && Some(e.tag) != external)
+        .map(|e| e.data.len() + e.data.len() % 2)
+        .sum();
+    2 + 12 * entries.len() + 4 + values
+}
+
+// entries sorted by tag, values that do not fit right after the IFD except
+// the external one, whose value is written by the caller at the given offset
+fn write_ifd(out: &mut Vec<u8>, mut entries: Vec<IfdEntry>, endian: &ENDIAN, next: u32, external: Option<(u16, u32)>) {
+    entries.sort_by_key(|e| e.tag);
+    let mut value_offset = out.len() + 2 + 12 * entries.len() + 4;
+    let mut values = Vec::new();
+    put_2bytes(out, entries.len() as u16, endian);
+    for entry in &entries {
+        put_2bytes(out, entry.tag, endian);
+        put_2bytes(out, entry.ifd_type, endian);
+        put_4bytes(out, entry.count as u32, endian);
+        if entry.data.len() <= 4 {
+            out.extend_from_slice(&entry.data);
+            out.resize(out.len() + 4 - entry.data.len(), 0);
+        } else if let Some((_, offset)) = external.filter(|(tag, _)| *tag == entry.tag) {
+            put_4bytes(out, offset, endian);
+        } else {
+            put_4bytes(out, value_offset as u32, endian);
+            values.extend_from_slice(&entry.data);
+            if entry.data.len() % 2 == 1 {
+                values.push(0);
+            }
+            value_offset += entry.data.len() + entry.data.len() % 2;
+        }
+    }
+    put_4bytes(out, next, endian);
+    out.extend_from_slice(&values);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // IFD0, Exif, Interoperability, GPS and IFD1, then the MakerNote and the thumbnail
+    const BIG_ENDIAN: &[u8] = include_bytes!("../tests/data/exif_be.tiff");
+    const LITTLE_ENDIAN: &[u8] = include_bytes!("../tests/data/exif_le.tiff");
+
+    fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+        data.windows(pattern.len()).position(|w| w == pattern)
+    }
+
+    #[test]
+    fn unedited_write_reproduces_the_stream() {
+        for tiff in [BIG_ENDIAN, LITTLE_ENDIAN] {
+            let edit = ExifEdit::load(tiff).unwrap();
+            assert_eq!(edit.write(), tiff);
+        }
+    }
+
+    #[test]
+    fn same_length_edit_changes_only_its_value() {
+        for tiff in [BIG_ENDIAN, LITTLE_ENDIAN] {
+            let mut edit = ExifEdit::load(tiff).unwrap();
+            edit.set_artist("John Roe");
+            let out = edit.write();
+            assert_eq!(out.len(), tiff.len());
+            let at = find(tiff, b"Jane Doe\0").unwrap();
+            let changed:Vec<usize> = (0..out.len()).filter(|&i| out[i] != tiff[i]).collect();
+            assert!(!changed.is_empty());
+            assert!(changed.iter().all(|i| (at..at + 8).contains(i)));
+            assert_eq!(&out[at..at + 8], b"John Roe");
+        }
+    }
+
+    #[test]
+    fn maker_note_keeps_its_offset() {
+        for tiff in [BIG_ENDIAN, LITTLE_ENDIAN] {
+            let mut edit = ExifEdit::load(tiff).unwrap();
+            edit.set_image_description("a description long enough to push the IFDs past the MakerNote");
+            edit.set_gps(-33.8568, 151.2153, Some(5.0));
+            let out = edit.write();
+            assert_eq!(find(&out, b"MAKERNOTE"), find(tiff, b"MAKERNOTE"));
+
+            let reloaded = ExifEdit::load(&out).unwrap();
+            let maker_note = |edit: &ExifEdit| edit.get(ExifIfd::EXIF, MAKER_NOTE).map(|e| e.data.clone());
+            assert_eq!(maker_note(&reloaded), maker_note(&edit));
+            assert_eq!(reloaded.thumbnail, edit.thumbnail);
+            assert_eq!(reloaded.get(ExifIfd::IFD0, 0x010E).map(|e| e.data.as_slice()),
+                Some(&b"a description long enough to push the IFDs past the MakerNote\0"[..]));
+            assert_eq!(reloaded.get(ExifIfd::GPS, 0x0001).map(|e| e.data.as_slice()), Some(&b"S\0"[..]));
+        }
+    }
+
+    #[test]
+    fn new_stream_has_the_set_values() {
+        let mut edit = ExifEdit::new(false);
+        edit.set_copyright("(c) 2026");
+        edit.set_date_time_original("2026:10:19 12:00:00");
+        let out = edit.write();
+        assert!(out.starts_with(b"II*\0\x08\0\0\0"));
+        let exif = EXIF::load(&out).unwrap();
+        assert_eq!(exif.values.get("Copyright").map(|s| s.as_str()), Some("(c) 2026"));
+        assert_eq!(exif.values.get("DateTimeOriginal").map(|s| s.as_str()), Some("2026:10:19 12:00:00"));
+    }
+}
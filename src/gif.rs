@@ -0,0 +1,191 @@
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use crate::xmp::*;
+use crate::icc::*;
+use crate::logger;
+
+/// GIF image: the logical screen, the frames and the application and comment extensions.
+pub struct GIF {
+    /// "87a" or "89a"
+    pub version: String,
+    pub width: u16,
+    pub height: u16,
+    /// bits per pixel of the global color table, 0 without one
+    pub bit_depth: u8,
+    pub frames: u32,
+    /// NETSCAPE2.0 loop count, 0 loops forever
+    pub loop_count: Option<u16>,
+    /// sum of the frame delays, in 1/100 s
+    pub duration: u32,
+    pub xmp: Option<XMP>,
+    pub icc: Option<ICC>,
+    pub comments: Vec<String>,
+}
+
+const GLOBAL_COLOR_TABLE: u8 = 0x80;
+const LOCAL_COLOR_TABLE: u8 = 0x80;
+
+impl GIF {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<GIF> {
+        let mut head = [0; 13];
+        f.read_exact(&mut head).ok()?;
+        if &head[0..3] != b"GIF" || !(&head[3..6] == b"87a" || &head[3..6] == b"89a") {
+            logger::debug("not GIF");
+            return None;
+        }
+
+        let flags = head[10];
+        let mut gif = GIF {
+            version: String::from_utf8_lossy(&head[3..6]).to_string(),
+            width: u16::from_le_bytes([head[6], head[7]]),
+            height: u16::from_le_bytes([head[8], head[9]]),
+            bit_depth: if flags & GLOBAL_COLOR_TABLE != 0 { (flags & 7) + 1 } else { 0 },
+            frames: 0,
+            loop_count: None,
+            duration: 0,
+            xmp: None,
+            icc: None,
+            comments: Vec::new(),
+        };
+        if flags & GLOBAL_COLOR_TABLE != 0 {
+            f.seek(SeekFrom::Current(3 << ((flags & 7) + 1))).ok()?;
+        }
+
+        loop {
+            let mut introducer = [0];
+            if f.read_exact(&mut introducer).is_err() {
+                logger::debug("no trailer");
+                break;
+            }
+            let ret = match introducer[0] {
+                0x2C => gif.image(f),
+                0x21 => gif.extension(f),
+                0x3B => {
+                    break;
+                }
+                b => {
+                    logger::debug(format!("unknown block {:02X}", b).as_str());
+                    break;
+                }
+            };
+            if ret.is_none() {
+                logger::debug("short block");
+                break;
+            }
+        }
+
+        Some(gif)
+    }
+
+    // image descriptor, local color table, LZW minimum code size and the image data
+    fn image<R: Read + Seek>(&mut self, f: &mut R) -> Option<()> {
+        let mut descriptor = [0; 9];
+        f.read_exact(&mut descriptor).ok()?;
+        let flags = descriptor[8];
+        if flags & LOCAL_COLOR_TABLE != 0 {
+            f.seek(SeekFrom::Current(3 << ((flags & 7) + 1))).ok()?;
+        }
+        f.seek(SeekFrom::Current(1)).ok()?;
+        skip_sub_blocks(f)?;
+        self.frames += 1;
+        Some(())
+    }
+
+    fn extension<R: Read + Seek>(&mut self, f: &mut R) -> Option<()> {
+        let mut label = [0];
+        f.read_exact(&mut label).ok()?;
+        match label[0] {
+            0xF9 => {
+                // graphic control: flags, delay, transparent color index
+                let data = sub_blocks(f, false)?;
+                if data.len() >= 3 {
+                    self.duration += u16::from_le_bytes([data[1], data[2]]) as u32;
+                }
+            }
+            0xFE => {
+                let data = sub_blocks(f, false)?;
+                self.comments.push(String::from_utf8_lossy(&data).to_string());
+            }
+            0xFF => {
+                // application identifier and authentication code in the first sub-block
+                let mut len = [0];
+                f.read_exact(&mut len).ok()?;
+                let mut id = vec![0; len[0] as usize];
+                f.read_exact(&mut id).ok()?;
+                match id.as_slice() {
+                    b"NETSCAPE2.0" | b"ANIMEXTS1.0" => {
+                        let data = sub_blocks(f, false)?;
+                        if data.len() >= 3 && data[0] == 1 {
+                            self.loop_count = Some(u16::from_le_bytes([data[1], data[2]]));
+                        }
+                    }
+                    b"XMP DataXMP" => {
+                        // the packet is stored as it is, its bytes read as sub-block lengths,
+                        // and ends with a 257-byte "magic trailer"
+                        let data = sub_blocks(f, true)?;
+                        let end = data.iter().rposition(|&b| b == b'>').map_or(0, |i| i + 1);
+                        self.xmp = XMP::load(&data[..end]);
+                    }
+                    b"ICCRGBG1012" => {
+                        self.icc = ICC::load(&sub_blocks(f, false)?);
+                    }
+                    _ => {
+                        logger::debug(format!("application {}", String::from_utf8_lossy(&id)).as_str());
+                        skip_sub_blocks(f)?;
+                    }
+                }
+            }
+            _ => {
+                skip_sub_blocks(f)?;
+            }
+        }
+        Some(())
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("Version".to_string(), self.version.clone()),
+            ("ImageWidth".to_string(), self.width.to_string()),
+            ("ImageHeight".to_string(), self.height.to_string()),
+            ("BitDepth".to_string(), self.bit_depth.to_string()),
+            ("Frames".to_string(), self.frames.to_string()),
+        ];
+        if let Some(loop_count) = self.loop_count {
+            let loop_count = if loop_count == 0 { "Infinite".to_string() } else { loop_count.to_string() };
+            entries.push(("LoopCount".to_string(), loop_count));
+        }
+        if self.frames > 1 {
+            entries.push(("Duration".to_string(), format!("{:.2} s", self.duration as f64 / 100.0)));
+        }
+        entries
+    }
+}
+
+// data sub-blocks up to the terminator; raw keeps the length bytes
+fn sub_blocks<R: Read>(f: &mut R, raw: bool) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    loop {
+        let mut len = [0];
+        f.read_exact(&mut len).ok()?;
+        if len[0] == 0 {
+            return Some(data);
+        }
+        if raw {
+            data.push(len[0]);
+        }
+        let start = data.len();
+        data.resize(start + len[0] as usize, 0);
+        f.read_exact(&mut data[start..]).ok()?;
+    }
+}
+
+fn skip_sub_blocks<R: Read + Seek>(f: &mut R) -> Option<()> {
+    loop {
+        let mut len = [0];
+        f.read_exact(&mut len).ok()?;
+        if len[0] == 0 {
+            return Some(());
+        }
+        f.seek(SeekFrom::Current(len[0] as i64)).ok()?;
+    }
+}
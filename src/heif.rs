@@ -0,0 +1,390 @@
+use std::io::prelude::*;
+use crate::bmff::*;
+use crate::exif::*;
+use crate::xmp::*;
+use crate::icc::*;
+use crate::logger;
+use crate::bytes::*;
+
+/// HEIF image (HEIC, AVIF): the items of the 'meta' box and the properties of the primary image.
+pub struct HEIF {
+    pub file_type: FileType,
+    pub exif: Option<EXIF>,
+    pub xmp: Option<XMP>,
+    pub icc: Option<ICC>,
+    /// 'ispe' of the primary item
+    pub width: u32,
+    pub height: u32,
+    /// 'irot', anti-clockwise
+    pub rotation: u16,
+    /// 'imir': 0 vertical axis (left-right), 1 horizontal axis (top-bottom)
+    pub mirror: Option<u8>,
+}
+
+struct Item {
+    id: u32,
+    item_type: [u8; 4],
+    content_type: String,
+}
+
+struct Location {
+    id: u32,
+    construction_method: u16,
+    /// (offset, length) with the base offset added
+    extents: Vec<(u64, u64)>,
+}
+
+// HEIF and AVIF still image brands
+const BRANDS: [&str; 9] = ["mif1", "msf1", "heic", "heix", "heim", "heis", "hevc", "avif", "avis"];
+
+impl HEIF {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<HEIF> {
+        let mut src = SeekReader::new(f)?;
+        HEIF::load_from(&mut src)
+    }
+
+    pub fn load_data(data: &[u8]) -> Option<HEIF> {
+        HEIF::load_from(&mut &data[..])
+    }
+
+    fn load_from<S: ReadAt>(src: &mut S) -> Option<HEIF> {
+        let size = src.size();
+        let boxes = read_boxes(src, 0, size);
+        let file_type = FileType::load(&find(&boxes, b"ftyp")?.data(src)?)?;
+        if !BRANDS.iter().any(|b| file_type.is(b)) {
+            logger::debug("not HEIF");
+            return None;
+        }
+        let meta = find(&boxes, b"meta")?.full_children(src, 0);
+
+        let mut heif = HEIF {
+            file_type,
+            exif: None,
+            xmp: None,
+            icc: None,
+            width: 0,
+            height: 0,
+            rotation: 0,
+            mirror: None,
+        };
+
+        let primary = find(&meta, b"pitm").and_then(|b| b.data(src)).and_then(|d| primary_item(&d));
+        let items = match find(&meta, b"iinf") {
+            Some(iinf) => item_infos(src, iinf),
+            None => Vec::new(),
+        };
+        let locations = match find(&meta, b"iloc").and_then(|b| b.data(src)) {
+            Some(iloc) => item_locations(&iloc).unwrap_or_else(|| {
+                logger::debug("broken iloc");
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        let references = match find(&meta, b"iref") {
+            Some(iref) => item_references(src, iref, b"cdsc"),
+            None => Vec::new(),
+        };
+        let idat = find(&meta, b"idat").and_then(|b| b.data(src));
+
+        // metadata items describing the primary image, or else any
+        let describes_primary = |id: u32| references.iter().any(|(from, to)| *from == id && Some(*to) == primary);
+        let mut metadata:Vec<&Item> = items.iter().filter(|i| &i.item_type == b"Exif" || is_xmp(i)).collect();
+        metadata.sort_by_key(|i| !describes_primary(i.id));
+        for item in metadata {
+            let data = match locations.iter().find(|l| l.id == item.id).and_then(|l| item_data(src, l, &idat)) {
+                Some(data) => data,
+                None => {
+                    logger::debug(format!("no data for item {}", item.id).as_str());
+                    continue;
+                }
+            };
+            if &item.item_type == b"Exif" && heif.exif.is_none() && data.len() >= 4 {
+                // the TIFF header follows the 4-byte offset field and that many bytes, usually "Exif\0\0"
+                let start = (be32(&data, 0) as usize).saturating_add(4);
+                heif.exif = data.get(start..).and_then(EXIF::load);
+            } else if is_xmp(item) && heif.xmp.is_none() {
+                heif.xmp = XMP::load(&data);
+            }
+        }
+
+        if let (Some(primary), Some(iprp)) = (primary, find(&meta, b"iprp")) {
+            heif.load_properties(src, iprp, primary);
+        }
+
+        Some(heif)
+    }
+
+    // 'ipco' properties that 'ipma' associates with the item, in order
+    fn load_properties<S: ReadAt>(&mut self, src: &mut S, iprp: &BMFFBox, item: u32) {
+        let iprp = iprp.children(src);
+        let ipco = match find(&iprp, b"ipco") {
+            Some(ipco) => ipco.children(src),
+            None => {
+                return;
+            }
+        };
+        let ipma = match find(&iprp, b"ipma").and_then(|b| b.data(src)) {
+            Some(ipma) => ipma,
+            None => {
+                return;
+            }
+        };
+        for index in property_indices(&ipma, item).unwrap_or_default() {
+            let property = match ipco.get(index.wrapping_sub(1)) {
+                Some(property) => property,
+                None => {
+                    continue;
+                }
+            };
+            let data = match property.data(src) {
+                Some(data) => data,
+                None => {
+                    continue;
+                }
+            };
+            let mut r = BoxReader::new(&data);
+            match &property.box_type {
+                b"ispe" => {
+                    r.full_box();
+                    self.width = r.u32().unwrap_or(0);
+                    self.height = r.u32().unwrap_or(0);
+                }
+                b"irot" => {
+                    self.rotation = r.u8().map(|a| (a & 3) as u16 * 90).unwrap_or(0);
+                }
+                b"imir" => {
+                    self.mirror = r.u8().map(|a| a & 1);
+                }
+                b"colr" => {
+                    if matches!(r.bytes(4), Some(b"prof") | Some(b"rICC")) {
+                        self.icc = ICC::load(&data[4..]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("Brand".to_string(), self.file_type.major_brand.clone()),
+            ("ImageWidth".to_string(), self.width.to_string()),
+            ("ImageHeight".to_string(), self.height.to_string()),
+            ("Rotation".to_string(), self.rotation.to_string()),
+        ];
+        match self.mirror {
+            Some(0) => entries.push(("Mirror".to_string(), "Vertical axis".to_string())),
+            Some(_) => entries.push(("Mirror".to_string(), "Horizontal axis".to_string())),
+            None => {}
+        }
+        entries
+    }
+}
+
+fn is_xmp(item: &Item) -> bool {
+    &item.item_type == b"mime" && item.content_type == "application/rdf+xml"
+}
+
+fn primary_item(pitm: &[u8]) -> Option<u32> {
+    let mut r = BoxReader::new(pitm);
+    let (version, _) = r.full_box()?;
+    if version == 0 {
+        r.u16().map(|id| id as u32)
+    } else {
+        r.u32()
+    }
+}
+
+// 'infe' boxes of version 2 and 3, which carry the item type
+fn item_infos<S: ReadAt>(src: &mut S, iinf: &BMFFBox) -> Vec<Item> {
+    let version = src.read_at(iinf.offset, 1).map(|v| v[0]).unwrap_or(0);
+    let count_size = if version == 0 { 2 } else { 4 };
+    let mut items = Vec::new();
+    for infe in iinf.full_children(src, count_size) {
+        let data = match infe.data(src) {
+            Some(data) => data,
+            None => {
+                continue;
+            }
+        };
+        if let Some(item) = item_info(&data) {
+            items.push(item);
+        }
+    }
+    items
+}
+
+fn item_info(infe: &[u8]) -> Option<Item> {
+    let mut r = BoxReader::new(infe);
+    let (version, _) = r.full_box()?;
+    if version < 2 {
+        logger::debug("old infe version");
+        return None;
+    }
+    let id = if version == 2 { r.u16()? as u32 } else { r.u32()? };
+    r.u16()?;   // protection index
+    let item_type = r.bytes(4)?;
+    r.string()?;    // name
+    let content_type = if item_type == b"mime" { r.string()? } else { String::new() };
+    Some(Item {
+        id,
+        item_type: [item_type[0], item_type[1], item_type[2], item_type[3]],
+        content_type,
+    })
+}
+
+fn item_locations(iloc: &[u8]) -> Option<Vec<Location>> {
+    let mut locations = Vec::new();
+    let mut r = BoxReader::new(iloc);
+    let (version, _) = r.full_box()?;
+    let sizes = r.u16()?;
+    let offset_size = (sizes >> 12) as usize;
+    let length_size = (sizes >> 8 & 0xF) as usize;
+    let base_offset_size = (sizes >> 4 & 0xF) as usize;
+    let index_size = if version > 0 { (sizes & 0xF) as usize } else { 0 };
+    let count = if version < 2 { r.u16()? as u32 } else { r.u32()? };
+    for _ in 0..count {
+        let id = if version < 2 { r.u16()? as u32 } else { r.u32()? };
+        let construction_method = if version > 0 { r.u16()? & 0xF } else { 0 };
+        r.u16()?;   // data reference index
+        let base_offset = r.uint(base_offset_size)?;
+        let extent_count = r.u16()?;
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            r.uint(index_size)?;
+            let offset = r.uint(offset_size)?;
+            let length = r.uint(length_size)?;
+            extents.push((base_offset.saturating_add(offset), length));
+        }
+        locations.push(Location {
+            id,
+            construction_method,
+            extents,
+        });
+    }
+    Some(locations)
+}
+
+// (from, to) pairs of one reference type
+fn item_references<S: ReadAt>(src: &mut S, iref: &BMFFBox, reference_type: &[u8; 4]) -> Vec<(u32, u32)> {
+    let version = src.read_at(iref.offset, 1).map(|v| v[0]).unwrap_or(0);
+    let mut references = Vec::new();
+    for reference in iref.full_children(src, 0) {
+        if &reference.box_type != reference_type {
+            continue;
+        }
+        let data = match reference.data(src) {
+            Some(data) => data,
+            None => {
+                continue;
+            }
+        };
+        let mut r = BoxReader::new(&data);
+        let id = |r: &mut BoxReader| if version == 0 { r.u16().map(|id| id as u32) } else { r.u32() };
+        if let (Some(from), Some(count)) = (id(&mut r), r.u16()) {
+            for _ in 0..count {
+                match id(&mut r) {
+                    Some(to) => references.push((from, to)),
+                    None => break,
+                }
+            }
+        }
+    }
+    references
+}
+
+// 1-based 'ipco' indices associated with the item
+fn property_indices(ipma: &[u8], item: u32) -> Option<Vec<usize>> {
+    let mut r = BoxReader::new(ipma);
+    let (version, flags) = r.full_box()?;
+    let count = r.u32()?;
+    for _ in 0..count {
+        let id = if version < 1 { r.u16()? as u32 } else { r.u32()? };
+        let associations = r.u8()?;
+        let mut indices = Vec::new();
+        for _ in 0..associations {
+            // the top bit is the essential flag
+            let index = if flags & 1 != 0 { (r.u16()? & 0x7FFF) as usize } else { (r.u8()? & 0x7F) as usize };
+            indices.push(index);
+        }
+        if id == item {
+            return Some(indices);
+        }
+    }
+    None
+}
+
+// extents from the file (construction method 0) or from 'idat' (1)
+fn item_data<S: ReadAt>(src: &mut S, location: &Location, idat: &Option<Vec<u8>>) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    for &(offset, length) in &location.extents {
+        match location.construction_method {
+            0 => {
+                // length 0: up to the end of the file
+                let length = if length == 0 { src.size().checked_sub(offset)? } else { length };
+                data.extend(src.read_at(offset, usize::try_from(length).ok()?)?);
+            }
+            1 => {
+                let idat = idat.as_ref()?;
+                let start = usize::try_from(offset).ok()?;
+                let end = if length == 0 { idat.len() } else { start.checked_add(usize::try_from(length).ok()?)? };
+                data.extend_from_slice(idat.get(start..end)?);
+            }
+            _ => {
+                logger::debug("unsupported construction method");
+                return None;
+            }
+        }
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // version 1, 4-byte offsets and lengths, 2-byte base offset: item 1 in the file at
+    // 0x100 + 0x10 in two extents, item 2 in 'idat'
+    const ILOC: &[u8] = b"\x01\0\0\0\x44\x20\0\x02\
+        \0\x01\0\0\0\0\x01\x00\0\x02\0\0\0\x10\0\0\0\x04\0\0\0\x20\0\0\0\x02\
+        \0\x02\0\x01\0\0\0\0\0\x01\0\0\0\x02\0\0\0\x03";
+
+    // version 0 without the large index flag: item 1 has properties 1 and 2 (essential),
+    // item 2 has property 3
+    const IPMA: &[u8] = b"\0\0\0\0\0\0\0\x02\0\x01\x02\x01\x82\0\x02\x01\x03";
+
+    #[test]
+    fn locations_are_read() {
+        let locations = item_locations(ILOC).unwrap();
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[0].id, 1);
+        assert_eq!(locations[0].construction_method, 0);
+        assert_eq!(locations[0].extents, [(0x110, 4), (0x120, 2)]);
+        assert_eq!(locations[1].construction_method, 1);
+        assert_eq!(locations[1].extents, [(2, 3)]);
+
+        let file:Vec<u8> = (0..0x200).map(|i| i as u8).collect();
+        assert_eq!(item_data(&mut &file[..], &locations[0], &None).unwrap(), [0x10, 0x11, 0x12, 0x13, 0x20, 0x21]);
+        // the file ends before 0x110
+        assert!(item_data(&mut &file[..0x100], &locations[0], &None).is_none());
+        let idat = Some(b"abcdef".to_vec());
+        assert_eq!(item_data(&mut &file[..], &locations[1], &idat).unwrap(), b"cde");
+        assert!(item_data(&mut &file[..], &locations[1], &Some(b"abc".to_vec())).is_none());
+    }
+
+    #[test]
+    fn truncated_locations() {
+        for end in [4, 7, ILOC.len() - 1] {
+            assert!(item_locations(&ILOC[..end]).is_none(), "{}", end);
+        }
+    }
+
+    #[test]
+    fn property_associations() {
+        assert_eq!(property_indices(IPMA, 1).unwrap(), [1, 2]);
+        assert_eq!(property_indices(IPMA, 2).unwrap(), [3]);
+        assert!(property_indices(IPMA, 3).is_none());
+        assert!(property_indices(&IPMA[..IPMA.len() - 1], 2).is_none());
+        assert!(property_indices(&IPMA[..6], 1).is_none());
+    }
+}
@@ -0,0 +1,343 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use crate::logger;
+use crate::bytes::*;
+
+/// ICC profile with its header and a few tags decoded.
+pub struct ICC {
+    /// the whole profile as embedded
+    pub data: Vec<u8>,
+    pub cmm: String,
+    pub version: String,
+    pub device_class: String,
+    pub color_space: String,
+    pub pcs: String,
+    pub created: String,
+    pub rendering_intent: u32,
+    pub description: Option<String>,
+    pub copyright: Option<String>,
+    pub media_white_point: Option<[f64; 3]>,
+    red_colorant: Option<[f64; 3]>,
+}
+
+pub enum ColorProfile {
+    SRGB,
+    DISPLAY_P3,
+    ADOBE_RGB,
+    PROPHOTO_RGB,
+    OTHER,
+}
+
+/// Chunks of a profile split over JPEG APP2 "ICC_PROFILE" segments.
+pub(crate) struct ICCChunks {
+    chunks: Vec<(u8, u8, Vec<u8>)>,
+}
+
+impl ICC {
+    pub fn load(data: &[u8]) -> Option<ICC> {
+        if data.len() < 132 || &data[36..40] != b"acsp" {
+            logger::debug("not ICC profile");
+            return None;
+        }
+
+        let mut icc = ICC {
+            data: data.to_vec(),
+            cmm: signature(data, 4),
+            version: format!("{}.{}.{}", data[8], data[9] >> 4, data[9] & 0x0F),
+            device_class: signature(data, 12),
+            color_space: signature(data, 16),
+            pcs: signature(data, 20),
+            created: format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+                be16(data, 24), be16(data, 26), be16(data, 28), be16(data, 30), be16(data, 32), be16(data, 34)),
+            rendering_intent: be32(data, 64),
+            description: None,
+            copyright: None,
+            media_white_point: None,
+            red_colorant: None,
+        };
+
+        let tag_count = be32(data, 128) as usize;
+        for i in 0..tag_count {
+            let entry = 132 + i * 12;
+            if entry + 12 > data.len() {
+                logger::debug("short ICC tag table");
+                break;
+            }
+            let offset = be32(data, entry+4) as usize;
+            let size = be32(data, entry+8) as usize;
+            let tag = match data.get(offset..offset.saturating_add(size)) {
+                Some(tag) => tag,
+                None => {
+                    logger::debug("invalid ICC tag offset");
+                    continue;
+                }
+            };
+            match &data[entry..entry+4] {
+                b"desc" => {
+                    icc.description = text_tag(tag);
+                }
+                b"cprt" => {
+                    icc.copyright = text_tag(tag);
+                }
+                b"wtpt" => {
+                    icc.media_white_point = xyz_tag(tag);
+                }
+                b"rXYZ" => {
+                    icc.red_colorant = xyz_tag(tag);
+                }
+                _ => {}
+            }
+        }
+
+        Some(icc)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut f = File::create(path)?;
+        f.write_all(&self.data)
+    }
+
+    pub fn rendering_intent_name(&self) -> &'static str {
+        match self.rendering_intent {
+            0 => "Perceptual",
+            1 => "Media-Relative Colorimetric",
+            2 => "Saturation",
+            3 => "ICC-Absolute Colorimetric",
+            _ => "Unknown",
+        }
+    }
+
+    /// Well known RGB working spaces, from the description or else from the red primary.
+    pub fn color_profile(&self) -> ColorProfile {
+        if let Some(description) = &self.description {
+            let description = description.to_ascii_lowercase();
+            if description.contains("srgb") {
+                return ColorProfile::SRGB;
+            }
+            if description.contains("display p3") {
+                return ColorProfile::DISPLAY_P3;
+            }
+            if description.contains("adobe rgb") {
+                return ColorProfile::ADOBE_RGB;
+            }
+            if description.contains("prophoto") {
+                return ColorProfile::PROPHOTO_RGB;
+            }
+        }
+
+        // D50 adapted red colorant of each space
+        let known = [
+            ([0.4361, 0.2225, 0.0139], ColorProfile::SRGB),
+            ([0.5151, 0.2412, -0.0011], ColorProfile::DISPLAY_P3),
+            ([0.6097, 0.3111, 0.0195], ColorProfile::ADOBE_RGB),
+            ([0.7977, 0.2880, 0.0000], ColorProfile::PROPHOTO_RGB),
+        ];
+        if let Some(red) = self.red_colorant {
+            for (xyz, profile) in known {
+                if (0..3).all(|i| (red[i] - xyz[i]).abs() < 0.005) {
+                    return profile;
+                }
+            }
+        }
+        ColorProfile::OTHER
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("ProfileCMMType".to_string(), self.cmm.clone()),
+            ("ProfileVersion".to_string(), self.version.clone()),
+            ("ProfileClass".to_string(), self.device_class.clone()),
+            ("ColorSpaceData".to_string(), self.color_space.clone()),
+            ("ProfileConnectionSpace".to_string(), self.pcs.clone()),
+            ("ProfileDateTime".to_string(), self.created.clone()),
+            ("RenderingIntent".to_string(), self.rendering_intent_name().to_string()),
+        ];
+        if let Some(description) = &self.description {
+            entries.push(("ProfileDescription".to_string(), description.clone()));
+        }
+        if let Some(copyright) = &self.copyright {
+            entries.push(("ProfileCopyright".to_string(), copyright.clone()));
+        }
+        if let Some(xyz) = self.media_white_point {
+            entries.push(("MediaWhitePoint".to_string(), format!("{:.5} {:.5} {:.5}", xyz[0], xyz[1], xyz[2])));
+        }
+        entries.push(("ColorProfile".to_string(), self.color_profile().to_string()));
+        entries
+    }
+}
+
+impl std::fmt::Display for ColorProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ColorProfile::SRGB => write!(f, "sRGB"),
+            ColorProfile::DISPLAY_P3 => write!(f, "Display P3"),
+            ColorProfile::ADOBE_RGB => write!(f, "Adobe RGB"),
+            ColorProfile::PROPHOTO_RGB => write!(f, "ProPhoto RGB"),
+            ColorProfile::OTHER => write!(f, "Other"),
+        }
+    }
+}
+
+impl ICCChunks {
+    pub(crate) fn new() -> ICCChunks {
+        ICCChunks {
+            chunks: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// payload: sequence number (from 1), chunk count, data
+    pub(crate) fn add(&mut self, payload: &[u8]) {
+        if payload.len() < 2 {
+            logger::debug("short ICC chunk");
+            return;
+        }
+        self.chunks.push((payload[0], payload[1], payload[2..].to_vec()));
+    }
+
+    pub(crate) fn assemble(&mut self) -> Option<Vec<u8>> {
+        self.chunks.sort_by_key(|(seq, _, _)| *seq);
+        let count = self.chunks.first()?.1 as usize;
+        if self.chunks.len() != count {
+            logger::debug("ICC chunks missing");
+            return None;
+        }
+        let mut profile = Vec::new();
+        for (i, (seq, cnt, data)) in self.chunks.iter().enumerate() {
+            if *seq as usize != i + 1 || *cnt as usize != count {
+                logger::debug("invalid ICC chunk sequence");
+                return None;
+            }
+            profile.extend_from_slice(data);
+        }
+        Some(profile)
+    }
+}
+
+// 'desc' (v2 textDescriptionType), 'text' or 'mluc' (v4)
+fn text_tag(tag: &[u8]) -> Option<String> {
+    if tag.len() < 12 {
+        return None;
+    }
+    match &tag[0..4] {
+        b"desc" => {
+            let len = be32(tag, 8) as usize;
+            tag.get(12..12+len).map(ascii)
+        }
+        b"text" => {
+            Some(ascii(&tag[8..]))
+        }
+        b"mluc" => {
+            // first record, UTF-16BE
+            if be32(tag, 8) == 0 || tag.len() < 28 {
+                return None;
+            }
+            let len = be32(tag, 20) as usize;
+            let offset = be32(tag, 24) as usize;
+            let units:Vec<u16> = tag.get(offset..offset+len)?.chunks_exact(2).map(|c| be16(c, 0)).collect();
+            Some(String::from_utf16_lossy(&units).trim_end_matches('\0').to_string())
+        }
+        _ => None,
+    }
+}
+
+fn xyz_tag(tag: &[u8]) -> Option<[f64; 3]> {
+    if tag.len() < 20 || &tag[0..4] != b"XYZ " {
+        return None;
+    }
+    let s15f16 = |offset| be32(tag, offset) as i32 as f64 / 65536.0;
+    Some([s15f16(8), s15f16(12), s15f16(16)])
+}
+
+fn ascii(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+fn signature(data: &[u8], offset: usize) -> String {
+    ascii(&data[offset..offset+4]).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // header, then a 'desc' mluc tag and the sRGB red colorant
+    fn profile() -> Vec<u8> {
+        let mut data = vec![0; 128];
+        data[4..8].copy_from_slice(b"lcms");
+        data[8] = 4;
+        data[9] = 0x30;
+        data[12..16].copy_from_slice(b"mntr");
+        data[16..20].copy_from_slice(b"RGB ");
+        data[20..24].copy_from_slice(b"XYZ ");
+        data[24..36].copy_from_slice(&[0x07, 0xE8, 0, 1, 0, 2, 0, 3, 0, 4, 0, 5]);
+        data[36..40].copy_from_slice(b"acsp");
+        data.extend_from_slice(&2u32.to_be_bytes());
+
+        let mut desc = b"mluc\0\0\0\0".to_vec();
+        desc.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 12]);
+        desc.extend_from_slice(b"enUS");
+        desc.extend_from_slice(&8u32.to_be_bytes());
+        desc.extend_from_slice(&28u32.to_be_bytes());
+        desc.extend_from_slice(&[0, b's', 0, b'R', 0, b'G', 0, b'B']);
+        let mut red = b"XYZ \0\0\0\0".to_vec();
+        for v in [0x6FA2u32, 0x38F5, 0x0390] {
+            red.extend_from_slice(&v.to_be_bytes());
+        }
+
+        let tags_start = 132 + 2 * 12;
+        data.extend_from_slice(b"desc");
+        data.extend_from_slice(&(tags_start as u32).to_be_bytes());
+        data.extend_from_slice(&(desc.len() as u32).to_be_bytes());
+        data.extend_from_slice(b"rXYZ");
+        data.extend_from_slice(&((tags_start + desc.len()) as u32).to_be_bytes());
+        data.extend_from_slice(&(red.len() as u32).to_be_bytes());
+        data.extend_from_slice(&desc);
+        data.extend_from_slice(&red);
+        data
+    }
+
+    #[test]
+    fn header_and_tags_are_decoded() {
+        let icc = ICC::load(&profile()).unwrap();
+        assert_eq!(icc.cmm, "lcms");
+        assert_eq!(icc.version, "4.3.0");
+        assert_eq!(icc.color_space, "RGB");
+        assert_eq!(icc.created, "2024:01:02 03:04:05");
+        assert_eq!(icc.description.as_deref(), Some("sRGB"));
+        assert!(matches!(icc.color_profile(), ColorProfile::SRGB));
+    }
+
+    #[test]
+    fn truncated_profile() {
+        let data = profile();
+        assert!(ICC::load(&data[..131]).is_none());
+        // the tags point past the end, the header is kept
+        let icc = ICC::load(&data[..140]).unwrap();
+        assert_eq!(icc.cmm, "lcms");
+        assert!(icc.description.is_none());
+        assert!(matches!(icc.color_profile(), ColorProfile::OTHER));
+    }
+
+    #[test]
+    fn chunks_are_assembled_in_order() {
+        let data = profile();
+        let mut chunks = ICCChunks::new();
+        for (seq, part) in [(2u8, &data[100..]), (1, &data[..100])] {
+            let mut payload = vec![seq, 2];
+            payload.extend_from_slice(part);
+            chunks.add(&payload);
+        }
+        assert_eq!(chunks.assemble().unwrap(), data);
+
+        let mut chunks = ICCChunks::new();
+        chunks.add(&[1, 2, 0, 0]);
+        assert!(chunks.assemble().is_none());
+    }
+}
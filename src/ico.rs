@@ -0,0 +1,101 @@
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use crate::logger;
+use crate::bytes::*;
+
+/// Windows icon (ICO) or cursor (CUR): the directory of the images.
+pub struct ICO {
+    pub cursor: bool,
+    pub images: Vec<IconImage>,
+}
+
+pub struct IconImage {
+    /// 0 in the directory means 256; PNG images report their own size
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u16,
+    /// CUR hotspot, taking the place of planes and bit count
+    pub hotspot: Option<(u16, u16)>,
+    pub png: bool,
+    pub offset: u32,
+    pub size: u32,
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+impl ICO {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<ICO> {
+        let mut head = [0; 6];
+        f.read_exact(&mut head).ok()?;
+        let image_type = le16(&head, 2);
+        let count = le16(&head, 4);
+        if le16(&head, 0) != 0 || !(image_type == 1 || image_type == 2) || count == 0 {
+            logger::debug("not ICO");
+            return None;
+        }
+
+        let mut ico = ICO {
+            cursor: image_type == 2,
+            images: Vec::new(),
+        };
+        let mut directory = vec![0; count as usize * 16];
+        f.read_exact(&mut directory).ok()?;
+        for entry in directory.chunks_exact(16) {
+            let size_of = |b: u8| if b == 0 { 256 } else { b as u32 };
+            let mut image = IconImage {
+                width: size_of(entry[0]),
+                height: size_of(entry[1]),
+                bit_depth: if ico.cursor { 0 } else { le16(entry, 6) },
+                hotspot: if ico.cursor { Some((le16(entry, 4), le16(entry, 6))) } else { None },
+                png: false,
+                offset: le32(entry, 12),
+                size: le32(entry, 8),
+            };
+
+            // PNG signature and IHDR, or a BITMAPINFOHEADER
+            let mut head = [0; 26];
+            if f.seek(SeekFrom::Start(image.offset as u64)).is_err() || f.read_exact(&mut head).is_err() {
+                logger::debug("short icon image");
+                ico.images.push(image);
+                continue;
+            }
+            if head.starts_with(PNG_SIGNATURE) {
+                image.png = true;
+                image.width = be32(&head, 16);
+                image.height = be32(&head, 20);
+                image.bit_depth = head[24] as u16 * png_channels(head[25]);
+            } else if le32(&head, 0) >= 40 {
+                image.bit_depth = le16(&head, 14);
+            }
+            ico.images.push(image);
+        }
+
+        Some(ico)
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("Type".to_string(), if self.cursor { "Cursor" } else { "Icon" }.to_string()),
+            ("Images".to_string(), self.images.len().to_string()),
+        ];
+        for (i, image) in self.images.iter().enumerate() {
+            let mut summary = format!("{}x{} {}-bit {}, {} bytes",
+                image.width, image.height, image.bit_depth, if image.png { "PNG" } else { "BMP" }, image.size);
+            if let Some((x, y)) = image.hotspot {
+                summary.push_str(&format!(", hotspot {},{}", x, y));
+            }
+            entries.push((format!("Image{}", i + 1), summary));
+        }
+        entries
+    }
+}
+
+// samples per pixel of a PNG color type
+fn png_channels(color_type: u8) -> u16 {
+    match color_type {
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => 1,
+    }
+}
@@ -0,0 +1,293 @@
+// DEFLATE (RFC 1951) decoder for the zlib streams (RFC 1950) of PNG text and profile chunks.
+use crate::logger;
+
+// metadata never gets near this, compressed bombs do
+const MAX_OUTPUT: usize = 1 << 26;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// order of the code length code lengths in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+// canonical Huffman code: number of codes of each length and the symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+/// Decompresses a zlib stream, checking its Adler-32.
+pub(crate) fn zlib_decompress(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 6 || data[0] & 0x0F != 8 || !(data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31) {
+        logger::debug("not zlib");
+        return None;
+    }
+    if data[1] & 0x20 != 0 {
+        logger::debug("zlib preset dictionary");
+        return None;
+    }
+    let (out, used) = inflate(&data[2..])?;
+    let checksum = data.get(2+used..2+used+4)?;
+    if u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]) != adler32(&out) {
+        logger::debug("zlib checksum mismatch");
+        return None;
+    }
+    Some(out)
+}
+
+/// Raw DEFLATE data; returns the output and the number of input bytes used.
+pub(crate) fn inflate(data: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut bits = Bits {
+        data,
+        pos: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bits(1)?;
+        match bits.bits(2)? {
+            0 => {
+                // stored: byte aligned LEN, NLEN and the bytes
+                bits.buffer = 0;
+                bits.count = 0;
+                let head = data.get(bits.pos..bits.pos+4)?;
+                let len = u16::from_le_bytes([head[0], head[1]]);
+                if len != !u16::from_le_bytes([head[2], head[3]]) {
+                    logger::debug("broken stored block");
+                    return None;
+                }
+                let start = bits.pos + 4;
+                out.extend_from_slice(data.get(start..start+len as usize)?);
+                bits.pos = start + len as usize;
+            }
+            1 => {
+                let mut lengths = [0u8; 288 + 30];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..288].fill(8);
+                lengths[288..].fill(5);
+                codes(&mut bits, &mut out, &Huffman::new(&lengths[..288]), &Huffman::new(&lengths[288..]))?;
+            }
+            2 => {
+                let (literal, distance) = dynamic_tables(&mut bits)?;
+                codes(&mut bits, &mut out, &literal, &distance)?;
+            }
+            _ => {
+                logger::debug("invalid block type");
+                return None;
+            }
+        }
+        if out.len() > MAX_OUTPUT {
+            logger::debug("inflated data too large");
+            return None;
+        }
+        if last == 1 {
+            return Some((out, bits.pos));
+        }
+    }
+}
+
+impl<'a> Bits<'a> {
+    fn bits(&mut self, n: u32) -> Option<u32> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let v = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Some(v)
+    }
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman {
+            counts,
+            symbols,
+        }
+    }
+
+    // one bit at a time, codes are stored most significant bit first
+    fn decode(&self, bits: &mut Bits) -> Option<u16> {
+        let mut code:i32 = 0;
+        let mut first:i32 = 0;
+        let mut index:i32 = 0;
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        logger::debug("invalid Huffman code");
+        None
+    }
+}
+
+fn dynamic_tables(bits: &mut Bits) -> Option<(Huffman, Huffman)> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        logger::debug("invalid dynamic block");
+        return None;
+    }
+
+    let mut lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(code_lengths) {
+        lengths[i] = bits.bits(3)? as u8;
+    }
+    let length_code = Huffman::new(&lengths);
+
+    let mut lengths = vec![0u8; literals + distances];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = length_code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.get(i.checked_sub(1)?)?, 3 + bits.bits(2)? as usize),
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            logger::debug("too many code lengths");
+            return None;
+        }
+        lengths[i..i+repeat].fill(value);
+        i += repeat;
+    }
+    Some((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, literal: &Huffman, distance: &Huffman) -> Option<()> {
+    loop {
+        let symbol = literal.decode(bits)? as usize;
+        match symbol {
+            0..=255 => {
+                out.push(symbol as u8);
+            }
+            256 => {
+                return Some(());
+            }
+            _ => {
+                let i = symbol - 257;
+                let len = *LENGTH_BASE.get(i)? as usize + bits.bits(*LENGTH_EXTRA.get(i)? as u32)? as usize;
+                let d = distance.decode(bits)? as usize;
+                let dist = *DISTANCE_BASE.get(d)? as usize + bits.bits(*DISTANCE_EXTRA.get(d)? as u32)? as usize;
+                if dist > out.len() || out.len() > MAX_OUTPUT {
+                    logger::debug("invalid distance");
+                    return None;
+                }
+                // byte by byte, the copy may overlap its own output
+                let start = out.len() - dist;
+                for k in 0..len {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // "line 0: the quick brown fox\n" up to "line 39: ...", at level 9
+    const DYNAMIC: &str = "78da7dd3cd0d82001044e1bb556c09ce8ce24f39180c4402d140a47c6301bcf33bed97d97198ba3ade6be9bb7aafc3e355ed67fe4ef59cb7c3f86f8266688176827686d640bb40bb42bbd1ed084332221a918d0847a423e211f9888044422621e37648c8246412320999844c4226219350482824147c2f120a098584424221a1905076847e62c984f8";
+
+    #[test]
+    fn stored_block() {
+        let stream = b"x\x01\x01\x0c\x00\xf3\xffstored block\x1f\x80\x04\xbd";
+        assert_eq!(zlib_decompress(stream).unwrap(), b"stored block");
+    }
+
+    #[test]
+    fn fixed_huffman_block() {
+        let stream = b"x\xda\xcbH\xcd\xc9\xc9W\xc8@\x90\x00:.\x06}";
+        assert_eq!(zlib_decompress(stream).unwrap(), b"hello hello hello");
+    }
+
+    #[test]
+    fn dynamic_huffman_block() {
+        let stream = unhex(DYNAMIC);
+        assert_eq!((stream[2] >> 1) & 3, 2);
+        let expected:String = (0..40).map(|i| format!("line {}: the quick brown fox\n", i)).collect();
+        assert_eq!(zlib_decompress(&stream).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn broken_streams() {
+        let mut stream = unhex(DYNAMIC);
+        assert!(zlib_decompress(&stream[..stream.len() - 10]).is_none());
+        // Adler-32 mismatch
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        assert!(zlib_decompress(&stream).is_none());
+        // stored length and its complement disagree
+        assert!(zlib_decompress(b"x\x01\x01\x0c\x00\xf4\xffstored block\x1f\x80\x04\xbd").is_none());
+    }
+
+    #[test]
+    fn adler32_known_answer() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+}
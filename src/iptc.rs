@@ -0,0 +1,252 @@
+use crate::logger;
+use crate::bytes::*;
+
+/// IPTC-IIM datasets of the envelope (1) and application (2) records.
+pub struct Iptc {
+    /// decoded values in file order; repeatable datasets such as
+    /// Keywords appear once per occurrence
+    pub values: Vec<(String, String)>,
+    pub datasets: Vec<IptcDataset>,
+}
+
+pub struct IptcDataset {
+    pub record: u8,
+    pub number: u8,
+    pub data: Vec<u8>,
+}
+
+enum CHARSET {
+    LATIN1, UTF8
+}
+
+impl Iptc {
+    pub fn load(data: &[u8]) -> Option<Iptc> {
+        let mut iptc = Iptc {
+            values: Vec::new(),
+            datasets: Vec::new(),
+        };
+
+        let mut offset:usize = 0;
+        while offset + 5 <= data.len() {
+            if data[offset] != 0x1C {
+                // padding at the end of the block
+                break;
+            }
+            let record = data[offset+1];
+            let number = data[offset+2];
+            let mut len = be16(data, offset+3) as usize;
+            offset += 5;
+
+            // extended dataset: the low bits give the size of the length field
+            if len & 0x8000 != 0 {
+                let size = len & 0x7FFF;
+                if size > 4 {
+                    logger::debug("IPTC dataset length field too long");
+                    break;
+                }
+                let field = match data.get(offset..offset+size) {
+                    Some(field) => field,
+                    None => {
+                        logger::debug("short IPTC dataset length field");
+                        break;
+                    }
+                };
+                len = field.iter().fold(0usize, |n, &b| n << 8 | b as usize);
+                offset += size;
+            }
+
+            let value = match offset.checked_add(len).and_then(|end| data.get(offset..end)) {
+                Some(value) => value,
+                None => {
+                    logger::debug("short IPTC dataset");
+                    break;
+                }
+            };
+            offset += len;
+
+            iptc.datasets.push(IptcDataset {
+                record,
+                number,
+                data: value.to_vec(),
+            });
+        }
+
+        if iptc.datasets.is_empty() {
+            return None;
+        }
+
+        // 1:90 CodedCharacterSet, "ESC % G" is UTF-8
+        let mut charset = CHARSET::LATIN1;
+        for dataset in &iptc.datasets {
+            if dataset.record == 1 && dataset.number == 90 && dataset.data == b"\x1B%G" {
+                charset = CHARSET::UTF8;
+            }
+        }
+
+        for dataset in &iptc.datasets {
+            let name = match dataset_name(dataset.record, dataset.number) {
+                Some(name) => name,
+                None => {
+                    logger::debug(format!("unknown IPTC dataset {}:{}", dataset.record, dataset.number).as_str());
+                    continue;
+                }
+            };
+            let value = match (dataset.record, dataset.number) {
+                (1, 0) | (1, 20) | (1, 22) | (1, 120) | (1, 122) | (2, 0) => {
+                    if dataset.data.len() == 2 {
+                        be16(&dataset.data, 0).to_string()
+                    } else {
+                        hex(&dataset.data)
+                    }
+                }
+                (1, 90) => {
+                    match charset {
+                        CHARSET::UTF8 => "UTF8".to_string(),
+                        CHARSET::LATIN1 => hex(&dataset.data),
+                    }
+                }
+                _ => decode_string(&dataset.data, &charset),
+            };
+            iptc.values.push((name.to_string(), value));
+        }
+
+        Some(iptc)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.values.iter().filter(|(n, _)| n == name).map(|(_, v)| v.as_str()).collect()
+    }
+}
+
+fn decode_string(data: &[u8], charset: &CHARSET) -> String {
+    let mut end = data.len();
+    while end > 0 && data[end-1] == 0 {
+        end -= 1;
+    }
+    let data = &data[..end];
+    match charset {
+        CHARSET::UTF8 => String::from_utf8_lossy(data).to_string(),
+        // no declared charset: UTF-8 is common in practice, fall back to Latin-1
+        CHARSET::LATIN1 => match std::str::from_utf8(data) {
+            Ok(s) => s.to_string(),
+            Err(_) => data.iter().map(|&b| b as char).collect(),
+        },
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn dataset_name(record: u8, number: u8) -> Option<&'static str> {
+    let name = match (record, number) {
+        (1, 0) => "ModelVersion",
+        (1, 5) => "Destination",
+        (1, 20) => "FileFormat",
+        (1, 22) => "FileVersion",
+        (1, 30) => "ServiceIdentifier",
+        (1, 40) => "EnvelopeNumber",
+        (1, 50) => "ProductID",
+        (1, 60) => "EnvelopePriority",
+        (1, 70) => "DateSent",
+        (1, 80) => "TimeSent",
+        (1, 90) => "CodedCharacterSet",
+        (1, 100) => "UniqueObjectName",
+        (1, 120) => "ARMIdentifier",
+        (1, 122) => "ARMVersion",
+        (2, 0) => "ApplicationRecordVersion",
+        (2, 3) => "ObjectTypeReference",
+        (2, 4) => "ObjectAttributeReference",
+        (2, 5) => "ObjectName",
+        (2, 7) => "EditStatus",
+        (2, 10) => "Urgency",
+        (2, 12) => "SubjectReference",
+        (2, 15) => "Category",
+        (2, 20) => "SupplementalCategories",
+        (2, 22) => "FixtureIdentifier",
+        (2, 25) => "Keywords",
+        (2, 26) => "ContentLocationCode",
+        (2, 27) => "ContentLocationName",
+        (2, 30) => "ReleaseDate",
+        (2, 35) => "ReleaseTime",
+        (2, 37) => "ExpirationDate",
+        (2, 38) => "ExpirationTime",
+        (2, 40) => "SpecialInstructions",
+        (2, 42) => "ActionAdvised",
+        (2, 45) => "ReferenceService",
+        (2, 47) => "ReferenceDate",
+        (2, 50) => "ReferenceNumber",
+        (2, 55) => "DateCreated",
+        (2, 60) => "TimeCreated",
+        (2, 62) => "DigitalCreationDate",
+        (2, 63) => "DigitalCreationTime",
+        (2, 65) => "OriginatingProgram",
+        (2, 70) => "ProgramVersion",
+        (2, 75) => "ObjectCycle",
+        (2, 80) => "By-line",
+        (2, 85) => "By-lineTitle",
+        (2, 90) => "City",
+        (2, 92) => "Sub-location",
+        (2, 95) => "Province-State",
+        (2, 100) => "Country-PrimaryLocationCode",
+        (2, 101) => "Country-PrimaryLocationName",
+        (2, 103) => "OriginalTransmissionReference",
+        (2, 105) => "Headline",
+        (2, 110) => "Credit",
+        (2, 115) => "Source",
+        (2, 116) => "CopyrightNotice",
+        (2, 118) => "Contact",
+        (2, 120) => "Caption-Abstract",
+        (2, 121) => "LocalCaption",
+        (2, 122) => "Writer-Editor",
+        (2, 130) => "ImageType",
+        (2, 131) => "ImageOrientation",
+        (2, 135) => "LanguageIdentifier",
+        _ => {
+            return None;
+        }
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1:90 CodedCharacterSet UTF-8, 2:80 By-line, two 2:25 Keywords
+    const BLOCK: &[u8] = b"\x1C\x01\x5A\x00\x03\x1B%G\x1C\x02\x50\x00\x04B\xC3\xB6b\x1C\x02\x19\x00\x03sea\x1C\x02\x19\x00\x03sky";
+
+    #[test]
+    fn datasets_are_decoded() {
+        let iptc = Iptc::load(BLOCK).unwrap();
+        assert_eq!(iptc.datasets.len(), 4);
+        assert_eq!(iptc.get("By-line"), Some("B\u{F6}b"));
+        assert_eq!(iptc.get_all("Keywords"), ["sea", "sky"]);
+    }
+
+    #[test]
+    fn extended_length() {
+        let mut block = b"\x1C\x02\x78\x80\x02\x00\x05hello".to_vec();
+        assert_eq!(Iptc::load(&block).unwrap().datasets[0].data, b"hello");
+        // a length field longer than 4 bytes
+        block[4] = 0x09;
+        assert!(Iptc::load(&block).is_none());
+    }
+
+    #[test]
+    fn truncated_block_keeps_the_datasets_before() {
+        for end in [BLOCK.len() - 1, BLOCK.len() - 6] {
+            let iptc = Iptc::load(&BLOCK[..end]).unwrap();
+            assert_eq!(iptc.datasets.len(), 3);
+            assert_eq!(iptc.get_all("Keywords"), ["sea"]);
+        }
+        // a truncated extended length field
+        let mut block = BLOCK.to_vec();
+        block.extend_from_slice(b"\x1C\x02\x78\x80\x04\x00");
+        assert_eq!(Iptc::load(&block).unwrap().datasets.len(), 4);
+    }
+}
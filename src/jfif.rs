@@ -1,8 +1,17 @@
+use std::fmt;
+use std::fs::File;
 use std::io;
 use std::io::prelude::*;
-use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
 use crate::exif::*;
+use crate::xmp::*;
+use crate::iptc::*;
+use crate::photoshop::*;
+use crate::icc::*;
+use crate::mpf::*;
 use crate::logger;
+use crate::bytes::*;
 
 pub struct JFIF {
 	pub version: u16,
@@ -12,8 +21,107 @@ pub struct JFIF {
 	pub thumnail_width: u8,
 	pub thumnail_height: u8,
 	pub exif: Option<EXIF>,
+	pub xmp: Option<XMP>,
+	pub iptc: Option<Iptc>,
+	pub icc: Option<ICC>,
+	pub mpf: Option<MPF>,
+	pub adobe: Option<Adobe>,
+	/// first frame header (SOFn)
+	pub frame: Option<Frame>,
+	/// APP13 "Photoshop 3.0" image resource blocks
+	pub image_resources: Vec<ImageResource>,
+	pub comments: Vec<String>,
+	pub thumbnails: Vec<Thumbnail>,
+	segments: Vec<Segment>,
+	extended_xmp: ExtendedXMP,
+	photoshop: Vec<u8>,
+	icc_chunks: ICCChunks,
+}
+
+/// Thumbnail carried by the JFIF APP0 segment, a JFXX extension segment or a Photoshop resource.
+pub struct Thumbnail {
+	pub format: ThumbnailFormat,
+	pub width: u16,
+	pub height: u16,
+	/// JPEG stream, palette indexes or RGB triplets depending on the format
+	pub data: Vec<u8>,
+	/// 256 RGB entries, only for ThumbnailFormat::PALETTE
+	pub palette: Vec<u8>,
+}
+
+/// APP14 "Adobe" segment.
+pub struct Adobe {
+	pub dct_encode_version: u16,
+	pub flags0: u16,
+	pub flags1: u16,
+	/// 0: RGB or CMYK, 1: YCbCr, 2: YCCK
+	pub color_transform: u8,
+}
+
+pub struct Frame {
+	/// n of SOFn
+	pub process: u8,
+	pub precision: u8,
+	pub height: u16,
+	pub width: u16,
+	pub components: Vec<Component>,
+}
+
+pub struct Component {
+	pub id: u8,
+	pub h: u8,
+	pub v: u8,
+	pub tq: u8,
 }
 
+pub enum ColorModel {
+	GRAYSCALE,
+	YCbCr,
+	RGB,
+	CMYK,
+	YCCK,
+	UNKNOWN,
+}
+
+pub enum ThumbnailFormat {
+	JPEG,
+	PALETTE,
+	RGB,
+}
+
+/// A marker found while walking the file, in file order.
+pub struct Segment {
+	pub marker: u8,
+	/// file offset of the 0xFF byte of the marker
+	pub offset: u64,
+	/// value of the length field (0 for standalone markers)
+	pub length: u16,
+	pub segment_type: SegmentType,
+}
+
+pub enum SegmentType {
+	SOI,
+	SOF(u8),
+	DHT,
+	DAC,
+	DQT,
+	DNL,
+	DRI,
+	SOS,
+	TEM,
+	APP(u8, String),	// APPn and its signature (e.g. "Exif", "ICC_PROFILE")
+	COM,
+	EOI,
+	UNKNOWN,
+}
+
+const EXIF_SIGNATURE: &[u8] = b"Exif\0\0";
+const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const ICC_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+const MPF_SIGNATURE: &[u8] = b"MPF\0";
+const PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+const XMP_EXTENSION_SIGNATURE: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
 enum JFIFState {
 	INIT,
 	FF,
@@ -25,7 +133,16 @@ enum JFIFState {
 }
 
 impl JFIF {
-	pub fn load(f: &mut File) -> Option<JFIF> {
+	pub fn load<R: Read + Seek>(f: &mut R) -> Option<JFIF> {
+		match JFIF::load_partial(f) {
+			(jfif, true) => Some(jfif),
+			(_, false) => None,
+		}
+	}
+
+	/// Same as `load`, but keeps what was read when the stream is truncated or broken,
+	/// with whether the walk reached EOI.
+	pub fn load_partial<R: Read + Seek>(f: &mut R) -> (JFIF, bool) {
 		let mut jfif = JFIF {
 			version: 0,
 			unit: 0,
@@ -34,6 +151,19 @@ impl JFIF {
 			thumnail_width: 0,
 			thumnail_height: 0,
 			exif: None,
+			xmp: None,
+			iptc: None,
+			icc: None,
+			mpf: None,
+			adobe: None,
+			frame: None,
+			image_resources: Vec::new(),
+			comments: Vec::new(),
+			thumbnails: Vec::new(),
+			segments: Vec::new(),
+			extended_xmp: ExtendedXMP::new(),
+			photoshop: Vec::new(),
+			icc_chunks: ICCChunks::new(),
 		};
 
 		let mut state: JFIFState = JFIFState::INIT;
@@ -58,6 +188,7 @@ impl JFIF {
 				JFIFState::FF => {
 					match inbyte[0] {
 						0xD8 => {
+							jfif.standalone(f, inbyte[0]);
 							state = JFIFState::JFIF;
 						}
 						_ => {
@@ -66,39 +197,30 @@ impl JFIF {
 					}
 				}
 				JFIFState::JFIF => {
-					match inbyte[0] {
-						0xFF => {
-							state = JFIFState::JFIF_FF;
-						}
-						_ => {}
+					if inbyte[0] == 0xFF {
+						state = JFIFState::JFIF_FF;
 					}
 				}
 				JFIFState::JFIF_FF => {
 					match inbyte[0] {
 						0x00 => {  // data [0xFF]
-							state = JFIFState::JFIF;	
+							state = JFIFState::JFIF;
 						}
+						0xFF => {} // fill byte
 						0xD0..=0xD7 => { // restart
-							state = JFIFState::JFIF;	
+							state = JFIFState::JFIF;
+						}
+						0x01 => { // TEM
+							jfif.standalone(f, inbyte[0]);
+							state = JFIFState::JFIF;
 						}
 						0xD9 => { // EOI
+							jfif.standalone(f, inbyte[0]);
 							logger::debug("ACCEPTED!");
 							state = JFIFState::ACCEPTED;
 						}
-						0xE0 => { // APP0
-							state = jfif.APP0(f, JFIFState::JFIF);
-						}
-						0xE1 => {
-							state = jfif.APP1(f, JFIFState::JFIF);
-						}
 						_ => {
-							logger::debug(format!("mark FF{:02X}", inbyte[0]).as_str());
-							if jfif.mark(f) {
-								state = JFIFState::JFIF;
-							} else {
-								logger::debug("ERR");
-								state = JFIFState::ERR;
-							}
+							state = jfif.mark(f, inbyte[0], JFIFState::JFIF);
 						}
 					}
 				}
@@ -108,207 +230,976 @@ impl JFIF {
 			}
 		}
 
-		match state {
-			JFIFState::ACCEPTED => {
-				Some(jfif)
+		if !jfif.extended_xmp.is_empty() {
+			if let Some(xmp) = &mut jfif.xmp {
+				xmp.merge_extended(&jfif.extended_xmp);
 			}
-			_ => {
-				None
+		}
+
+		if !jfif.icc_chunks.is_empty() {
+			if let Some(profile) = jfif.icc_chunks.assemble() {
+				jfif.icc = ICC::load(&profile);
+			}
+		}
+
+		// resource blocks may be split over several APP13 segments
+		if !jfif.photoshop.is_empty() {
+			jfif.image_resources = load_resources(&jfif.photoshop);
+			if let Some(resource) = jfif.image_resources.iter().find(|r| r.id == RESOURCE_IPTC) {
+				jfif.iptc = Iptc::load(&resource.data);
 			}
-		}	
+		}
+
+		if let Some(mpf) = &mut jfif.mpf {
+			load_mp_attributes(f, mpf);
+		}
+
+		let accepted = matches!(state, JFIFState::ACCEPTED);
+		(jfif, accepted)
+	}
+
+	/// Every marker of the file in the order it was found, from SOI to EOI.
+	/// Restart markers inside the entropy-coded data are not listed.
+	pub fn segments(&self) -> &[Segment] {
+		&self.segments
+	}
+
+	fn standalone<R: Read + Seek>(&mut self, f: &mut R, marker: u8) {
+		let offset = f.stream_position().unwrap_or(2) - 2;
+		self.segments.push(Segment {
+			marker,
+			offset,
+			length: 0,
+			segment_type: segment_type(marker, &[]),
+		});
 	}
 
-	fn mark(&mut self, f: &mut File) -> bool {
-		let mut inbyte = [0;1];
-		let mut len:u16 = 0;
+	fn mark<R: Read + Seek>(&mut self, f: &mut R, marker: u8, ok_state: JFIFState) -> JFIFState {
+		logger::debug(format!("mark FF{:02X}", marker).as_str());
+		let offset = match f.stream_position() {
+			Ok(pos) => pos - 2,
+			Err(_) => {
+				return JFIFState::IOERR;
+			}
+		};
 
 		// length
-		if let Some(l) = read_length(f) {
-			len = l;
-		} else {
-			return false;
-		}
+		let len = match read_length(f) {
+			Some(l) => l,
+			None => {
+				return JFIFState::ERR;
+			}
+		};
 		logger::debug(format!("length: {}", len).as_str());
 
-		// skip
-		for i in 0..len-2 {
-			let ret = f.read_exact(&mut inbyte);
-			if ret.is_err() {
-				return false;
+		let payload = match read_bytes(f, len as usize - 2) {
+			Some(payload) => payload,
+			None => {
+				return JFIFState::ERR;
 			}
-		}
+		};
+		self.segments.push(Segment {
+			marker,
+			offset,
+			length: len,
+			segment_type: segment_type(marker, &payload),
+		});
 
-		return true;
+		match marker {
+			0xE0 => { // APP0
+				self.APP0(&payload, ok_state)
+			}
+			0xE1 => {
+				self.APP1(&payload, ok_state)
+			}
+			0xE2 => {
+				self.APP2(&payload, offset, ok_state)
+			}
+			0xED => {
+				self.APP13(&payload, ok_state)
+			}
+			0xEE => {
+				self.APP14(&payload, ok_state)
+			}
+			_ if is_sof(marker) => {
+				self.SOF(marker - 0xC0, &payload, ok_state)
+			}
+			0xFE => {
+				self.COM(&payload, ok_state)
+			}
+			_ => {
+				ok_state
+			}
+		}
 	}
 
-	fn APP0(&mut self, f: &mut File, ok_state: JFIFState) -> JFIFState {
+	fn APP0(&mut self, payload: &[u8], ok_state: JFIFState) -> JFIFState {
 		logger::debug("APP0");
 
-		// Length
-		let mut len: u16 = 0;
-		if let Some(l) = read_length(f) {
-			len = l;
+		if payload.starts_with(b"JFIF\0") {
+			self.APP0_JFIF(payload, ok_state)
+		} else if payload.starts_with(b"JFXX\0") {
+			self.APP0_JFXX(payload, ok_state)
 		} else {
-			return JFIFState::ERR;
+			logger::debug("unknown APP0");
+			ok_state
 		}
-		logger::debug(format!("length: {}", len).as_str());
+	}
 
-		// "JFIF\0"
-		let mut buff = [0; 5];
-		let ret = f.read_exact(&mut buff);
-		if ret.is_err() {
-			return JFIFState::ERR;
-		}
-		if buff[0] != 0x4A { // 'J'
-			return JFIFState::ERR;
-		}
-		if buff[1] != 0x46 { // 'F'
+	fn APP0_JFIF(&mut self, payload: &[u8], ok_state: JFIFState) -> JFIFState {
+		// "JFIF\0", version, unit, Dots Par Unit X/Y, thumnail size
+		if payload.len() < 14 {
 			return JFIFState::ERR;
 		}
-		if buff[2] != 0x49 { // 'I'
-			return JFIFState::ERR;
+		self.version = be16(payload, 5);
+		self.unit = payload[7];
+		self.dots_par_unit_x = be16(payload, 8);
+		self.dots_par_unit_y = be16(payload, 10);
+		self.thumnail_width = payload[12];
+		self.thumnail_height = payload[13];
+
+		// thumnail image (RGB)
+		let size = self.thumnail_width as usize * self.thumnail_height as usize * 3;
+		if size > 0 {
+			if let Some(data) = payload.get(14..14+size) {
+				self.thumbnails.push(Thumbnail {
+					format: ThumbnailFormat::RGB,
+					width: self.thumnail_width as u16,
+					height: self.thumnail_height as u16,
+					data: data.to_vec(),
+					palette: Vec::new(),
+				});
+			} else {
+				logger::debug("short thumnail");
+			}
 		}
-		if buff[3] != 0x46 { // 'F'
-			return JFIFState::ERR;
+
+		ok_state
+	}
+
+	fn APP0_JFXX(&mut self, payload: &[u8], ok_state: JFIFState) -> JFIFState {
+		logger::debug("JFXX");
+		if payload.len() < 6 {
+			logger::debug("short JFXX");
+			return ok_state;
 		}
-		if buff[4] != 0x00 { // '\0'
-			return JFIFState::ERR;
+
+		let body = &payload[6..];
+		let thumbnail = match payload[5] {
+			0x10 => { // JPEG
+				let (width, height) = jpeg_size(body).unwrap_or((0, 0));
+				Some(Thumbnail {
+					format: ThumbnailFormat::JPEG,
+					width,
+					height,
+					data: body.to_vec(),
+					palette: Vec::new(),
+				})
+			}
+			0x11 => { // 1 byte/pixel with palette
+				if body.len() < 2 + 768 {
+					None
+				} else {
+					let size = body[0] as usize * body[1] as usize;
+					body.get(770..770+size).map(|data| Thumbnail {
+						format: ThumbnailFormat::PALETTE,
+						width: body[0] as u16,
+						height: body[1] as u16,
+						data: data.to_vec(),
+						palette: body[2..770].to_vec(),
+					})
+				}
+			}
+			0x13 => { // 3 bytes/pixel RGB
+				if body.len() < 2 {
+					None
+				} else {
+					let size = body[0] as usize * body[1] as usize * 3;
+					body.get(2..2+size).map(|data| Thumbnail {
+						format: ThumbnailFormat::RGB,
+						width: body[0] as u16,
+						height: body[1] as u16,
+						data: data.to_vec(),
+						palette: Vec::new(),
+					})
+				}
+			}
+			code => {
+				logger::debug(format!("unknown JFXX extension code {:02X}", code).as_str());
+				return ok_state;
+			}
+		};
+
+		match thumbnail {
+			Some(thumbnail) => {
+				self.thumbnails.push(thumbnail);
+			}
+			None => {
+				logger::debug("short JFXX thumnail");
+			}
 		}
 
-		// version
-		if let Some(version) = read_2bytes(f) {
-			self.version = version;
+		ok_state
+	}
+
+	fn APP1(&mut self, payload: &[u8], ok_state: JFIFState) -> JFIFState {
+		logger::debug("APP1");
+
+		// EXIF check
+		if payload.starts_with(EXIF_SIGNATURE) {
+			logger::debug("EXIF");
+			self.exif = EXIF::load(&payload[EXIF_SIGNATURE.len()..]);
+		} else if payload.starts_with(XMP_SIGNATURE) {
+			logger::debug("XMP");
+			self.xmp = XMP::load(&payload[XMP_SIGNATURE.len()..]);
+		} else if payload.starts_with(XMP_EXTENSION_SIGNATURE) {
+			logger::debug("extended XMP");
+			self.extended_xmp.add(&payload[XMP_EXTENSION_SIGNATURE.len()..]);
 		} else {
-			return JFIFState::ERR;
+			logger::debug("not EXIF");
 		}
 
-		// unit
-		if let Some(unit) = read_1byte(f) {
-			self.unit = unit;
+		ok_state
+	}
+
+	fn APP2(&mut self, payload: &[u8], offset: u64, ok_state: JFIFState) -> JFIFState {
+		logger::debug("APP2");
+
+		if payload.starts_with(ICC_SIGNATURE) {
+			self.icc_chunks.add(&payload[ICC_SIGNATURE.len()..]);
+		} else if payload.starts_with(MPF_SIGNATURE) {
+			logger::debug("MPF");
+			// marker, length and signature precede the MP header
+			let base_offset = offset + 4 + MPF_SIGNATURE.len() as u64;
+			self.mpf = MPF::load(&payload[MPF_SIGNATURE.len()..], base_offset);
 		} else {
-			return JFIFState::ERR;
+			logger::debug("unknown APP2");
 		}
 
-		// Dots Par Unit X/Y
-		if let Some(dpu) = read_2bytes(f) {
-			self.dots_par_unit_x = dpu;
+		ok_state
+	}
+
+	fn APP13(&mut self, payload: &[u8], ok_state: JFIFState) -> JFIFState {
+		logger::debug("APP13");
+
+		if payload.starts_with(PHOTOSHOP_SIGNATURE) {
+			self.photoshop.extend_from_slice(&payload[PHOTOSHOP_SIGNATURE.len()..]);
 		} else {
-			return JFIFState::ERR;
-		}
-		if let Some(dpu) = read_2bytes(f) {
-			self.dots_par_unit_y = dpu;
-		} else {
-			return JFIFState::ERR;
+			logger::debug("not Photoshop");
 		}
 
-		// thumnail size
-		if let Some(size) = read_1byte(f) {
-			self.thumnail_width = size;
+		ok_state
+	}
+
+	fn APP14(&mut self, payload: &[u8], ok_state: JFIFState) -> JFIFState {
+		logger::debug("APP14");
+
+		if payload.starts_with(b"Adobe") && payload.len() >= 12 {
+			self.adobe = Some(Adobe {
+				dct_encode_version: be16(payload, 5),
+				flags0: be16(payload, 7),
+				flags1: be16(payload, 9),
+				color_transform: payload[11],
+			});
 		} else {
-			return JFIFState::ERR;
+			logger::debug("not Adobe");
 		}
-		if let Some(size) = read_1byte(f) {
-			self.thumnail_height = size;
-		} else {
-			return JFIFState::ERR;
+
+		ok_state
+	}
+
+	fn SOF(&mut self, process: u8, payload: &[u8], ok_state: JFIFState) -> JFIFState {
+		logger::debug("SOF");
+		if self.frame.is_some() {
+			return ok_state;
 		}
-			
-		// skip thumnail image	
-		if !skip(f, len-16 ) {
+		if payload.len() < 6 {
 			return JFIFState::ERR;
 		}
 
-		return ok_state;
+		let components = payload[6..].chunks_exact(3).take(payload[5] as usize).map(|c| Component {
+			id: c[0],
+			h: c[1] >> 4,
+			v: c[1] & 0x0F,
+			tq: c[2],
+		}).collect();
+		self.frame = Some(Frame {
+			process,
+			precision: payload[0],
+			height: be16(payload, 1),
+			width: be16(payload, 3),
+			components,
+		});
+
+		ok_state
 	}
 
-	fn APP1(&mut self, f: &mut File, ok_state: JFIFState) -> JFIFState {
-		logger::debug("APP1");
-		let mut len: u16 = 0;
-		if let Some(l) = read_length(f) {
-			len = l;
-		} else {
-			return JFIFState::ERR;
+	/// Colour model of the decoded samples, following the rules of libjpeg:
+	/// the JFIF marker implies YCbCr, the Adobe transform flag tells RGB from
+	/// YCbCr and CMYK from YCCK, and RGB component ids are honoured otherwise.
+	pub fn color_model(&self) -> ColorModel {
+		let frame = match &self.frame {
+			Some(frame) => frame,
+			None => {
+				return ColorModel::UNKNOWN;
+			}
+		};
+		let jfif = self.segments.iter().any(|s| matches!(&s.segment_type, SegmentType::APP(0, signature) if signature == "JFIF"));
+
+		match frame.components.len() {
+			1 => ColorModel::GRAYSCALE,
+			3 => {
+				if jfif {
+					ColorModel::YCbCr
+				} else if let Some(adobe) = &self.adobe {
+					match adobe.color_transform {
+						0 => ColorModel::RGB,
+						_ => ColorModel::YCbCr,
+					}
+				} else {
+					let ids:Vec<u8> = frame.components.iter().map(|c| c.id).collect();
+					if ids == b"RGB" {
+						ColorModel::RGB
+					} else {
+						ColorModel::YCbCr
+					}
+				}
+			}
+			4 => {
+				match &self.adobe {
+					Some(adobe) => {
+						match adobe.color_transform {
+							0 => ColorModel::CMYK,
+							_ => ColorModel::YCCK,
+						}
+					}
+					None => ColorModel::CMYK,
+				}
+			}
+			_ => ColorModel::UNKNOWN,
 		}
-		logger::debug(format!("length: {}", len).as_str());
-		
-		// EXIF check
-		let mut buff = [0; 6];
-		let ret = f.read_exact(&mut buff);
-		if ret.is_err() {
-			return JFIFState::ERR;
+	}
+
+	fn COM(&mut self, payload: &[u8], ok_state: JFIFState) -> JFIFState {
+		logger::debug("COM");
+		self.comments.push(decode_comment(payload));
+
+		ok_state
+	}
+}
+
+impl Thumbnail {
+	/// File extension matching the output of `save()`.
+	pub fn extension(&self) -> &'static str {
+		match self.format {
+			ThumbnailFormat::JPEG => "jpg",
+			_ => "ppm",
 		}
-		if buff[0] == 0x45 && buff[1] == 0x78 && buff[2] == 0x69 && buff[3] == 0x66 && buff[4] == 0x00 && buff[5] == 0x00 {
-			logger::debug("EXIF");
-			let mut buff:Vec<u8> = Vec::with_capacity(len as usize -8);
-			unsafe {
-				buff.set_len(len as usize -8);
+	}
+
+	/// Uncompressed thumbnails as a binary PPM (P6) image; None for JPEG, or when a palette
+	/// index points past the end of the palette.
+	pub fn to_ppm(&self) -> Option<Vec<u8>> {
+		let rgb:Vec<u8> = match self.format {
+			ThumbnailFormat::JPEG => {
+				return None;
 			}
-			let ret = f.read_exact(&mut buff);
-			if ret.is_err() {
-				return JFIFState::ERR;
+			ThumbnailFormat::RGB => {
+				self.data.clone()
 			}
-			self.exif = EXIF::load(&mut buff.as_slice());
-		} else {
-			logger::debug("not EXIF");
-			if !skip(f, len-2-6) {
-				return JFIFState::ERR;
+			ThumbnailFormat::PALETTE => {
+				let mut rgb = Vec::with_capacity(self.data.len() * 3);
+				for &i in &self.data {
+					let i = i as usize * 3;
+					match self.palette.get(i..i+3) {
+						Some(color) => rgb.extend_from_slice(color),
+						None => {
+							logger::debug("short thumbnail palette");
+							return None;
+						}
+					}
+				}
+				rgb
 			}
+		};
+
+		let mut ppm = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+		ppm.extend_from_slice(&rgb);
+		Some(ppm)
+	}
+
+	/// Writes the thumbnail as it is for JPEG, as PPM otherwise.
+	pub fn save(&self, path: &Path) -> io::Result<()> {
+		let data = match self.format {
+			ThumbnailFormat::JPEG => self.data.clone(),
+			_ => self.to_ppm().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "short thumbnail palette"))?,
+		};
+		File::create(path)?.write_all(&data)
+	}
+}
+
+impl fmt::Display for ColorModel {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ColorModel::GRAYSCALE => write!(f, "Grayscale"),
+			ColorModel::YCbCr => write!(f, "YCbCr"),
+			ColorModel::RGB => write!(f, "RGB"),
+			ColorModel::CMYK => write!(f, "CMYK"),
+			ColorModel::YCCK => write!(f, "YCCK"),
+			ColorModel::UNKNOWN => write!(f, "Unknown"),
 		}
-	
-		return ok_state;
 	}
 }
 
+impl fmt::Display for SegmentType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SegmentType::SOI => write!(f, "SOI"),
+			SegmentType::SOF(n) => write!(f, "SOF{}", n),
+			SegmentType::DHT => write!(f, "DHT"),
+			SegmentType::DAC => write!(f, "DAC"),
+			SegmentType::DQT => write!(f, "DQT"),
+			SegmentType::DNL => write!(f, "DNL"),
+			SegmentType::DRI => write!(f, "DRI"),
+			SegmentType::SOS => write!(f, "SOS"),
+			SegmentType::TEM => write!(f, "TEM"),
+			SegmentType::APP(n, signature) => {
+				if signature.is_empty() {
+					write!(f, "APP{}", n)
+				} else {
+					write!(f, "APP{} \"{}\"", n, signature)
+				}
+			}
+			SegmentType::COM => write!(f, "COM"),
+			SegmentType::EOI => write!(f, "EOI"),
+			SegmentType::UNKNOWN => write!(f, "UNKNOWN"),
+		}
+	}
+}
+
+pub(crate) fn segment_type(marker: u8, payload: &[u8]) -> SegmentType {
+	match marker {
+		0xD8 => SegmentType::SOI,
+		0xC4 => SegmentType::DHT,
+		0xCC => SegmentType::DAC,
+		_ if is_sof(marker) => SegmentType::SOF(marker - 0xC0),
+		0xDA => SegmentType::SOS,
+		0xDB => SegmentType::DQT,
+		0xDC => SegmentType::DNL,
+		0xDD => SegmentType::DRI,
+		0x01 => SegmentType::TEM,
+		0xE0..=0xEF => SegmentType::APP(marker - 0xE0, app_signature(payload)),
+		0xFE => SegmentType::COM,
+		0xD9 => SegmentType::EOI,
+		_ => SegmentType::UNKNOWN,
+	}
+}
+
+// FFC0-FFCF but DHT, JPG and DAC
+fn is_sof(marker: u8) -> bool {
+	matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC)
+}
+
+// leading printable run of an APPn payload, up to the NUL terminator
+fn app_signature(payload: &[u8]) -> String {
+	let head = &payload[..payload.len().min(64)];
+	let end = head.iter().position(|b| !(0x20..0x7F).contains(b)).unwrap_or(head.len());
+	String::from_utf8_lossy(&head[..end]).to_string()
+}
 
+// The comment has no declared charset: UTF-8 (with or without BOM) and
+// UTF-16 with BOM are recognized, anything else is taken as Latin-1.
+fn decode_comment(payload: &[u8]) -> String {
+	if payload.len() >= 2 && (payload.starts_with(b"\xFE\xFF") || payload.starts_with(b"\xFF\xFE")) {
+		let big_endian = payload[0] == 0xFE;
+		let units:Vec<u16> = payload[2..].chunks_exact(2).map(|c| {
+			if big_endian {
+				(c[0] as u16) << 8 | c[1] as u16
+			} else {
+				(c[1] as u16) << 8 | c[0] as u16
+			}
+		}).collect();
+		return String::from_utf16_lossy(&units).trim_end_matches('\0').to_string();
+	}
 
-fn read_length(f: &mut File) -> Option<u16> {
-	if let Some(len) = read_2bytes(f) {
-		if len < 2 {
+	// NUL padding, only for the byte-oriented encodings
+	let mut end = payload.len();
+	while end > 0 && payload[end-1] == 0 {
+		end -= 1;
+	}
+	let text = &payload[..end];
+	if let Some(text) = text.strip_prefix(b"\xEF\xBB\xBF") {
+		return String::from_utf8_lossy(text).to_string();
+	}
+	match std::str::from_utf8(text) {
+		Ok(s) => s.to_string(),
+		Err(_) => text.iter().map(|&b| b as char).collect(),
+	}
+}
+
+// image size from the first SOFn of an in-memory JPEG stream;
+// None for lossless JPEG, which raw files use for sensor data rather than pictures
+pub(crate) fn jpeg_size(data: &[u8]) -> Option<(u16, u16)> {
+	if !data.starts_with(&[0xFF, 0xD8]) {
+		return None;
+	}
+	let mut offset = 2;
+	while offset + 4 <= data.len() {
+		if data[offset] != 0xFF {
 			return None;
-		} else {
-			return Some(len);
 		}
+		let marker = data[offset+1];
+		if marker == 0xFF {
+			offset += 1;
+			continue;
+		}
+		let len = be16(data, offset+2) as usize;
+		if matches!(segment_type(marker, &[]), SegmentType::SOF(_)) {
+			if matches!(marker, 0xC3 | 0xC7 | 0xCB | 0xCF) || offset + 9 > data.len() {
+				return None;
+			}
+			return Some((be16(data, offset+7), be16(data, offset+5)));
+		}
+		if marker == 0xDA {
+			return None;
+		}
+		offset += 2 + len;
+	}
+	None
+}
+
+/// Replaces the Exif of an in-memory JPEG stream, see `write_exif`.
+pub(crate) fn replace_exif(data: &[u8], tiff: &[u8]) -> Option<Vec<u8>> {
+	let mut out = Vec::with_capacity(data.len() + tiff.len());
+	match write_exif(&mut &data[..], &mut out, tiff) {
+		Ok(_) => Some(out),
+		Err(e) => {
+			logger::debug(format!("cant write Exif: {}", e).as_str());
+			None
+		}
+	}
+}
+
+/// Copies a JPEG stream with `tiff` as its Exif. The first Exif APP1 segment is replaced in place
+/// and any other one dropped; without one, the new segment goes after SOI, or after APP0 when
+/// the stream starts with it. Everything else is copied byte for byte.
+pub fn write_exif<R: Read, W: Write>(f: &mut R, out: &mut W, tiff: &[u8]) -> io::Result<()> {
+	let app1 = match new_segment(0xE1, &[EXIF_SIGNATURE, tiff].concat()) {
+		Some(app1) => app1,
+		None => {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "Exif too large for APP1"));
+		}
+	};
+	rewrite_segments(f, out, |segments| {
+		let position = segments.iter().position(|segment| is_exif_segment(segment));
+		segments.retain(|segment| !is_exif_segment(segment));
+		let after_app0 = segments.first().is_some_and(|segment| segment[1] == 0xE0);
+		segments.insert(position.unwrap_or(after_app0 as usize), app1);
+	})
+}
+
+/// TIFF stream of the first Exif APP1 segment of a JPEG stream.
+pub fn exif_tiff<R: Read>(f: &mut R) -> Option<Vec<u8>> {
+	let (segments, _) = read_header_segments(f).ok()?;
+	segments.into_iter()
+		.find(|segment| is_exif_segment(segment))
+		.map(|segment| segment[4 + EXIF_SIGNATURE.len()..].to_vec())
+}
+
+/// Copies a JPEG stream, letting `edit` change the segments between SOI and SOS: each one
+/// as stored, with its marker and length, in file order. SOS and the rest of the stream,
+/// entropy-coded data and any trailing images included, are copied as they are; the MP Entries
+/// of an APP2 "MPF" segment are updated for the new size of the first image.
+pub fn rewrite_segments<R, W, F>(f: &mut R, out: &mut W, edit: F) -> io::Result<()>
+	where R: Read, W: Write, F: FnOnce(&mut Vec<Vec<u8>>)
+{
+	write_header_segments(f, out, |segments| {
+		let before = mpf_position(segments);
+		edit(segments);
+		// the images that follow keep their place relative to the end of the first one
+		if let (Some(before), Some(after)) = (before, mpf_position(segments)) {
+			let index = after.0;
+			let first_delta = after.2 as i64 - before.2 as i64;
+			let header_delta = after.1 as i64 - before.1 as i64;
+			if shift_entries(&mut segments[index][4 + MPF_SIGNATURE.len()..], first_delta, header_delta).is_none() {
+				logger::debug("cant update MPF index");
+			}
+		}
+	})?;
+	io::copy(f, out)?;
+	Ok(())
+}
+
+/// Same as `rewrite_segments`, but the copy ends with the EOI of the first image:
+/// whatever follows it, such as the other images of a Multi-Picture Format file, is dropped.
+pub fn rewrite_primary_segments<R, W, F>(f: &mut R, out: &mut W, edit: F) -> io::Result<()>
+	where R: Read, W: Write, F: FnOnce(&mut Vec<Vec<u8>>)
+{
+	if write_header_segments(f, out, edit)? == 0xD9 {
+		return Ok(());
+	}
+	let mut rest = Vec::new();
+	f.read_to_end(&mut rest)?;
+	let end = image_end(&rest).unwrap_or_else(|| {
+		logger::debug("no EOI");
+		rest.len()
+	});
+	out.write_all(&rest[..end])
+}
+
+/// Same as `rewrite_segments` for the first image, with `edit_image` changing the segments of
+/// each of the other images of a Multi-Picture Format file as well. Those images follow the first
+/// one in the order of their offsets and the MP Entries are updated for their new sizes and
+/// places; anything else after the first image is dropped. When the images cannot be found, or
+/// no MPF segment is left to index them, they are dropped too, with the MPF segment.
+pub fn rewrite_mp_segments<R, W, F, G>(f: &mut R, out: &mut W, edit: F, mut edit_image: G) -> io::Result<()>
+	where R: Read, W: Write, F: FnOnce(&mut Vec<Vec<u8>>), G: FnMut(&mut Vec<Vec<u8>>)
+{
+	let mut data = Vec::new();
+	f.read_to_end(&mut data)?;
+	let mut src = &data[..];
+	let (mut segments, marker) = read_header_segments(&mut src)?;
+	let header_end = data.len() - src.len();
+	let first_end = if marker == 0xD9 {
+		header_end
 	} else {
+		header_end + image_end(&data[header_end..]).unwrap_or_else(|| {
+			logger::debug("no EOI");
+			data.len() - header_end
+		})
+	};
+	let images = mp_images(&data, &segments, first_end);
+	edit(&mut segments);
+	let rest = images.and_then(|images| {
+		place_mp_images(&data, &mut segments, images, first_end - header_end, &mut edit_image)
+	});
+	if rest.is_none() {
+		if let Some((index, _, _)) = mpf_position(&segments) {
+			logger::debug("MP images dropped");
+			segments.remove(index);
+		}
+	}
+	write_segments(out, &segments, marker)?;
+	out.write_all(&data[header_end..first_end])?;
+	out.write_all(&rest.unwrap_or_default())
+}
+
+/// Marker, length and payload of a segment; None when the payload does not fit.
+pub fn new_segment(marker: u8, payload: &[u8]) -> Option<Vec<u8>> {
+	let len = 2 + payload.len();
+	if len > 0xFFFF {
+		return None;
+	}
+	let mut segment = vec![0xFF, marker, (len >> 8) as u8, len as u8];
+	segment.extend_from_slice(payload);
+	Some(segment)
+}
+
+// MP Attribute IFDs of the images after the first one
+fn load_mp_attributes<R: Read + Seek>(f: &mut R, mpf: &mut MPF) {
+	for i in 1..mpf.images.len() {
+		let offset = match mpf.image_offset(i) {
+			Some(offset) if offset > 0 => offset,
+			_ => {
+				continue;
+			}
+		};
+		if f.seek(io::SeekFrom::Start(offset)).is_err() {
+			continue;
+		}
+		let segments = match read_header_segments(&mut f.by_ref().take(mpf.images[i].size as u64)) {
+			Ok((segments, _)) => segments,
+			Err(e) => {
+				logger::debug(format!("MP image {}: {}", i + 1, e).as_str());
+				continue;
+			}
+		};
+		let payload = segments.iter()
+			.find(|segment| segment[1] == 0xE2 && segment.get(4..).is_some_and(|p| p.starts_with(MPF_SIGNATURE)))
+			.map(|segment| &segment[4 + MPF_SIGNATURE.len()..]);
+		if let Some(attributes) = payload.and_then(load_attributes) {
+			mpf.images[i].attributes = attributes;
+		}
+	}
+}
+
+// index of the APP2 "MPF" segment, bytes before it and bytes of all the segments
+fn mpf_position(segments: &[Vec<u8>]) -> Option<(usize, usize, usize)> {
+	let index = segments.iter().position(|segment| {
+		segment.get(1) == Some(&0xE2) && segment.get(4..).is_some_and(|payload| payload.starts_with(MPF_SIGNATURE))
+	})?;
+	let before = segments[..index].iter().map(|segment| segment.len()).sum();
+	let total = segments.iter().map(|segment| segment.len()).sum();
+	Some((index, before, total))
+}
+
+// file offset of the MP header and the byte ranges of the other images, each one once,
+// in file order
+fn mp_images(data: &[u8], segments: &[Vec<u8>], first_end: usize) -> Option<(usize, Vec<Range<usize>>)> {
+	let (index, before, _) = mpf_position(segments)?;
+	let base = 2 + before + 4 + MPF_SIGNATURE.len();
+	let mpf = MPF::load(&segments[index][4 + MPF_SIGNATURE.len()..], base as u64)?;
+	let mut ranges = Vec::new();
+	for image in mpf.images.iter().skip(1).filter(|image| image.offset != 0) {
+		let start = base + image.offset as usize;
+		let end = start + image.size as usize;
+		if start < first_end || end > data.len() {
+			logger::debug("MP image out of the file");
+			return None;
+		}
+		ranges.push(start..end);
+	}
+	ranges.sort_by_key(|range| range.start);
+	ranges.dedup();
+	if ranges.windows(2).any(|pair| pair[1].start < pair[0].end) {
+		logger::debug("overlapping MP images");
 		return None;
 	}
+	Some((base, ranges))
 }
 
-fn read_1byte(f: &mut File) -> Option<u8> {
-	let mut inbyte = [0;1];
-	let mut r:u8 = 0;
+// the other images with their segments edited, laid out after the first one, whose
+// entropy-coded data and EOI take `coded` bytes; the MP Entries are updated for them
+fn place_mp_images<G>(data: &[u8], segments: &mut [Vec<u8>], images: (usize, Vec<Range<usize>>), coded: usize, edit_image: &mut G) -> Option<Vec<u8>>
+	where G: FnMut(&mut Vec<Vec<u8>>)
+{
+	let (index, before, total) = mpf_position(segments)?;
+	let (base, ranges) = images;
+	let first_size = 2 + total + 2 + coded;
+	let new_base = 2 + before + 4 + MPF_SIGNATURE.len();
+	let mut rest = Vec::new();
+	// old offset, new offset and size of each image
+	let mut placed = Vec::new();
+	for range in ranges {
+		let mut src = &data[range.clone()];
+		let (mut image_segments, marker) = match read_header_segments(&mut src) {
+			Ok(read) => read,
+			Err(e) => {
+				logger::debug(format!("MP image: {}", e).as_str());
+				return None;
+			}
+		};
+		edit_image(&mut image_segments);
+		let start = rest.len();
+		write_segments(&mut rest, &image_segments, marker).ok()?;
+		rest.extend_from_slice(src);
+		placed.push((range.start - base, first_size + start - new_base, rest.len() - start));
+	}
+	let updated = update_entries(&mut segments[index][4 + MPF_SIGNATURE.len()..], |i, size, offset| {
+		if i == 0 {
+			return Some((u32::try_from(first_size).ok()?, offset));
+		}
+		if offset == 0 {
+			return Some((size, offset));
+		}
+		let &(_, new_offset, new_size) = placed.iter().find(|image| image.0 == offset as usize)?;
+		Some((u32::try_from(new_size).ok()?, u32::try_from(new_offset).ok()?))
+	});
+	if updated.is_none() {
+		logger::debug("cant update MPF index");
+		return None;
+	}
+	Some(rest)
+}
 
-	let ret = f.read_exact(&mut inbyte);
-	if ret.is_err() {
+// SOI, the edited segments and the marker that ended them
+fn write_header_segments<R, W, F>(f: &mut R, out: &mut W, edit: F) -> io::Result<u8>
+	where R: Read, W: Write, F: FnOnce(&mut Vec<Vec<u8>>)
+{
+	let (mut segments, marker) = read_header_segments(f)?;
+	edit(&mut segments);
+	write_segments(out, &segments, marker)?;
+	Ok(marker)
+}
+
+// SOI, the segments and the marker that follows them
+fn write_segments<W: Write>(out: &mut W, segments: &[Vec<u8>], marker: u8) -> io::Result<()> {
+	out.write_all(&[0xFF, 0xD8])?;
+	for segment in segments {
+		if segment.len() < 2 || segment.len() > 2 + 0xFFFF || segment[0] != 0xFF {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "broken segment"));
+		}
+		out.write_all(segment)?;
+	}
+	out.write_all(&[0xFF, marker])
+}
+
+// end of the EOI marker in `data`, which starts with the length of the first SOS;
+// 0xFF in entropy-coded data is followed by 0x00 or RSTn, any other marker has a length
+fn image_end(data: &[u8]) -> Option<usize> {
+	let mut offset = be16_at(data, 0)? as usize;
+	while offset < data.len() {
+		if data[offset] != 0xFF {
+			offset += 1;
+			continue;
+		}
+		let mut next = offset + 1;
+		while data.get(next) == Some(&0xFF) {
+			next += 1;
+		}
+		match *data.get(next)? {
+			0xD9 => {
+				return Some(next + 1);
+			}
+			0x00 | 0xD0..=0xD7 => {
+				offset = next + 1;
+			}
+			_ => {
+				offset = next + 1 + be16_at(data, next + 1)? as usize;
+			}
+		}
+	}
+	None
+}
+
+fn be16_at(data: &[u8], offset: usize) -> Option<u16> {
+	data.get(offset..offset+2).map(|_| be16(data, offset))
+}
+
+fn is_exif_segment(segment: &[u8]) -> bool {
+	segment.get(1) == Some(&0xE1) && segment.get(4..).is_some_and(|payload| payload.starts_with(EXIF_SIGNATURE))
+}
+
+// segments after SOI up to SOS (or EOI), and the marker that ended them
+fn read_header_segments<R: Read>(f: &mut R) -> io::Result<(Vec<Vec<u8>>, u8)> {
+	let broken = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+	let mut soi = [0; 2];
+	f.read_exact(&mut soi)?;
+	if soi != [0xFF, 0xD8] {
+		return Err(broken("not JPEG"));
+	}
+	let mut segments = Vec::new();
+	loop {
+		let mut b = [0; 1];
+		f.read_exact(&mut b)?;
+		if b[0] != 0xFF {
+			return Err(broken("no marker"));
+		}
+		// fill bytes
+		while b[0] == 0xFF {
+			f.read_exact(&mut b)?;
+		}
+		let marker = b[0];
+		match marker {
+			0xDA | 0xD9 => {
+				return Ok((segments, marker));
+			}
+			0x01 | 0xD0..=0xD7 => {
+				segments.push(vec![0xFF, marker]);
+			}
+			_ => {
+				let mut len = [0; 2];
+				f.read_exact(&mut len)?;
+				let len = u16::from_be_bytes(len) as usize;
+				if len < 2 {
+					return Err(broken("broken segment length"));
+				}
+				let mut segment = vec![0xFF, marker, (len >> 8) as u8, len as u8];
+				segment.resize(2 + len, 0);
+				f.read_exact(&mut segment[4..])?;
+				segments.push(segment);
+			}
+		}
+	}
+}
+
+fn read_length<R: Read>(f: &mut R) -> Option<u16> {
+	let len = read_2bytes(f)?;
+	if len < 2 {
 		None
 	} else {
-		r = inbyte[0];
-		Some(r)
+		Some(len)
 	}
 }
 
-fn read_2bytes(f: &mut File) -> Option<u16> {
+fn read_2bytes<R: Read>(f: &mut R) -> Option<u16> {
 	let mut inbyte = [0;2];
-	let mut r:u16 = 0;
 
 	let ret = f.read_exact(&mut inbyte);
 	if ret.is_err() {
 		None
 	} else {
-		r = inbyte[0] as u16;
-		r <<= 8;
-		r |= inbyte[1] as u16;
-		Some(r)
+		Some(be16(&inbyte, 0))
 	}
 }
 
-fn skip(f: &mut File, len: u16) -> bool {
-	let mut buff = [0;1];
+fn read_bytes<R: Read>(f: &mut R, len: usize) -> Option<Vec<u8>> {
+	let mut buff = vec![0; len];
 
-	for i in 0..len {	
-		let ret = f.read_exact(&mut buff);
-		if ret.is_err() {
-			return false;
+	let ret = f.read_exact(&mut buff);
+	if ret.is_err() {
+		None
+	} else {
+		Some(buff)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+
+	// Exif, MPF and a second image after the first EOI
+	const JPEG: &[u8] = include_bytes!("../tests/data/strip.jpg");
+
+	#[test]
+	fn write_exif_keeps_the_mp_images_reachable() {
+		let tiff = exif_tiff(&mut Cursor::new(JPEG)).unwrap();
+		let mut out = Vec::new();
+		// a shorter Exif, its content does not matter here
+		write_exif(&mut Cursor::new(JPEG), &mut out, &tiff[..tiff.len() / 2]).unwrap();
+		assert!(out.len() < JPEG.len());
+
+		let mut f = Cursor::new(&out);
+		let jfif = JFIF::load(&mut f).unwrap();
+		let mpf = jfif.mpf.as_ref().unwrap();
+		assert_eq!(mpf.images[0].size as usize, out.len() - mpf.images[1].size as usize);
+		let second = mpf.extract(&mut f, 1).unwrap();
+		assert!(JPEG.ends_with(&second));
+	}
+
+	// SOI, an Adobe APP14 segment with the transform when given, a baseline frame and EOI
+	fn frame(components: &[u8], transform: Option<u8>) -> Vec<u8> {
+		let mut data = vec![0xFF, 0xD8];
+		if let Some(transform) = transform {
+			data.extend_from_slice(b"\xFF\xEE\x00\x0EAdobe\x00\x64\x00\x00\x00\x00");
+			data.push(transform);
+		}
+		data.extend_from_slice(&[0xFF, 0xC0, 0x00, 8 + 3 * components.len() as u8, 8, 0, 1, 0, 1, components.len() as u8]);
+		for &id in components {
+			data.extend_from_slice(&[id, 0x11, 0]);
 		}
+		data.extend_from_slice(&[0xFF, 0xD9]);
+		data
+	}
+
+	#[test]
+	fn color_model_follows_libjpeg() {
+		let model = |components: &[u8], transform| {
+			JFIF::load(&mut Cursor::new(frame(components, transform))).unwrap().color_model().to_string()
+		};
+		assert_eq!(model(&[1], None), "Grayscale");
+		assert_eq!(model(&[1, 2, 3], None), "YCbCr");
+		assert_eq!(model(b"RGB", None), "RGB");
+		assert_eq!(model(&[1, 2, 3], Some(0)), "RGB");
+		assert_eq!(model(&[1, 2, 3], Some(1)), "YCbCr");
+		assert_eq!(model(&[1, 2, 3, 4], None), "CMYK");
+		assert_eq!(model(&[1, 2, 3, 4], Some(0)), "CMYK");
+		assert_eq!(model(&[1, 2, 3, 4], Some(1)), "YCCK");
+		assert_eq!(model(&[1, 2, 3, 4], Some(2)), "YCCK");
+	}
+
+	#[test]
+	fn palette_thumbnail_checks_its_indexes() {
+		let mut thumbnail = Thumbnail {
+			format: ThumbnailFormat::PALETTE,
+			width: 2,
+			height: 1,
+			data: vec![0, 1],
+			palette: vec![1, 2, 3, 4, 5, 6],
+		};
+		assert_eq!(thumbnail.to_ppm().unwrap(), b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06");
+		thumbnail.data = vec![0, 2];
+		assert!(thumbnail.to_ppm().is_none());
 	}
-	return true;
 }
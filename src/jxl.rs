@@ -0,0 +1,222 @@
+use std::io::prelude::*;
+use crate::bmff::*;
+use crate::exif::*;
+use crate::xmp::*;
+use crate::logger;
+use crate::brotli;
+use crate::bytes::*;
+
+/// JPEG XL image, a bare codestream or the ISO-BMFF container with metadata boxes.
+pub struct JXL {
+    pub container: bool,
+    /// from the codestream SizeHeader, 0 when not found
+    pub width: u32,
+    pub height: u32,
+    /// 'Exif' box
+    pub exif: Option<EXIF>,
+    /// 'xml ' box
+    pub xmp: Option<XMP>,
+    /// original types of the Brotli compressed ('brob') boxes
+    pub compressed_boxes: Vec<String>,
+}
+
+const CONTAINER_SIGNATURE: &[u8] = b"\0\0\0\x0CJXL \x0D\x0A\x87\x0A";
+const CODESTREAM_SIGNATURE: &[u8] = b"\xFF\x0A";
+
+// bits of the SizeHeader are read LSB first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl JXL {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<JXL> {
+        let mut src = SeekReader::new(f)?;
+        JXL::load_from(&mut src)
+    }
+
+    pub fn load_data(data: &[u8]) -> Option<JXL> {
+        JXL::load_from(&mut &data[..])
+    }
+
+    fn load_from<S: ReadAt>(src: &mut S) -> Option<JXL> {
+        let mut jxl = JXL {
+            container: false,
+            width: 0,
+            height: 0,
+            exif: None,
+            xmp: None,
+            compressed_boxes: Vec::new(),
+        };
+
+        let head = src.read_at(0, CONTAINER_SIGNATURE.len()).unwrap_or_default();
+        if head.starts_with(CODESTREAM_SIGNATURE) {
+            jxl.load_size(&src.read_at(0, 64.min(src.size() as usize))?);
+            return Some(jxl);
+        }
+        if head != CONTAINER_SIGNATURE {
+            logger::debug("not JPEG XL");
+            return None;
+        }
+        jxl.container = true;
+
+        let size = src.size();
+        for b in read_boxes(src, 0, size) {
+            match &b.box_type {
+                b"Exif" | b"xml " => {
+                    if let Some(data) = b.data(src) {
+                        jxl.load_box(&b.box_type, &data);
+                    }
+                }
+                b"brob" => {
+                    // the original box type, then the Brotli stream
+                    let data = b.data(src).unwrap_or_default();
+                    if data.len() >= 4 {
+                        jxl.compressed_boxes.push(String::from_utf8_lossy(&data[..4]).to_string());
+                        match brotli::decompress(&data[4..]) {
+                            Some(inner) => jxl.load_box(&data[..4], &inner),
+                            None => logger::debug("Brotli box not decoded"),
+                        }
+                    }
+                }
+                b"jxlc" => {
+                    if let Some(head) = src.read_at(b.offset, 64.min(b.size as usize)) {
+                        jxl.load_size(&head);
+                    }
+                }
+                b"jxlp" => {
+                    // sequence number, then a part of the codestream; the first one holds the header
+                    if jxl.width == 0 {
+                        if let Some(head) = src.read_at(b.offset + 4, 64.min(b.size.saturating_sub(4) as usize)) {
+                            jxl.load_size(&head);
+                        }
+                    }
+                }
+                _ => {
+                    logger::debug(format!("box {}", b.type_name()).as_str());
+                }
+            }
+        }
+
+        Some(jxl)
+    }
+
+    // metadata box contents, whether stored or Brotli compressed
+    fn load_box(&mut self, box_type: &[u8], data: &[u8]) {
+        match box_type {
+            b"Exif" => {
+                // the TIFF header follows the 4-byte offset field and that many bytes
+                if data.len() >= 4 {
+                    let start = (be32(data, 0) as usize).saturating_add(4);
+                    self.exif = data.get(start..).and_then(EXIF::load);
+                }
+            }
+            b"xml " => {
+                self.xmp = XMP::load(data);
+            }
+            _ => {
+                logger::debug(format!("compressed box {}", String::from_utf8_lossy(box_type)).as_str());
+            }
+        }
+    }
+
+    // SizeHeader right after the codestream signature
+    fn load_size(&mut self, codestream: &[u8]) {
+        if !codestream.starts_with(CODESTREAM_SIGNATURE) {
+            logger::debug("no codestream signature");
+            return;
+        }
+        let mut bits = Bits {
+            data: &codestream[2..],
+            pos: 0,
+        };
+        if let Some((width, height)) = bits.size_header() {
+            self.width = width;
+            self.height = height;
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("Container".to_string(), if self.container { "Yes" } else { "No" }.to_string()),
+            ("ImageWidth".to_string(), self.width.to_string()),
+            ("ImageHeight".to_string(), self.height.to_string()),
+        ];
+        for box_type in &self.compressed_boxes {
+            let value = match box_type.as_str() {
+                "Exif" | "xml " => box_type.trim_end().to_string(),
+                _ => format!("{} (not decoded)", box_type.trim_end()),
+            };
+            entries.push(("BrotliBox".to_string(), value));
+        }
+        entries
+    }
+}
+
+impl<'a> Bits<'a> {
+    fn bits(&mut self, n: usize) -> Option<u32> {
+        let mut v = 0;
+        for i in 0..n {
+            let byte = *self.data.get((self.pos + i) / 8)?;
+            v |= ((byte >> ((self.pos + i) % 8)) as u32 & 1) << i;
+        }
+        self.pos += n;
+        Some(v)
+    }
+
+    // U32(Bits(9), Bits(13), Bits(18), Bits(30)), plus one
+    fn dimension(&mut self) -> Option<u32> {
+        let n = [9, 13, 18, 30][self.bits(2)? as usize];
+        Some(self.bits(n)? + 1)
+    }
+
+    fn size_header(&mut self) -> Option<(u32, u32)> {
+        let div8 = self.bits(1)? == 1;
+        let height = if div8 { (self.bits(5)? + 1) * 8 } else { self.dimension()? };
+        let ratio = self.bits(3)?;
+        let width = match ratio {
+            0 => {
+                if div8 { (self.bits(5)? + 1) * 8 } else { self.dimension()? }
+            }
+            _ => {
+                let (num, den) = [(1, 1), (12, 10), (4, 3), (3, 2), (16, 9), (5, 4), (2, 1)][ratio as usize - 1];
+                (height as u64 * num / den) as u32
+            }
+        };
+        Some((width, height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // container with Brotli compressed Exif (the big-endian TIFF) and XMP boxes
+    const BROB: &[u8] = include_bytes!("../tests/data/brob.jxl");
+
+    #[test]
+    fn compressed_boxes_are_decoded() {
+        let jxl = JXL::load_data(BROB).unwrap();
+        assert!(jxl.container);
+        assert_eq!(jxl.compressed_boxes, ["Exif", "xml "]);
+        assert_eq!(jxl.exif.unwrap().values["Artist"], "Jane Doe");
+        assert!(matches!(jxl.xmp.unwrap().get("dc:title"), Some(XMPValue::Text(title)) if title == "The house by the river"));
+    }
+
+    #[test]
+    fn truncated_compressed_box() {
+        // the xml box loses its end; its declared size no longer fits the file
+        let jxl = JXL::load_data(&BROB[..BROB.len() - 20]).unwrap();
+        assert!(jxl.exif.is_some());
+        assert!(jxl.xmp.is_none());
+
+        // the Exif stream is cut short inside a box of consistent size
+        let exif_box = 32;
+        let size = be32(BROB, exif_box) as usize;
+        let mut data = BROB[..exif_box + size - 10].to_vec();
+        data[exif_box..exif_box + 4].copy_from_slice(&(size as u32 - 10).to_be_bytes());
+        let jxl = JXL::load_data(&data).unwrap();
+        assert_eq!(jxl.compressed_boxes, ["Exif"]);
+        assert!(jxl.exif.is_none());
+    }
+}
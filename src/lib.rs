@@ -0,0 +1,32 @@
+#![allow(non_snake_case, non_camel_case_types, non_upper_case_globals, clippy::upper_case_acronyms)]
+
+pub mod jfif;
+pub mod exif;
+pub mod exif_edit;
+pub mod strip;
+pub mod tiff;
+pub mod raw;
+pub mod bmff;
+pub mod cr3;
+pub mod raf;
+pub mod x3f;
+pub mod heif;
+pub mod png;
+pub mod webp;
+pub mod jxl;
+pub mod quicktime;
+pub mod psd;
+pub mod gif;
+pub mod bmp;
+pub mod ico;
+pub mod metadata;
+pub mod xmp;
+pub mod iptc;
+pub mod photoshop;
+pub mod icc;
+pub mod mpf;
+pub mod logger;
+mod bytes;
+mod md5;
+mod inflate;
+mod brotli;
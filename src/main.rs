@@ -1,41 +1,337 @@
-mod jfif;
-mod exif;
-mod logger;
-
 use std::env;
+use std::fs;
 use std::fs::File;
-use crate::jfif::*;
-//use crate::logger;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+use exif::jfif::*;
+use exif::icc::*;
+use exif::mpf::*;
+use exif::exif::*;
+use exif::metadata::*;
+use exif::strip::*;
+use exif::exif_edit::ExifIfd;
+//use exif::logger;
+
+struct Options {
+	show_segments: bool,
+	thumbnail_dir: Option<String>,
+	icc_dir: Option<String>,
+	mpf_dir: Option<String>,
+	preview_dir: Option<String>,
+}
 
 fn main() {
-	let args:Vec<String> = env::args().collect();
-	let mut cnt:i32 = 0;
-	for arg in args {
-		if cnt > 0 {
-			let f = match File::open(&arg) {
-				Err(e) => {
-					println!("cant open file [{}]: {}", arg, e);
-				}
-				Ok(mut f) => {
-					if let Some(jfif) = JFIF::load(&mut f) {
-/*
-						println!("version: {:04X}", jfif.version);
-						println!("unit: {}", jfif.unit);
-						println!("DPUX: {}", jfif.dots_par_unit_x);
-						println!("DPUY: {}", jfif.dots_par_unit_y);
-						println!("Thumnail Width: {}", jfif.thumnail_width);
-						println!("Thumnail Height: {}", jfif.thumnail_height);
-*/
-						if let Some(exif) = jfif.exif {
-							for (key, value) in exif.values {
-								println!("{}: {}", key, value);
-							}
+	let mut options = Options {
+		show_segments: false,
+		thumbnail_dir: None,
+		icc_dir: None,
+		mpf_dir: None,
+		preview_dir: None,
+	};
+	let mut files:Vec<String> = Vec::new();
+	let mut args = env::args().skip(1).peekable();
+	if args.peek().is_some_and(|arg| arg == "strip") {
+		args.next();
+		strip_files(args);
+		return;
+	}
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--segments" => {
+				options.show_segments = true;
+			}
+			"--thumbnails" => {
+				options.thumbnail_dir = args.next();
+			}
+			"--icc" => {
+				options.icc_dir = args.next();
+			}
+			"--extract-mpf" => {
+				options.mpf_dir = args.next();
+			}
+			"--preview" => {
+				options.preview_dir = args.next();
+			}
+			_ => {
+				files.push(arg);
+			}
+		}
+	}
+
+	for arg in files {
+		match File::open(&arg) {
+			Err(e) => {
+				println!("cant open file [{}]: {}", arg, e);
+			}
+			Ok(f) => {
+				let mut f = BufReader::new(f);
+				let metadata = match Metadata::load(&mut f) {
+					Some(metadata) => metadata,
+					None => {
+						// a truncated or broken JPEG still has a segment map
+						if options.show_segments && jpeg(&mut f, &arg, &options) {
+							continue;
 						}
+						println!("unknown file format [{}]", arg);
+						continue;
 					}
+				};
+				let structure = options.show_segments || options.mpf_dir.is_some();
+				if metadata.format == "JPEG" && structure && jpeg(&mut f, &arg, &options) {
+					continue;
 				}
-			};
+				show(&metadata, &mut f, &arg, &options);
+			}
+		};
+	}
+}
+
+// the segment walk and the MP images need the JPEG itself; true when only the segments are shown
+fn jpeg<R: Read + Seek>(f: &mut R, arg: &str, options: &Options) -> bool {
+	if f.rewind().is_err() {
+		return false;
+	}
+	let (jfif, complete) = JFIF::load_partial(f);
+	if jfif.segments().is_empty() {
+		return false;
+	}
+	if options.show_segments {
+		print_segments(&jfif);
+		if !complete {
+			println!("broken JPEG stream after the last segment [{}]", arg);
+		}
+		return true;
+	}
+	if !complete {
+		return false;
+	}
+	if let (Some(dir), Some(mpf)) = (&options.mpf_dir, &jfif.mpf) {
+		save_mp_images(mpf, f, arg, dir);
+	}
+	false
+}
+
+fn show<R: Read + Seek>(metadata: &Metadata, f: &mut R, arg: &str, options: &Options) {
+	if let Some(dir) = &options.thumbnail_dir {
+		save_thumbnails(&metadata.thumbnails, arg, dir);
+	}
+	if let (Some(dir), Some(icc)) = (&options.icc_dir, &metadata.icc) {
+		save_icc(icc, arg, dir);
+	}
+	if let (Some(dir), Some(preview)) = (&options.preview_dir, metadata.preview) {
+		match f.rewind().and_then(|_| preview(f)) {
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				println!("no preview in file [{}]", arg);
+			}
+			ret => {
+				write_preview(ret, arg, dir);
+			}
+		}
+	}
+
+	for (key, value) in &metadata.image_info {
+		println!("{}: {}", key, value);
+	}
+	if let Some(exif) = &metadata.exif {
+		print_exif(exif);
+	}
+	if let Some(xmp) = &metadata.xmp {
+		for (key, value) in xmp.entries() {
+			println!("XMP-{}: {}", key, value);
+		}
+	}
+	if let Some(iptc) = &metadata.iptc {
+		for (key, value) in &iptc.values {
+			println!("IPTC-{}: {}", key, value);
+		}
+	}
+	if let Some(icc) = &metadata.icc {
+		for (key, value) in icc.entries() {
+			println!("ICC-{}: {}", key, value);
 		}
-		cnt += 1;
+	}
+	for comment in &metadata.comments {
+		println!("Comment: {}", comment);
 	}
 }
 
+fn print_exif(exif: &EXIF) {
+	for (key, value) in &exif.values {
+		println!("{}: {}", key, value);
+	}
+}
+
+fn print_segments(jfif: &JFIF) {
+	for segment in jfif.segments() {
+		if segment.length > 0 {
+			println!("{:08X}: FF{:02X} {} length: {}", segment.offset, segment.marker, segment.segment_type, segment.length);
+		} else {
+			println!("{:08X}: FF{:02X} {}", segment.offset, segment.marker, segment.segment_type);
+		}
+	}
+}
+
+fn save_thumbnails(thumbnails: &[Thumbnail], file: &str, dir: &str) {
+	let stem = Path::new(file).file_stem().unwrap_or_default().to_string_lossy();
+	for (i, thumbnail) in thumbnails.iter().enumerate() {
+		let path = Path::new(dir).join(format!("{}_thumb{}.{}", stem, i, thumbnail.extension()));
+		match thumbnail.save(&path) {
+			Ok(_) => {
+				println!("Thumbnail: {} ({}x{})", path.display(), thumbnail.width, thumbnail.height);
+			}
+			Err(e) => {
+				println!("cant write file [{}]: {}", path.display(), e);
+			}
+		}
+	}
+}
+
+fn save_icc(icc: &ICC, file: &str, dir: &str) {
+	let stem = Path::new(file).file_stem().unwrap_or_default().to_string_lossy();
+	let path = Path::new(dir).join(format!("{}.icc", stem));
+	match icc.save(&path) {
+		Ok(_) => {
+			println!("ICC Profile: {}", path.display());
+		}
+		Err(e) => {
+			println!("cant write file [{}]: {}", path.display(), e);
+		}
+	}
+}
+
+fn write_preview(preview: io::Result<(Vec<u8>, u32, u32)>, file: &str, dir: &str) {
+	let stem = Path::new(file).file_stem().unwrap_or_default().to_string_lossy();
+	let path = Path::new(dir).join(format!("{}_preview.jpg", stem));
+	match preview.and_then(|(image, width, height)| fs::write(&path, image).map(|_| (width, height))) {
+		Ok((width, height)) => {
+			println!("Preview: {} ({}x{})", path.display(), width, height);
+		}
+		Err(e) => {
+			println!("cant extract preview [{}]: {}", path.display(), e);
+		}
+	}
+}
+
+// every image but the first one, which is the file itself
+fn save_mp_images<R: Read + Seek>(mpf: &MPF, f: &mut R, file: &str, dir: &str) {
+	let stem = Path::new(file).file_stem().unwrap_or_default().to_string_lossy();
+	for i in 1..mpf.images.len() {
+		let path = Path::new(dir).join(format!("{}_mp{}.jpg", stem, i + 1));
+		let ret = mpf.extract(f, i).and_then(|image| fs::write(&path, image));
+		match ret {
+			Ok(_) => {
+				println!("MP Image: {} ({})", path.display(), mpf.images[i].type_name());
+			}
+			Err(e) => {
+				println!("cant extract MP image {} [{}]: {}", i + 1, path.display(), e);
+			}
+		}
+	}
+}
+
+// exif strip [--policy all|gps|privacy|essential | [--allow <tags>] [--deny <tags>]] <dir> <filepath>...
+fn strip_files<I: Iterator<Item = String>>(mut args: I) {
+	let mut policy = StripPolicy::PRIVACY;
+	let mut allow = Vec::new();
+	let mut deny = Vec::new();
+	let mut custom = false;
+	let mut policy_given = false;
+	let mut paths:Vec<String> = Vec::new();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--policy" => {
+				policy = match args.next().as_deref() {
+					Some("all") => StripPolicy::ALL,
+					Some("gps") => StripPolicy::GPS,
+					Some("privacy") => StripPolicy::PRIVACY,
+					Some("essential") => StripPolicy::ESSENTIAL,
+					other => {
+						println!("unknown policy [{}]", other.unwrap_or_default());
+						return;
+					}
+				};
+				policy_given = true;
+			}
+			"--allow" | "--deny" => {
+				let tags = match args.next().as_deref().map(parse_tags) {
+					Some(Some(tags)) => tags,
+					_ => {
+						println!("bad tag list for {}", arg);
+						return;
+					}
+				};
+				if arg == "--allow" {
+					allow.extend(tags);
+				} else {
+					deny.extend(tags);
+				}
+				custom = true;
+			}
+			_ => {
+				paths.push(arg);
+			}
+		}
+	}
+	if custom && policy_given {
+		println!("--policy cant be combined with --allow or --deny");
+		return;
+	}
+	if custom {
+		policy = StripPolicy::CUSTOM { allow, deny };
+	}
+	if paths.len() < 2 {
+		println!("usage: exif strip [--policy all|gps|privacy|essential | [--allow <tags>] [--deny <tags>]] <dir> <filepath>...");
+		println!("  tags: [ifd0:|exif:|gps:|interop:|ifd1:]<number>, e.g. 0x0112,gps:0x0002; unscoped numbers apply to IFD0, Exif and IFD1");
+		return;
+	}
+	let dir = paths.remove(0);
+	for file in paths {
+		let name = Path::new(&file).file_name().unwrap_or_default();
+		let path = Path::new(&dir).join(name);
+		if fs::canonicalize(&path).is_ok_and(|p| fs::canonicalize(&file).is_ok_and(|q| p == q)) {
+			println!("cant overwrite file [{}]", file);
+			continue;
+		}
+		let mut out = Vec::new();
+		let ret = File::open(&file)
+			.and_then(|f| strip(&mut BufReader::new(f), &mut out, &policy))
+			.and_then(|_| fs::write(&path, &out));
+		match ret {
+			Ok(_) => {
+				println!("Stripped: {}", path.display());
+			}
+			Err(e) => {
+				println!("cant strip file [{}]: {}", file, e);
+			}
+		}
+	}
+}
+
+// comma separated tag numbers, hexadecimal with 0x or decimal
+// "gps:0x0002" for a tag of one IFD; GPS and Interoperability tags need the prefix since their
+// numbers collide with others, so a bare number stands for IFD0, Exif and IFD1
+fn parse_tags(list: &str) -> Option<Vec<(ExifIfd, u16)>> {
+	let mut tags = Vec::new();
+	for tag in list.split(',') {
+		let tag = tag.trim();
+		let (ifds, number) = match tag.split_once(':') {
+			Some(("ifd0", number)) => (vec![ExifIfd::IFD0], number),
+			Some(("exif", number)) => (vec![ExifIfd::EXIF], number),
+			Some(("gps", number)) => (vec![ExifIfd::GPS], number),
+			Some(("interop", number)) => (vec![ExifIfd::INTEROP], number),
+			Some(("ifd1", number)) => (vec![ExifIfd::IFD1], number),
+			Some(_) => {
+				return None;
+			}
+			None => (vec![ExifIfd::IFD0, ExifIfd::EXIF, ExifIfd::IFD1], tag),
+		};
+		let number = match number.strip_prefix("0x").or_else(|| number.strip_prefix("0X")) {
+			Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+			None => number.parse().ok()?,
+		};
+		tags.extend(ifds.into_iter().map(|ifd| (ifd, number)));
+	}
+	Some(tags)
+}
@@ -0,0 +1,414 @@
+use std::io;
+use std::io::prelude::*;
+use crate::jfif::*;
+use crate::exif::*;
+use crate::xmp::*;
+use crate::iptc::*;
+use crate::icc::*;
+use crate::raw::*;
+use crate::cr3::*;
+use crate::heif::*;
+use crate::quicktime::*;
+use crate::png::*;
+use crate::webp::*;
+use crate::jxl::*;
+use crate::psd::*;
+use crate::raf::*;
+use crate::x3f::*;
+use crate::gif::*;
+use crate::bmp::*;
+use crate::ico::*;
+use crate::logger;
+
+/// What every container reader found, in one shape.
+pub struct Metadata {
+    /// "JPEG", "PNG", "DNG", ...
+    pub format: String,
+    pub exif: Option<EXIF>,
+    pub xmp: Option<XMP>,
+    pub iptc: Option<Iptc>,
+    pub icc: Option<ICC>,
+    /// container properties, named with the container prefix ("PNG-ImageWidth", "RAW-Preview1", ...)
+    pub image_info: Vec<(String, String)>,
+    pub comments: Vec<String>,
+    pub thumbnails: Vec<Thumbnail>,
+    /// extracts the largest embedded JPEG, for raw formats
+    pub preview: Option<PreviewFn>,
+}
+
+/// Any seekable reader, so the registry can hold plain function pointers.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A container format: how to recognize it and how to read it.
+pub struct Format {
+    pub name: &'static str,
+    /// whether the first `MAGIC_SIZE` bytes may belong to the format
+    pub detect: fn(&[u8]) -> bool,
+    pub load: fn(&mut dyn ReadSeek) -> Option<Metadata>,
+    pub preview: Option<PreviewFn>,
+}
+
+/// The largest embedded JPEG with its width and height.
+pub type PreviewFn = fn(&mut dyn ReadSeek) -> io::Result<(Vec<u8>, u32, u32)>;
+
+pub const MAGIC_SIZE: usize = 16;
+
+/// Known formats, tried in this order; formats sharing a magic fall through to the next one.
+pub static FORMATS: [Format; 14] = [
+    Format {
+        name: "JPEG",
+        detect: |magic| magic.starts_with(&[0xFF, 0xD8]),
+        load: jpeg,
+        preview: None,
+    },
+    Format {
+        name: "TIFF",
+        detect: |magic| matches!(magic,
+            [0x49, 0x49, 0x2A, 0x00, ..] | [0x4D, 0x4D, 0x00, 0x2A, ..] |
+            [0x49, 0x49, 0x2B, 0x00, ..] | [0x4D, 0x4D, 0x00, 0x2B, ..] |
+            [0x49, 0x49, 0x52, 0x4F, ..] | [0x49, 0x49, 0x52, 0x53, ..] | [0x4D, 0x4D, 0x4F, 0x52, ..] |
+            [0x49, 0x49, 0x55, 0x00, ..]),
+        load: tiff,
+        preview: Some(raw_preview),
+    },
+    Format {
+        name: "PNG",
+        detect: |magic| magic.starts_with(b"\x89PNG"),
+        load: png,
+        preview: None,
+    },
+    Format {
+        name: "WebP",
+        detect: |magic| matches!(magic, [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..]),
+        load: webp,
+        preview: None,
+    },
+    Format {
+        name: "JXL",
+        detect: |magic| magic.starts_with(b"\0\0\0\x0CJXL \x0D\x0A\x87\x0A") || magic.starts_with(&[0xFF, 0x0A]),
+        load: jxl,
+        preview: None,
+    },
+    Format {
+        name: "CR3",
+        detect: |magic| matches!(magic, [_, _, _, _, b'f', b't', b'y', b'p', b'c', b'r', b'x', b' ', ..]),
+        load: cr3,
+        preview: None,
+    },
+    Format {
+        name: "HEIF",
+        detect: |magic| matches!(magic, [_, _, _, _, b'f', b't', b'y', b'p', ..]),
+        load: heif,
+        preview: None,
+    },
+    Format {
+        name: "QuickTime",
+        // MP4, or QuickTime movies without 'ftyp'
+        detect: |magic| matches!(magic.get(4..8), Some(b"ftyp" | b"moov" | b"mdat" | b"wide" | b"free" | b"skip")),
+        load: quicktime,
+        preview: None,
+    },
+    Format {
+        name: "PSD",
+        detect: |magic| magic.starts_with(b"8BPS"),
+        load: psd,
+        preview: None,
+    },
+    Format {
+        name: "RAF",
+        detect: |magic| magic.starts_with(b"FUJIFILM"),
+        load: raf,
+        preview: Some(raf_preview),
+    },
+    Format {
+        name: "X3F",
+        detect: |magic| magic.starts_with(b"FOVb"),
+        load: x3f,
+        preview: Some(x3f_preview),
+    },
+    Format {
+        name: "GIF",
+        detect: |magic| magic.starts_with(b"GIF87a") || magic.starts_with(b"GIF89a"),
+        load: gif,
+        preview: None,
+    },
+    Format {
+        name: "BMP",
+        detect: |magic| magic.starts_with(b"BM"),
+        load: bmp,
+        preview: None,
+    },
+    // weak magics, after the ISO Base Media formats whose box sizes may start the same
+    Format {
+        name: "ICO",
+        detect: |magic| matches!(magic, [0x00, 0x00, 0x01 | 0x02, 0x00, ..]),
+        load: ico,
+        preview: None,
+    },
+];
+
+impl Metadata {
+    fn new(format: &str) -> Metadata {
+        Metadata {
+            format: format.to_string(),
+            exif: None,
+            xmp: None,
+            iptc: None,
+            icc: None,
+            image_info: Vec::new(),
+            comments: Vec::new(),
+            thumbnails: Vec::new(),
+            preview: None,
+        }
+    }
+
+    /// Sniffs the first bytes and reads the file with the first format that accepts it.
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<Metadata> {
+        let mut magic = Vec::new();
+        if f.take(MAGIC_SIZE as u64).read_to_end(&mut magic).is_err() {
+            logger::debug("cant read magic");
+            return None;
+        }
+        let mut f:&mut dyn ReadSeek = f;
+        for format in detect(&magic) {
+            f.rewind().ok()?;
+            if let Some(mut metadata) = (format.load)(&mut f) {
+                metadata.preview = format.preview;
+                return Some(metadata);
+            }
+            logger::debug(format!("not {}", format.name).as_str());
+        }
+        None
+    }
+
+    fn info(&mut self, prefix: &str, entries: Vec<(String, String)>) {
+        for (key, value) in entries {
+            self.image_info.push((format!("{}-{}", prefix, key), value));
+        }
+    }
+}
+
+/// Formats whose magic matches the first bytes of a file, in the order to try them.
+pub fn detect(magic: &[u8]) -> impl Iterator<Item = &'static Format> + '_ {
+    FORMATS.iter().filter(move |format| (format.detect)(magic))
+}
+
+fn jpeg(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let jfif = JFIF::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("JPEG");
+    metadata.image_info = frame_info(&jfif);
+    if let Some(mpf) = &jfif.mpf {
+        metadata.info("MPF", mpf.entries());
+    }
+    metadata.exif = jfif.exif;
+    metadata.xmp = jfif.xmp;
+    metadata.iptc = jfif.iptc;
+    metadata.icc = jfif.icc;
+    metadata.comments = jfif.comments;
+    metadata.thumbnails = jfif.thumbnails;
+    Some(metadata)
+}
+
+fn frame_info(jfif: &JFIF) -> Vec<(String, String)> {
+    let mut info = Vec::new();
+    if let Some(frame) = &jfif.frame {
+        info.push(("JPEG-ImageWidth".to_string(), frame.width.to_string()));
+        info.push(("JPEG-ImageHeight".to_string(), frame.height.to_string()));
+        info.push(("JPEG-BitsPerSample".to_string(), frame.precision.to_string()));
+        info.push(("JPEG-ColorComponents".to_string(), frame.components.len().to_string()));
+        info.push(("JPEG-ColorModel".to_string(), jfif.color_model().to_string()));
+    }
+    if let Some(adobe) = &jfif.adobe {
+        info.push(("Adobe-DCTEncodeVersion".to_string(), adobe.dct_encode_version.to_string()));
+        info.push(("Adobe-Flags0".to_string(), format!("{:04X}", adobe.flags0)));
+        info.push(("Adobe-Flags1".to_string(), format!("{:04X}", adobe.flags1)));
+        info.push(("Adobe-ColorTransform".to_string(), adobe.color_transform.to_string()));
+    }
+    info
+}
+
+fn tiff(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let raw = RAW::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new(&raw.format.to_string());
+    if !matches!(raw.format, RawFormat::TIFF) {
+        metadata.image_info.push(("RAW-Format".to_string(), raw.format.to_string()));
+    }
+    for image in &raw.tiff.images {
+        metadata.image_info.push((format!("TIFF-{}", image.name), image.summary()));
+    }
+    for (i, preview) in raw.previews.iter().enumerate() {
+        metadata.image_info.push((format!("RAW-Preview{}", i + 1), format!("{}x{} JPEG, {} bytes at 0x{:X} ({})",
+            preview.width, preview.height, preview.length, preview.offset, preview.ifd)));
+    }
+    metadata.exif = raw.tiff.exif;
+    Some(metadata)
+}
+
+fn raw_preview(f: &mut dyn ReadSeek) -> io::Result<(Vec<u8>, u32, u32)> {
+    let raw = match RAW::load(&mut &mut *f) {
+        Some(raw) => raw,
+        None => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a raw file"));
+        }
+    };
+    let index = match raw.largest_preview() {
+        Some(index) => index,
+        None => {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no preview"));
+        }
+    };
+    let image = raw.extract_preview(&mut &mut *f, index, CarryOver::EXIF)?;
+    Ok((image, raw.previews[index].width as u32, raw.previews[index].height as u32))
+}
+
+fn cr3(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let cr3 = CR3::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("CR3");
+    metadata.image_info.push(("RAW-Format".to_string(), "CR3".to_string()));
+    metadata.exif = cr3.exif;
+    metadata.xmp = cr3.xmp;
+    Some(metadata)
+}
+
+fn heif(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let heif = HEIF::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("HEIF");
+    metadata.info("HEIF", heif.entries());
+    metadata.exif = heif.exif;
+    metadata.xmp = heif.xmp;
+    metadata.icc = heif.icc;
+    Some(metadata)
+}
+
+fn quicktime(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let movie = QuickTime::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("QuickTime");
+    metadata.info("QuickTime", movie.entries());
+    metadata.exif = movie.exif;
+    metadata.xmp = movie.xmp;
+    Some(metadata)
+}
+
+fn png(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let png = PNG::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("PNG");
+    for chunk in &png.crc_errors {
+        metadata.image_info.push(("PNG-CRCError".to_string(), chunk.clone()));
+    }
+    metadata.info("PNG", png.entries());
+    metadata.exif = png.exif;
+    metadata.xmp = png.xmp;
+    metadata.icc = png.icc;
+    Some(metadata)
+}
+
+fn webp(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let webp = WebP::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("WebP");
+    metadata.info("WebP", webp.entries());
+    metadata.exif = webp.exif;
+    metadata.xmp = webp.xmp;
+    metadata.icc = webp.icc;
+    Some(metadata)
+}
+
+fn jxl(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let jxl = JXL::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("JXL");
+    metadata.info("JXL", jxl.entries());
+    metadata.exif = jxl.exif;
+    metadata.xmp = jxl.xmp;
+    Some(metadata)
+}
+
+fn psd(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let psd = PSD::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new(if psd.version == 2 { "PSB" } else { "PSD" });
+    metadata.info("PSD", psd.entries());
+    if let Some(thumbnail) = &psd.thumbnail {
+        metadata.image_info.push(("PSD-Thumbnail".to_string(),
+            format!("{}x{} JPEG, {} bytes", thumbnail.width, thumbnail.height, thumbnail.data.len())));
+    }
+    metadata.exif = psd.exif;
+    metadata.xmp = psd.xmp;
+    metadata.iptc = psd.iptc;
+    metadata.icc = psd.icc;
+    metadata.thumbnails.extend(psd.thumbnail);
+    Some(metadata)
+}
+
+fn raf(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let raf = RAF::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("RAF");
+    metadata.image_info.push(("RAW-Format".to_string(), "RAF".to_string()));
+    metadata.info("RAF", raf.entries());
+    if let Some(jpeg) = raf.jpeg {
+        metadata.image_info.extend(frame_info(&jpeg));
+        metadata.exif = jpeg.exif;
+        metadata.xmp = jpeg.xmp;
+    }
+    Some(metadata)
+}
+
+fn raf_preview(f: &mut dyn ReadSeek) -> io::Result<(Vec<u8>, u32, u32)> {
+    let raf = match RAF::load(&mut &mut *f) {
+        Some(raf) => raf,
+        None => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a RAF file"));
+        }
+    };
+    let (width, height) = raf.jpeg.as_ref().and_then(|jpeg| jpeg.frame.as_ref())
+        .map_or((0, 0), |frame| (frame.width as u32, frame.height as u32));
+    Ok((raf.extract_preview(&mut &mut *f)?, width, height))
+}
+
+fn x3f(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let x3f = X3F::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("X3F");
+    metadata.image_info.push(("RAW-Format".to_string(), "X3F".to_string()));
+    metadata.info("X3F", x3f.entries());
+    for (i, preview) in x3f.previews.iter().enumerate() {
+        metadata.image_info.push((format!("RAW-Preview{}", i + 1),
+            format!("{}x{} JPEG, {} bytes at 0x{:X}", preview.width, preview.height, preview.length, preview.offset)));
+    }
+    Some(metadata)
+}
+
+fn x3f_preview(f: &mut dyn ReadSeek) -> io::Result<(Vec<u8>, u32, u32)> {
+    let x3f = match X3F::load(&mut &mut *f) {
+        Some(x3f) => x3f,
+        None => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an X3F file"));
+        }
+    };
+    let (width, height) = x3f.previews.iter().max_by_key(|p| p.width as u64 * p.height as u64)
+        .map_or((0, 0), |preview| (preview.width, preview.height));
+    Ok((x3f.extract_preview(&mut &mut *f)?, width, height))
+}
+
+fn gif(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let gif = GIF::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("GIF");
+    metadata.info("GIF", gif.entries());
+    metadata.xmp = gif.xmp;
+    metadata.icc = gif.icc;
+    metadata.comments = gif.comments;
+    Some(metadata)
+}
+
+fn bmp(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let bmp = BMP::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new("BMP");
+    metadata.info("BMP", bmp.entries());
+    metadata.icc = bmp.icc;
+    Some(metadata)
+}
+
+fn ico(f: &mut dyn ReadSeek) -> Option<Metadata> {
+    let ico = ICO::load(&mut &mut *f)?;
+    let mut metadata = Metadata::new(if ico.cursor { "CUR" } else { "ICO" });
+    metadata.info("ICO", ico.entries());
+    Some(metadata)
+}
@@ -0,0 +1,314 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use crate::exif::*;
+use crate::logger;
+
+/// Multi-Picture Format index (CIPA DC-007) from the APP2 "MPF" segment.
+pub struct MPF {
+    pub version: String,
+    pub images: Vec<MPImage>,
+    /// MP Attribute IFD of the first image, those of the others are in `MPImage::attributes`
+    pub attributes: Vec<(String, String)>,
+    /// file offset of the MP header, image offsets are relative to it
+    pub base_offset: u64,
+}
+
+pub struct MPImage {
+    pub attribute: u32,
+    pub size: u32,
+    /// relative to `MPF::base_offset`, 0 for the first image
+    pub offset: u32,
+    pub dependent1: u16,
+    pub dependent2: u16,
+    /// MP Attribute IFD from the APP2 "MPF" segment of the image itself, for all but the first
+    pub attributes: Vec<(String, String)>,
+}
+
+impl MPF {
+    /// `data` starts at the MP header (after "MPF\0"), found at `base_offset` in the file.
+    pub fn load(data: &[u8], base_offset: u64) -> Option<MPF> {
+        let mut src = data;
+        let header = read_header(&mut src)?;
+        let endian = header.byte_order;
+        let index = read_ifd(&mut src, header.offset, &endian, false)?;
+
+        let mut mpf = MPF {
+            version: String::new(),
+            images: Vec::new(),
+            attributes: Vec::new(),
+            base_offset,
+        };
+
+        if let Some(version) = index.get(0xB000) {
+            mpf.version = String::from_utf8_lossy(&version.data).to_string();
+        }
+        let count = index.get(0xB001).and_then(|e| e.u32_value(&endian)).unwrap_or(0) as usize;
+        if let Some(entries) = index.get(0xB002) {
+            for entry in entries.data.chunks_exact(16).take(count) {
+                mpf.images.push(MPImage {
+                    attribute: read_4bytes(entry, 0, &endian)?,
+                    size: read_4bytes(entry, 4, &endian)?,
+                    offset: read_4bytes(entry, 8, &endian)?,
+                    dependent1: read_2bytes(entry, 12, &endian)?,
+                    dependent2: read_2bytes(entry, 14, &endian)?,
+                    attributes: Vec::new(),
+                });
+            }
+        }
+        if mpf.images.len() != count {
+            logger::debug("MP entry count mismatch");
+        }
+
+        if index.next != 0 {
+            if let Some(attributes) = read_ifd(&mut src, index.next, &endian, false) {
+                mpf.attributes = attribute_values(&attributes, &endian);
+            }
+        }
+
+        Some(mpf)
+    }
+
+    /// File offset of an image.
+    pub fn image_offset(&self, index: usize) -> Option<u64> {
+        let image = self.images.get(index)?;
+        if image.offset == 0 {
+            Some(0)
+        } else {
+            Some(self.base_offset + image.offset as u64)
+        }
+    }
+
+    /// Reads an image out of the file the index was loaded from.
+    pub fn extract<R: Read + Seek>(&self, f: &mut R, index: usize) -> io::Result<Vec<u8>> {
+        let offset = match self.image_offset(index) {
+            Some(offset) => offset,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no such MP image"));
+            }
+        };
+        let size = self.images[index].size as u64;
+        let file_size = f.seek(SeekFrom::End(0))?;
+        if offset.checked_add(size).is_none_or(|end| end > file_size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "MP image beyond the end of the file"));
+        }
+        let mut image = vec![0; size as usize];
+        f.seek(SeekFrom::Start(offset))?;
+        f.read_exact(&mut image)?;
+        if !image.starts_with(&[0xFF, 0xD8]) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "MP image is not JPEG"));
+        }
+        Ok(image)
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("MPFVersion".to_string(), self.version.clone()),
+            ("NumberOfImages".to_string(), self.images.len().to_string()),
+        ];
+        for (i, image) in self.images.iter().enumerate() {
+            entries.push((
+                format!("MPImage{}", i + 1),
+                format!("{}, {} bytes at 0x{:X}", image.type_name(), image.size, self.image_offset(i).unwrap_or(0)),
+            ));
+        }
+        entries.extend(self.attributes.iter().cloned());
+        for (i, image) in self.images.iter().enumerate() {
+            for (key, value) in &image.attributes {
+                entries.push((format!("MPImage{}-{}", i + 1, key), value.clone()));
+            }
+        }
+        entries
+    }
+}
+
+/// Updates the MP Entries of an MP header (after "MPF\0") once the segments of the first
+/// image have changed size: `first_delta` bytes for the whole first image, of which
+/// `header_delta` come before the MP header, which the offsets of the other images are relative to.
+pub(crate) fn shift_entries(data: &mut [u8], first_delta: i64, header_delta: i64) -> Option<()> {
+    let shift = |value: u32, delta: i64| u32::try_from(value as i64 + delta).ok();
+    update_entries(data, |i, size, offset| {
+        if i == 0 {
+            Some((shift(size, first_delta)?, offset))
+        } else if offset != 0 {
+            Some((size, shift(offset, first_delta - header_delta)?))
+        } else {
+            Some((size, offset))
+        }
+    })
+}
+
+/// Replaces the size and offset of each MP Entry of an MP header (after "MPF\0") with what
+/// `update` returns for its index, size and offset.
+pub(crate) fn update_entries<F>(data: &mut [u8], mut update: F) -> Option<()>
+    where F: FnMut(usize, u32, u32) -> Option<(u32, u32)>
+{
+    let endian = match data.get(0..2)? {
+        b"MM" => ENDIAN::BIG_ENDIAN,
+        b"II" => ENDIAN::LITTLE_ENDIAN,
+        _ => {
+            return None;
+        }
+    };
+    let index = read_4bytes(data, 4, &endian)? as usize;
+    let count = read_2bytes(data, index, &endian)? as usize;
+    let entry = (0..count).map(|i| index + 2 + i * 12)
+        .find(|&entry| read_2bytes(data, entry, &endian) == Some(0xB002))?;
+    let length = read_4bytes(data, entry + 4, &endian)? as usize;
+    let start = read_4bytes(data, entry + 8, &endian)? as usize;
+    for i in 0..length / 16 {
+        let at = start + i * 16;
+        let (size, offset) = (read_4bytes(data, at + 4, &endian)?, read_4bytes(data, at + 8, &endian)?);
+        let (size, offset) = update(i, size, offset)?;
+        let mut field = Vec::new();
+        put_4bytes(&mut field, size, &endian);
+        put_4bytes(&mut field, offset, &endian);
+        data.get_mut(at + 4..at + 12)?.copy_from_slice(&field);
+    }
+    Some(())
+}
+
+/// MP Attribute IFD of an image other than the first, from the payload of its APP2 "MPF"
+/// segment after the signature; there the attribute IFD comes first, without an index IFD.
+pub(crate) fn load_attributes(data: &[u8]) -> Option<Vec<(String, String)>> {
+    let mut src = data;
+    let header = read_header(&mut src)?;
+    let endian = header.byte_order;
+    let mut ifd = read_ifd(&mut src, header.offset, &endian, false)?;
+    if ifd.get(0xB002).is_some() && ifd.next != 0 {
+        ifd = read_ifd(&mut src, ifd.next, &endian, false)?;
+    }
+    Some(attribute_values(&ifd, &endian))
+}
+
+impl MPImage {
+    pub fn type_code(&self) -> u32 {
+        self.attribute & 0x00FFFFFF
+    }
+
+    pub fn is_representative(&self) -> bool {
+        self.attribute & 0x20000000 != 0
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.type_code() {
+            0x030000 => "Baseline MP Primary Image",
+            0x010001 => "Large Thumbnail (VGA)",
+            0x010002 => "Large Thumbnail (Full HD)",
+            0x020001 => "Multi-frame Panorama",
+            0x020002 => "Multi-frame Disparity",
+            0x020003 => "Multi-frame Multi-angle",
+            0x000000 => "Undefined",
+            _ => "Unknown",
+        }
+    }
+}
+
+fn attribute_values(ifd: &Ifd, endian: &ENDIAN) -> Vec<(String, String)> {
+    let mut values = Vec::new();
+    for entry in &ifd.entries {
+        let name = match entry.tag {
+            0xB000 => "MPFVersion",
+            0xB101 => "MPIndividualNum",
+            0xB201 => "PanOrientation",
+            0xB202 => "PanOverlapH",
+            0xB203 => "PanOverlapV",
+            0xB204 => "BaseViewpointNum",
+            0xB205 => "ConvergenceAngle",
+            0xB206 => "BaselineLength",
+            0xB207 => "VerticalDivergence",
+            0xB208 => "AxisDistanceX",
+            0xB209 => "AxisDistanceY",
+            0xB20A => "AxisDistanceZ",
+            0xB20B => "YawAngle",
+            0xB20C => "PitchAngle",
+            0xB20D => "RollAngle",
+            _ => {
+                logger::debug(format!("unknown MP attribute {:04X}", entry.tag).as_str());
+                continue;
+            }
+        };
+        let value = match entry.ifd_type {
+            5 | 10 => {
+                let top = read_4bytes(&entry.data, 0, endian);
+                let bottom = read_4bytes(&entry.data, 4, endian);
+                match (top, bottom) {
+                    (Some(top), Some(bottom)) if entry.ifd_type == 10 => format!("{}/{}", top as i32, bottom as i32),
+                    (Some(top), Some(bottom)) => format!("{}/{}", top, bottom),
+                    _ => continue,
+                }
+            }
+            7 => String::from_utf8_lossy(&entry.data).to_string(),
+            _ => match entry.u32_value(endian) {
+                Some(v) => v.to_string(),
+                None => continue,
+            },
+        };
+        values.push((name.to_string(), value));
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // big endian MP header: MPFVersion, NumberOfImages and the MP Entries of two images,
+    // the second one 0x40 bytes after the header and 6 bytes long
+    fn header() -> Vec<u8> {
+        let mut data = b"MM\0\x2A\0\0\0\x08\0\x03".to_vec();
+        data.extend_from_slice(b"\xB0\x00\0\x07\0\0\0\x040100");
+        data.extend_from_slice(b"\xB0\x01\0\x04\0\0\0\x01\0\0\0\x02");
+        data.extend_from_slice(b"\xB0\x02\0\x07\0\0\0\x20\0\0\0\x32");
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(b"\x20\x03\0\0\0\0\x01\0\0\0\0\0\0\0\0\0");
+        data.extend_from_slice(b"\0\x01\0\x01\0\0\0\x06\0\0\0\x40\0\0\0\0");
+        data
+    }
+
+    #[test]
+    fn index_is_loaded() {
+        let mpf = MPF::load(&header(), 0x20).unwrap();
+        assert_eq!(mpf.version, "0100");
+        assert_eq!(mpf.images.len(), 2);
+        assert!(mpf.images[0].is_representative());
+        assert_eq!(mpf.images[0].size, 0x100);
+        assert_eq!(mpf.images[1].type_name(), "Large Thumbnail (VGA)");
+        assert_eq!(mpf.image_offset(1), Some(0x60));
+    }
+
+    #[test]
+    fn truncated_index() {
+        let data = header();
+        assert!(MPF::load(&data[..20], 0).is_none());
+        // the entries are cut off
+        let mpf = MPF::load(&data[..70], 0).unwrap();
+        assert!(mpf.images.is_empty());
+        assert!(shift_entries(&mut data[..70].to_vec(), 1, 1).is_none());
+    }
+
+    #[test]
+    fn extract_checks_the_file_size() {
+        let mpf = MPF::load(&header(), 0x20).unwrap();
+        let mut file = vec![0; 0x60];
+        file.extend_from_slice(b"\xFF\xD8\0\0\xFF\xD9");
+        assert_eq!(mpf.extract(&mut Cursor::new(&file), 1).unwrap(), b"\xFF\xD8\0\0\xFF\xD9");
+        file.pop();
+        let e = mpf.extract(&mut Cursor::new(&file), 1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(mpf.extract(&mut Cursor::new(&file), 2).is_err());
+    }
+
+    #[test]
+    fn entries_are_shifted() {
+        let mut data = header();
+        shift_entries(&mut data, 10, 4).unwrap();
+        let mpf = MPF::load(&data, 0x24).unwrap();
+        assert_eq!(mpf.images[0].size, 0x10A);
+        assert_eq!(mpf.images[0].offset, 0);
+        assert_eq!(mpf.images[1].size, 6);
+        assert_eq!(mpf.images[1].offset, 0x46);
+        assert!(shift_entries(&mut data, -0x200, 0).is_none());
+    }
+}
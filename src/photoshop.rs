@@ -0,0 +1,63 @@
+use crate::logger;
+use crate::bytes::*;
+
+/// Photoshop image resource block ("8BIM" + id + name + data).
+pub struct ImageResource {
+    pub id: u16,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+pub const RESOURCE_IPTC: u16 = 0x0404;
+pub const RESOURCE_THUMBNAIL: u16 = 0x040C;
+pub const RESOURCE_ICC: u16 = 0x040F;
+pub const RESOURCE_EXIF1: u16 = 0x0422;
+pub const RESOURCE_EXIF3: u16 = 0x0423;
+pub const RESOURCE_XMP: u16 = 0x0424;
+
+/// Parses a list of image resource blocks, stopping at the first broken one.
+pub fn load_resources(data: &[u8]) -> Vec<ImageResource> {
+    let mut resources = Vec::new();
+    let mut offset:usize = 0;
+
+    while offset + 12 <= data.len() {
+        let signature = &data[offset..offset+4];
+        if !matches!(signature, b"8BIM" | b"MeSa" | b"PHUT" | b"AgHg" | b"DCSR") {
+            logger::debug("not 8BIM");
+            break;
+        }
+        let id = be16(data, offset+4);
+        offset += 6;
+
+        // Pascal string, padded to even size
+        let name_len = data[offset] as usize;
+        let name = match data.get(offset+1..offset+1+name_len) {
+            Some(name) => String::from_utf8_lossy(name).to_string(),
+            None => break,
+        };
+        offset += (1 + name_len + 1) & !1;
+
+        let size = match data.get(offset..offset+4) {
+            Some(b) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize,
+            None => break,
+        };
+        offset += 4;
+        let body = match data.get(offset..offset+size) {
+            Some(body) => body,
+            None => {
+                logger::debug("short image resource");
+                break;
+            }
+        };
+        offset += (size + 1) & !1;
+
+        logger::debug(format!("image resource {:04X} size: {}", id, size).as_str());
+        resources.push(ImageResource {
+            id,
+            name,
+            data: body.to_vec(),
+        });
+    }
+
+    resources
+}
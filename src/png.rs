@@ -0,0 +1,323 @@
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use crate::exif::*;
+use crate::xmp::*;
+use crate::icc::*;
+use crate::inflate::*;
+use crate::logger;
+use crate::bytes::*;
+
+/// PNG image: IHDR, the metadata chunks and the text chunks.
+pub struct PNG {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub interlace: u8,
+    /// eXIf
+    pub exif: Option<EXIF>,
+    /// iTXt "XML:com.adobe.xmp"
+    pub xmp: Option<XMP>,
+    /// iCCP, with the profile name
+    pub icc: Option<ICC>,
+    pub icc_name: Option<String>,
+    /// tEXt, zTXt and iTXt keywords and text, in file order
+    pub texts: Vec<(String, String)>,
+    /// pHYs: pixels per unit x, y and the unit (1: metre)
+    pub physical: Option<(u32, u32, u8)>,
+    /// tIME, "YYYY:MM:DD HH:MM:SS" in UTC
+    pub modified: Option<String>,
+    /// gAMA, times 100000
+    pub gamma: Option<u32>,
+    /// cHRM white point and red, green, blue x/y, times 100000
+    pub chromaticities: Option<[u32; 8]>,
+    /// sRGB rendering intent
+    pub srgb_intent: Option<u8>,
+    /// chunks whose CRC did not match, skipped
+    pub crc_errors: Vec<String>,
+}
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1A\n";
+
+// larger chunks are not metadata
+const MAX_CHUNK: usize = 1 << 26;
+
+impl PNG {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<PNG> {
+        let mut signature = [0; 8];
+        f.read_exact(&mut signature).ok()?;
+        if signature != SIGNATURE {
+            logger::debug("not PNG");
+            return None;
+        }
+
+        let mut png = PNG {
+            width: 0,
+            height: 0,
+            bit_depth: 0,
+            color_type: 0,
+            interlace: 0,
+            exif: None,
+            xmp: None,
+            icc: None,
+            icc_name: None,
+            texts: Vec::new(),
+            physical: None,
+            modified: None,
+            gamma: None,
+            chromaticities: None,
+            srgb_intent: None,
+            crc_errors: Vec::new(),
+        };
+
+        loop {
+            let mut head = [0; 8];
+            if f.read_exact(&mut head).is_err() {
+                logger::debug("no IEND");
+                break;
+            }
+            let len = be32(&head, 0);
+            let chunk_type = [head[4], head[5], head[6], head[7]];
+            match &chunk_type {
+                b"IEND" => {
+                    break;
+                }
+                // image data is neither needed nor checked
+                b"IDAT" => {
+                    f.seek(SeekFrom::Current(len as i64 + 4)).ok()?;
+                    continue;
+                }
+                _ => {}
+            }
+            if len as usize > MAX_CHUNK {
+                logger::debug("chunk too large, skipped");
+                f.seek(SeekFrom::Current(len as i64 + 4)).ok()?;
+                continue;
+            }
+
+            let mut data = vec![0; len as usize + 4];
+            if f.read_exact(&mut data).is_err() {
+                logger::debug("short chunk");
+                break;
+            }
+            let crc = be32(&data, len as usize);
+            data.truncate(len as usize);
+            if crc32(&chunk_type, &data) != crc {
+                png.crc_errors.push(String::from_utf8_lossy(&chunk_type).to_string());
+                continue;
+            }
+            png.chunk(&chunk_type, &data);
+        }
+
+        Some(png)
+    }
+
+    fn chunk(&mut self, chunk_type: &[u8; 4], data: &[u8]) {
+        match chunk_type {
+            b"IHDR" => {
+                if data.len() >= 13 {
+                    self.width = be32(data, 0);
+                    self.height = be32(data, 4);
+                    self.bit_depth = data[8];
+                    self.color_type = data[9];
+                    self.interlace = data[12];
+                }
+            }
+            b"eXIf" => {
+                // a few writers keep the JPEG APP1 signature
+                let tiff = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+                self.exif = EXIF::load(tiff);
+            }
+            b"tEXt" => {
+                if let Some((keyword, text)) = split_keyword(data) {
+                    self.texts.push((keyword, latin1(text)));
+                }
+            }
+            b"zTXt" => {
+                match split_keyword(data) {
+                    Some((keyword, [0, compressed @ ..])) => {
+                        if let Some(text) = zlib_decompress(compressed) {
+                            self.texts.push((keyword, latin1(&text)));
+                        }
+                    }
+                    _ => {
+                        logger::debug("broken zTXt");
+                    }
+                }
+            }
+            b"iTXt" => {
+                self.international_text(data);
+            }
+            b"iCCP" => {
+                match split_keyword(data) {
+                    Some((name, [0, compressed @ ..])) => {
+                        self.icc = zlib_decompress(compressed).and_then(|profile| ICC::load(&profile));
+                        self.icc_name = Some(name);
+                    }
+                    _ => {
+                        logger::debug("broken iCCP");
+                    }
+                }
+            }
+            b"pHYs" => {
+                if data.len() >= 9 {
+                    self.physical = Some((be32(data, 0), be32(data, 4), data[8]));
+                }
+            }
+            b"tIME" => {
+                if data.len() >= 7 {
+                    self.modified = Some(format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+                        (data[0] as u16) << 8 | data[1] as u16, data[2], data[3], data[4], data[5], data[6]));
+                }
+            }
+            b"gAMA" => {
+                if data.len() >= 4 {
+                    self.gamma = Some(be32(data, 0));
+                }
+            }
+            b"cHRM" => {
+                if data.len() >= 32 {
+                    let mut values = [0; 8];
+                    for (i, v) in values.iter_mut().enumerate() {
+                        *v = be32(data, i * 4);
+                    }
+                    self.chromaticities = Some(values);
+                }
+            }
+            b"sRGB" => {
+                self.srgb_intent = data.first().copied();
+            }
+            _ => {
+                logger::debug(format!("chunk {}", String::from_utf8_lossy(chunk_type)).as_str());
+            }
+        }
+    }
+
+    // keyword, compression flag and method, language tag, translated keyword, UTF-8 text
+    fn international_text(&mut self, data: &[u8]) {
+        let (keyword, rest) = match split_keyword(data) {
+            Some((keyword, rest)) if rest.len() >= 2 => (keyword, rest),
+            _ => {
+                logger::debug("broken iTXt");
+                return;
+            }
+        };
+        let compressed = rest[0] == 1;
+        let mut fields = rest[2..].splitn(3, |&b| b == 0);
+        let text = match (fields.next(), fields.next(), fields.next()) {
+            (Some(_language), Some(_translated), Some(text)) => text,
+            _ => {
+                logger::debug("broken iTXt");
+                return;
+            }
+        };
+        let text = if compressed {
+            match zlib_decompress(text) {
+                Some(text) => text,
+                None => {
+                    return;
+                }
+            }
+        } else {
+            text.to_vec()
+        };
+        if keyword == "XML:com.adobe.xmp" {
+            self.xmp = XMP::load(&text);
+        } else {
+            self.texts.push((keyword, String::from_utf8_lossy(&text).to_string()));
+        }
+    }
+
+    pub fn color_type_name(&self) -> &'static str {
+        match self.color_type {
+            0 => "Grayscale",
+            2 => "RGB",
+            3 => "Palette",
+            4 => "Grayscale with Alpha",
+            6 => "RGB with Alpha",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("ImageWidth".to_string(), self.width.to_string()),
+            ("ImageHeight".to_string(), self.height.to_string()),
+            ("BitDepth".to_string(), self.bit_depth.to_string()),
+            ("ColorType".to_string(), self.color_type_name().to_string()),
+            ("Interlace".to_string(), if self.interlace == 1 { "Adam7" } else { "Noninterlaced" }.to_string()),
+        ];
+        if let Some((x, y, unit)) = self.physical {
+            entries.push(("PixelsPerUnitX".to_string(), x.to_string()));
+            entries.push(("PixelsPerUnitY".to_string(), y.to_string()));
+            entries.push(("PixelUnits".to_string(), if unit == 1 { "meters" } else { "Unknown" }.to_string()));
+        }
+        if let Some(modified) = &self.modified {
+            entries.push(("ModifyDate".to_string(), modified.clone()));
+        }
+        if let Some(gamma) = self.gamma {
+            entries.push(("Gamma".to_string(), format!("{:.5}", gamma as f64 / 100000.0)));
+        }
+        if let Some(values) = self.chromaticities {
+            let names = ["WhitePointX", "WhitePointY", "RedX", "RedY", "GreenX", "GreenY", "BlueX", "BlueY"];
+            for (name, v) in names.iter().zip(values) {
+                entries.push((name.to_string(), format!("{:.5}", v as f64 / 100000.0)));
+            }
+        }
+        if let Some(intent) = self.srgb_intent {
+            let name = match intent {
+                0 => "Perceptual",
+                1 => "Relative Colorimetric",
+                2 => "Saturation",
+                3 => "Absolute Colorimetric",
+                _ => "Unknown",
+            };
+            entries.push(("SRGBRendering".to_string(), name.to_string()));
+        }
+        if let Some(name) = &self.icc_name {
+            entries.push(("ProfileName".to_string(), name.clone()));
+        }
+        entries.extend(self.texts.iter().cloned());
+        entries
+    }
+}
+
+// keyword (1-79 Latin-1 bytes) up to the NUL separator, and the rest
+fn split_keyword(data: &[u8]) -> Option<(String, &[u8])> {
+    let end = data.iter().position(|&b| b == 0)?;
+    if end == 0 || end > 79 {
+        return None;
+    }
+    Some((latin1(&data[..end]), &data[end+1..]))
+}
+
+fn latin1(data: &[u8]) -> String {
+    data.iter().map(|&b| b as char).collect()
+}
+
+// over the chunk type and data
+fn crc32(chunk_type: &[u8], data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in chunk_type.iter().chain(data) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xEDB88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_known_answers() {
+        // the check value of CRC-32/ISO-HDLC
+        assert_eq!(crc32(b"", b"123456789"), 0xCBF43926);
+        // IHDR of a 1x1 8-bit grayscale image
+        assert_eq!(crc32(b"IHDR", b"\0\0\0\x01\0\0\0\x01\x08\0\0\0\0"), 0x3A7E9B55);
+        // IEND
+        assert_eq!(crc32(b"IEND", b""), 0xAE426082);
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use crate::exif::*;
+use crate::xmp::*;
+use crate::iptc::*;
+use crate::icc::*;
+use crate::jfif::*;
+use crate::photoshop::*;
+use crate::logger;
+use crate::bytes::*;
+
+/// Photoshop document (PSD) or large document (PSB): the header and the image resources section.
+pub struct PSD {
+    /// 1: PSD, 2: PSB
+    pub version: u16,
+    pub channels: u16,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u16,
+    pub color_mode: u16,
+    /// Exif data 1 and 3 resources
+    pub exif: Option<EXIF>,
+    pub xmp: Option<XMP>,
+    pub iptc: Option<Iptc>,
+    pub icc: Option<ICC>,
+    pub thumbnail: Option<Thumbnail>,
+    pub image_resources: Vec<ImageResource>,
+}
+
+const PSD_SIGNATURE: &[u8] = b"8BPS";
+
+// larger resource sections are not metadata
+const MAX_RESOURCES: usize = 1 << 26;
+
+impl PSD {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<PSD> {
+        let mut head = [0; 26];
+        f.read_exact(&mut head).ok()?;
+        let version = be16(&head, 4);
+        if &head[0..4] != PSD_SIGNATURE || !(version == 1 || version == 2) {
+            logger::debug("not PSD");
+            return None;
+        }
+
+        let mut psd = PSD {
+            version,
+            channels: be16(&head, 12),
+            height: be32(&head, 14),
+            width: be32(&head, 18),
+            depth: be16(&head, 22),
+            color_mode: be16(&head, 24),
+            exif: None,
+            xmp: None,
+            iptc: None,
+            icc: None,
+            thumbnail: None,
+            image_resources: Vec::new(),
+        };
+
+        // color mode data, then the image resources; both have 4-byte lengths in PSB too
+        let mut len = [0; 4];
+        f.read_exact(&mut len).ok()?;
+        f.seek(SeekFrom::Current(be32(&len, 0) as i64)).ok()?;
+        f.read_exact(&mut len).ok()?;
+        let size = be32(&len, 0) as usize;
+        if size > MAX_RESOURCES {
+            logger::debug("image resources too large");
+            return Some(psd);
+        }
+        let mut data = vec![0; size];
+        if f.read_exact(&mut data).is_err() {
+            logger::debug("short image resources");
+            return Some(psd);
+        }
+
+        psd.image_resources = load_resources(&data);
+        for resource in &psd.image_resources {
+            match resource.id {
+                RESOURCE_EXIF1 | RESOURCE_EXIF3 => {
+                    let tiff = resource.data.strip_prefix(b"Exif\0\0").unwrap_or(&resource.data);
+                    match (&mut psd.exif, EXIF::load(tiff)) {
+                        (Some(values), Some(exif)) => {
+                            values.merge(exif);
+                        }
+                        (None, exif) => {
+                            psd.exif = exif;
+                        }
+                        _ => {}
+                    }
+                }
+                RESOURCE_XMP => {
+                    psd.xmp = XMP::load(&resource.data);
+                }
+                RESOURCE_IPTC => {
+                    psd.iptc = Iptc::load(&resource.data);
+                }
+                RESOURCE_ICC => {
+                    psd.icc = ICC::load(&resource.data);
+                }
+                RESOURCE_THUMBNAIL => {
+                    psd.thumbnail = thumbnail(&resource.data);
+                }
+                _ => {}
+            }
+        }
+
+        Some(psd)
+    }
+
+    pub fn color_mode_name(&self) -> &'static str {
+        match self.color_mode {
+            0 => "Bitmap",
+            1 => "Grayscale",
+            2 => "Indexed",
+            3 => "RGB",
+            4 => "CMYK",
+            7 => "Multichannel",
+            8 => "Duotone",
+            9 => "Lab",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        vec![
+            ("Format".to_string(), if self.version == 2 { "PSB" } else { "PSD" }.to_string()),
+            ("ImageWidth".to_string(), self.width.to_string()),
+            ("ImageHeight".to_string(), self.height.to_string()),
+            ("Channels".to_string(), self.channels.to_string()),
+            ("BitDepth".to_string(), self.depth.to_string()),
+            ("ColorMode".to_string(), self.color_mode_name().to_string()),
+        ]
+    }
+}
+
+// format, width, height, widthbytes, total size, compressed size, bits per pixel, planes, then JFIF
+fn thumbnail(data: &[u8]) -> Option<Thumbnail> {
+    if data.len() < 28 {
+        logger::debug("short thumbnail resource");
+        return None;
+    }
+    if be32(data, 0) != 1 {
+        logger::debug("raw RGB thumbnail");
+        return None;
+    }
+    let jpeg = &data[28..];
+    Some(Thumbnail {
+        format: ThumbnailFormat::JPEG,
+        width: be32(data, 4) as u16,
+        height: be32(data, 8) as u16,
+        data: jpeg.to_vec(),
+        palette: Vec::new(),
+    })
+}
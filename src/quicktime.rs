@@ -0,0 +1,443 @@
+use std::io::prelude::*;
+use crate::bmff::*;
+use crate::cr3::*;
+use crate::exif::*;
+use crate::xmp::*;
+use crate::logger;
+use crate::bytes::*;
+
+/// QuickTime (MOV) or MP4 movie: the movie and track headers and the user metadata.
+pub struct QuickTime {
+    /// absent in older QuickTime files
+    pub file_type: Option<FileType>,
+    /// 'mvhd', "YYYY:MM:DD HH:MM:SS" in UTC
+    pub created: Option<String>,
+    pub modified: Option<String>,
+    /// seconds
+    pub duration: f64,
+    pub tracks: Vec<Track>,
+    /// 'udta' atoms and 'meta' keys, in file order
+    pub values: Vec<(String, String)>,
+    /// Canon CMT or Exif 'uuid' boxes
+    pub exif: Option<EXIF>,
+    /// XMP 'uuid' box or 'XMP_' atom
+    pub xmp: Option<XMP>,
+}
+
+pub struct Track {
+    pub id: u32,
+    /// 'vide', 'soun', 'meta', ...
+    pub handler: String,
+    /// sample description format, e.g. 'hvc1' or 'mp4a'
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    /// clockwise, from the display matrix
+    pub rotation: u16,
+    /// seconds
+    pub duration: f64,
+}
+
+impl QuickTime {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<QuickTime> {
+        let mut src = SeekReader::new(f)?;
+        QuickTime::load_from(&mut src)
+    }
+
+    pub fn load_data(data: &[u8]) -> Option<QuickTime> {
+        QuickTime::load_from(&mut &data[..])
+    }
+
+    fn load_from<S: ReadAt>(src: &mut S) -> Option<QuickTime> {
+        let size = src.size();
+        let boxes = read_boxes(src, 0, size);
+        let moov = match find(&boxes, b"moov") {
+            Some(moov) => moov.children(src),
+            None => {
+                logger::debug("no moov");
+                return None;
+            }
+        };
+
+        let mut movie = QuickTime {
+            file_type: find(&boxes, b"ftyp").and_then(|b| b.data(src)).and_then(|d| FileType::load(&d)),
+            created: None,
+            modified: None,
+            duration: 0.0,
+            tracks: Vec::new(),
+            values: Vec::new(),
+            exif: None,
+            xmp: None,
+        };
+
+        let mut timescale = 0;
+        if let Some(mvhd) = find(&moov, b"mvhd").and_then(|b| b.data(src)) {
+            let mut r = BoxReader::new(&mvhd);
+            if let Some((created, modified, scale, duration)) = movie_header(&mut r) {
+                movie.created = date_1904(created);
+                movie.modified = date_1904(modified);
+                movie.duration = seconds(duration, scale);
+                timescale = scale;
+            }
+        }
+
+        for b in &moov {
+            match &b.box_type {
+                b"trak" => {
+                    if let Some(track) = load_track(src, b, timescale) {
+                        movie.tracks.push(track);
+                    }
+                }
+                b"udta" => {
+                    movie.load_udta(src, b);
+                }
+                b"meta" => {
+                    movie.load_meta(src, b);
+                }
+                _ => {}
+            }
+        }
+
+        // metadata boxes of cameras, at the top level or in 'moov'
+        for b in boxes.iter().chain(moov.iter()) {
+            match (&b.box_type, &b.uuid) {
+                (b"uuid", Some(uuid)) if uuid == &XMP_UUID => {
+                    movie.xmp = b.data(src).and_then(|d| XMP::load(&d));
+                }
+                (b"uuid", Some(uuid)) if uuid == &CANON_UUID => {
+                    movie.exif = load_cmt(src, b);
+                }
+                (b"uuid", Some(_)) => {
+                    if let Some(data) = src.read_at(b.offset, 6.min(b.size as usize)) {
+                        if data.starts_with(b"Exif\0\0") || data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+                            let data = b.data(src).unwrap_or_default();
+                            movie.exif = EXIF::load(data.strip_prefix(b"Exif\0\0").unwrap_or(&data));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(movie)
+    }
+
+    // QuickTime user data atoms: international text, or iTunes style 'meta'
+    fn load_udta<S: ReadAt>(&mut self, src: &mut S, udta: &BMFFBox) {
+        for b in udta.children(src) {
+            match &b.box_type {
+                b"meta" => {
+                    self.load_meta(src, &b);
+                }
+                b"XMP_" => {
+                    self.xmp = b.data(src).and_then(|d| XMP::load(&d));
+                }
+                [0xA9, ..] => {
+                    let data = b.data(src).unwrap_or_default();
+                    let value = match text_atom(&data) {
+                        Some(value) => value,
+                        None => {
+                            continue;
+                        }
+                    };
+                    self.add_value(&udta_name(&b.box_type), value);
+                }
+                _ => {
+                    logger::debug(format!("udta {}", b.type_name()).as_str());
+                }
+            }
+        }
+    }
+
+    // 'keys' and 'ilst' of QuickTime, or the iTunes 'ilst' of MP4
+    fn load_meta<S: ReadAt>(&mut self, src: &mut S, meta: &BMFFBox) {
+        // an ISO full box in MP4, a plain atom in QuickTime
+        let head = src.read_at(meta.offset, 12).unwrap_or_default();
+        let children = if head.get(8..12) == Some(b"hdlr") {
+            meta.full_children(src, 0)
+        } else {
+            meta.children(src)
+        };
+
+        let mut keys:Vec<String> = Vec::new();
+        if let Some(data) = find(&children, b"keys").and_then(|b| b.data(src)) {
+            keys = meta_keys(&data).unwrap_or_default();
+        }
+        let ilst = match find(&children, b"ilst") {
+            Some(ilst) => ilst.children(src),
+            None => {
+                return;
+            }
+        };
+        for item in ilst {
+            // 1-based index into 'keys', or an iTunes atom type
+            let index = be32(&item.box_type, 0) as usize;
+            let name = match keys.get(index.wrapping_sub(1)) {
+                Some(key) => key_name(key),
+                None => udta_name(&item.box_type),
+            };
+            let data = item.children(src);
+            if let Some(value) = find(&data, b"data").and_then(|b| b.data(src)).and_then(|d| data_value(&d)) {
+                self.add_value(&name, value);
+            }
+        }
+    }
+
+    fn add_value(&mut self, name: &str, value: String) {
+        if name == "GPSCoordinates" {
+            if let Some((latitude, longitude, altitude)) = iso6709(&value) {
+                self.values.push(("GPSLatitude".to_string(), format!("{:.6}", latitude)));
+                self.values.push(("GPSLongitude".to_string(), format!("{:.6}", longitude)));
+                if let Some(altitude) = altitude {
+                    self.values.push(("GPSAltitude".to_string(), format!("{:.3}", altitude)));
+                }
+            }
+        }
+        self.values.push((name.to_string(), value));
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        if let Some(file_type) = &self.file_type {
+            entries.push(("Brand".to_string(), file_type.major_brand.trim_end().to_string()));
+        }
+        if let Some(created) = &self.created {
+            entries.push(("CreateDate".to_string(), created.clone()));
+        }
+        if let Some(modified) = &self.modified {
+            entries.push(("ModifyDate".to_string(), modified.clone()));
+        }
+        entries.push(("Duration".to_string(), format!("{:.3} s", self.duration)));
+        for (i, track) in self.tracks.iter().enumerate() {
+            let prefix = format!("Track{}-", i + 1);
+            entries.push((format!("{}Type", prefix), track.handler.clone()));
+            entries.push((format!("{}Codec", prefix), track.codec.clone()));
+            if track.width != 0 || track.height != 0 {
+                entries.push((format!("{}ImageWidth", prefix), track.width.to_string()));
+                entries.push((format!("{}ImageHeight", prefix), track.height.to_string()));
+                entries.push((format!("{}Rotation", prefix), track.rotation.to_string()));
+            }
+            entries.push((format!("{}Duration", prefix), format!("{:.3} s", track.duration)));
+        }
+        entries.extend(self.values.iter().cloned());
+        entries
+    }
+}
+
+// creation and modification times, timescale and duration, 32 or 64-bit
+fn movie_header(r: &mut BoxReader) -> Option<(u64, u64, u32, u64)> {
+    let (version, _) = r.full_box()?;
+    let size = if version == 1 { 8 } else { 4 };
+    let created = r.uint(size)?;
+    let modified = r.uint(size)?;
+    let timescale = r.u32()?;
+    let duration = r.uint(size)?;
+    Some((created, modified, timescale, duration))
+}
+
+fn load_track<S: ReadAt>(src: &mut S, trak: &BMFFBox, timescale: u32) -> Option<Track> {
+    let trak = trak.children(src);
+    let tkhd = find(&trak, b"tkhd")?.data(src)?;
+    let mut r = BoxReader::new(&tkhd);
+    let (version, _) = r.full_box()?;
+    let size = if version == 1 { 8 } else { 4 };
+    r.uint(size * 2)?;  // creation and modification times
+    let id = r.u32()?;
+    r.u32()?;
+    let duration = r.uint(size)?;
+    r.bytes(8 + 2 + 2 + 2 + 2)?;  // reserved, layer, alternate group, volume, reserved
+    // a, b, u, c, d, v, x, y, w; rotation from a and b (16.16)
+    let a = r.u32()? as i32;
+    let b = r.u32()? as i32;
+    r.bytes(7 * 4)?;
+    let width = r.u32()? >> 16;
+    let height = r.u32()? >> 16;
+    let rotation = match (a.signum(), b.signum()) {
+        (0, 1) => 90,
+        (-1, 0) => 180,
+        (0, -1) => 270,
+        _ => 0,
+    };
+
+    let mut track = Track {
+        id,
+        handler: String::new(),
+        codec: String::new(),
+        width,
+        height,
+        rotation,
+        duration: seconds(duration, timescale),
+    };
+
+    let mdia = find(&trak, b"mdia").map(|b| b.children(src)).unwrap_or_default();
+    if let Some(hdlr) = find(&mdia, b"hdlr").and_then(|b| src.read_at(b.offset + 8, 4)) {
+        track.handler = String::from_utf8_lossy(&hdlr).to_string();
+    }
+    let minf = find(&mdia, b"minf").map(|b| b.children(src)).unwrap_or_default();
+    let stbl = find(&minf, b"stbl").map(|b| b.children(src)).unwrap_or_default();
+    // first sample description: size and format after the entry count
+    if let Some(stsd) = find(&stbl, b"stsd").and_then(|b| src.read_at(b.offset + 12, 4)) {
+        track.codec = String::from_utf8_lossy(&stsd).to_string();
+    }
+    Some(track)
+}
+
+// entry count, then size, namespace and name of each key
+fn meta_keys(keys: &[u8]) -> Option<Vec<String>> {
+    let mut r = BoxReader::new(keys);
+    r.full_box()?;
+    let count = r.u32()?;
+    let mut names = Vec::new();
+    for _ in 0..count {
+        let size = r.u32()? as usize;
+        r.bytes(4)?;
+        let name = r.bytes(size.checked_sub(8)?)?;
+        names.push(String::from_utf8_lossy(name).to_string());
+    }
+    Some(names)
+}
+
+// 'data' atom: type indicator, locale, value
+fn data_value(data: &[u8]) -> Option<String> {
+    let mut r = BoxReader::new(data);
+    let type_indicator = r.u32()? & 0x00FFFFFF;
+    r.u32()?;
+    let value = &data[8..];
+    let text = match type_indicator {
+        1 => String::from_utf8_lossy(value).to_string(),
+        2 => {
+            let units:Vec<u16> = value.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+            String::from_utf16_lossy(&units)
+        }
+        // big endian signed and unsigned integers of 1 to 8 bytes
+        21 => {
+            let v = value.iter().fold(0i64, |v, &b| v << 8 | b as i64);
+            let shift = 64 - 8 * value.len().clamp(1, 8) as u32;
+            (v << shift >> shift).to_string()
+        }
+        22 => value.iter().fold(0u64, |v, &b| v << 8 | b as u64).to_string(),
+        23 if value.len() == 4 => f32::from_be_bytes([value[0], value[1], value[2], value[3]]).to_string(),
+        24 if value.len() == 8 => {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(value);
+            f64::from_be_bytes(bytes).to_string()
+        }
+        _ => format!("({} bytes)", value.len()),
+    };
+    Some(text)
+}
+
+// 16-bit length, 16-bit language code, text
+fn text_atom(data: &[u8]) -> Option<String> {
+    let mut r = BoxReader::new(data);
+    let len = r.u16()? as usize;
+    r.u16()?;
+    let text = r.bytes(len)?;
+    Some(String::from_utf8_lossy(text).trim_end_matches('\0').to_string())
+}
+
+fn key_name(key: &str) -> String {
+    let key = key.strip_prefix("com.apple.quicktime.").unwrap_or(key);
+    match key {
+        "make" => "Make",
+        "model" => "Model",
+        "software" => "Software",
+        "creationdate" => "CreationDate",
+        "location.ISO6709" => "GPSCoordinates",
+        "location.accuracy.horizontal" => "LocationAccuracyHorizontal",
+        "content.identifier" => "ContentIdentifier",
+        "live-photo.auto" => "LivePhotoAuto",
+        "title" => "Title",
+        "description" => "Description",
+        "author" => "Author",
+        _ => key,
+    }.to_string()
+}
+
+fn udta_name(atom: &[u8; 4]) -> String {
+    match &atom[1..] {
+        b"xyz" => "GPSCoordinates".to_string(),
+        b"mak" => "Make".to_string(),
+        b"mod" => "Model".to_string(),
+        b"swr" => "Software".to_string(),
+        b"too" => "Encoder".to_string(),
+        b"day" => "ContentCreateDate".to_string(),
+        b"nam" => "Title".to_string(),
+        b"cmt" => "Comment".to_string(),
+        b"ART" => "Artist".to_string(),
+        _ => String::from_utf8_lossy(&atom[1..]).to_string(),
+    }
+}
+
+// "+35.6895+139.6917+040.000/": latitude, longitude and altitude in decimal degrees and metres
+fn iso6709(s: &str) -> Option<(f64, f64, Option<f64>)> {
+    let s = s.trim_end_matches('/');
+    let mut values = Vec::new();
+    let mut start = 0;
+    for (i, c) in s.char_indices().skip(1) {
+        if c == '+' || c == '-' {
+            values.push(s[start..i].parse::<f64>().ok()?);
+            start = i;
+        }
+    }
+    values.push(s[start..].parse::<f64>().ok()?);
+    match values.as_slice() {
+        [latitude, longitude] => Some((*latitude, *longitude, None)),
+        [latitude, longitude, altitude, ..] => Some((*latitude, *longitude, Some(*altitude))),
+        _ => None,
+    }
+}
+
+fn seconds(duration: u64, timescale: u32) -> f64 {
+    if timescale == 0 {
+        0.0
+    } else {
+        duration as f64 / timescale as f64
+    }
+}
+
+// seconds since 1904-01-01 00:00:00 UTC, None when unset
+fn date_1904(seconds: u64) -> Option<String> {
+    if seconds == 0 {
+        return None;
+    }
+    // days from 1904-01-01 to 1970-01-01
+    let days = (seconds / 86400) as i64 - 24107;
+    let time = seconds % 86400;
+    // civil from days, proleptic Gregorian calendar
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    Some(format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}", year, month, day, time / 3600, time / 60 % 60, time % 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_since_1904() {
+        assert_eq!(date_1904(0), None);
+        assert_eq!(date_1904(2082844800).as_deref(), Some("1970:01:01 00:00:00"));
+        assert_eq!(date_1904(5097601).as_deref(), Some("1904:02:29 00:00:01"));
+        assert_eq!(date_1904(3792054896).as_deref(), Some("2024:02:29 12:34:56"));
+        // out of range values do not panic
+        assert!(date_1904(u64::MAX).is_some());
+    }
+
+    #[test]
+    fn iso6709_locations() {
+        assert_eq!(iso6709("+35.6895+139.6917+040.000/"), Some((35.6895, 139.6917, Some(40.0))));
+        assert_eq!(iso6709("-33.8568+151.2153/"), Some((-33.8568, 151.2153, None)));
+        // truncated
+        assert_eq!(iso6709("+35.6895/"), None);
+        assert_eq!(iso6709("+35.6895+"), None);
+        assert_eq!(iso6709(""), None);
+    }
+}
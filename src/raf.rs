@@ -0,0 +1,181 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+use crate::exif::*;
+use crate::jfif::*;
+use crate::logger;
+use crate::bytes::*;
+
+/// Fujifilm RAF: a big endian header, the embedded JPEG and the RAF directory of the raw data.
+pub struct RAF {
+    /// "0201" etc.
+    pub version: String,
+    pub camera_id: String,
+    pub model: String,
+    pub jpeg_offset: u32,
+    pub jpeg_length: u32,
+    pub cfa_offset: u32,
+    pub cfa_length: u32,
+    /// the embedded JPEG, with the Exif of the camera
+    pub jpeg: Option<JFIF>,
+    /// RAF directory tags, (height, width) or (top, left) pairs
+    pub raw_size: Option<(u16, u16)>,
+    pub full_size: Option<(u16, u16)>,
+    pub crop_top_left: Option<(u16, u16)>,
+    pub cropped_size: Option<(u16, u16)>,
+    pub aspect_ratio: Option<(u16, u16)>,
+}
+
+const RAF_SIGNATURE: &[u8] = b"FUJIFILMCCD-RAW ";
+
+// the embedded JPEG is a few MB at most
+const MAX_JPEG: u32 = 1 << 26;
+
+impl RAF {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<RAF> {
+        let mut head = [0; 108];
+        f.read_exact(&mut head).ok()?;
+        if !head.starts_with(RAF_SIGNATURE) {
+            logger::debug("not RAF");
+            return None;
+        }
+
+        let mut raf = RAF {
+            version: string(&head[16..20]),
+            camera_id: string(&head[20..28]),
+            model: string(&head[28..60]),
+            jpeg_offset: be32(&head, 84),
+            jpeg_length: be32(&head, 88),
+            cfa_offset: be32(&head, 100),
+            cfa_length: be32(&head, 104),
+            jpeg: None,
+            raw_size: None,
+            full_size: None,
+            crop_top_left: None,
+            cropped_size: None,
+            aspect_ratio: None,
+        };
+
+        match raf.read_jpeg(f) {
+            Ok(jpeg) => {
+                raf.jpeg = JFIF::load(&mut Cursor::new(&jpeg));
+            }
+            Err(e) => {
+                logger::debug(format!("cant read embedded JPEG: {}", e).as_str());
+            }
+        }
+
+        // RAF directory: entry count, then tag, size and data
+        let directory_offset = be32(&head, 92);
+        let directory_length = be32(&head, 96).min(1 << 20);
+        let directory = SeekReader::new(&mut *f)
+            .and_then(|mut src| src.read_at(directory_offset as u64, directory_length as usize));
+        match directory {
+            Some(directory) => {
+                raf.load_directory(&directory);
+            }
+            None => {
+                logger::debug("cant read RAF directory");
+            }
+        }
+
+        Some(raf)
+    }
+
+    fn read_jpeg<R: Read + Seek>(&self, f: &mut R) -> io::Result<Vec<u8>> {
+        if self.jpeg_length == 0 || self.jpeg_length > MAX_JPEG {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no embedded JPEG"));
+        }
+        // checked against the file size before allocating
+        let mut src = SeekReader::new(f).ok_or_else(|| io::Error::other("cant seek"))?;
+        match src.read_at(self.jpeg_offset as u64, self.jpeg_length as usize) {
+            Some(jpeg) => Ok(jpeg),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "embedded JPEG beyond the end of the file")),
+        }
+    }
+
+    /// The embedded JPEG as it is; it carries the Exif of the camera already.
+    pub fn extract_preview<R: Read + Seek>(&self, f: &mut R) -> io::Result<Vec<u8>> {
+        self.read_jpeg(f)
+    }
+
+    fn load_directory(&mut self, data: &[u8]) {
+        if data.len() < 4 {
+            return;
+        }
+        let count = be32(data, 0);
+        let mut offset = 4;
+        for _ in 0..count {
+            if offset + 4 > data.len() {
+                logger::debug("short RAF directory");
+                break;
+            }
+            let tag = be16(data, offset);
+            let size = be16(data, offset + 2) as usize;
+            let value = match data.get(offset+4..offset+4+size) {
+                Some(value) => value,
+                None => {
+                    logger::debug("short RAF directory entry");
+                    break;
+                }
+            };
+            offset += 4 + size;
+
+            let pair = if size >= 4 { Some((be16(value, 0), be16(value, 2))) } else { None };
+            match tag {
+                0x0100 => {
+                    self.full_size = pair;
+                }
+                0x0110 => {
+                    self.crop_top_left = pair;
+                }
+                0x0111 => {
+                    self.cropped_size = pair;
+                }
+                0x0115 => {
+                    self.aspect_ratio = pair;
+                }
+                0x0121 => {
+                    self.raw_size = pair;
+                }
+                _ => {
+                    logger::debug(format!("RAF tag {:04X} size: {}", tag, size).as_str());
+                }
+            }
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("Version".to_string(), self.version.clone()),
+            ("CameraID".to_string(), self.camera_id.clone()),
+            ("Model".to_string(), self.model.clone()),
+        ];
+        // stored as height, width
+        let sizes = [
+            ("RawImageFullSize", self.full_size),
+            ("RawImageCroppedSize", self.cropped_size),
+            ("RawImageSize", self.raw_size),
+        ];
+        for (name, size) in sizes {
+            if let Some((height, width)) = size {
+                entries.push((name.to_string(), format!("{}x{}", width, height)));
+            }
+        }
+        if let Some((top, left)) = self.crop_top_left {
+            entries.push(("RawImageCropTopLeft".to_string(), format!("{} {}", top, left)));
+        }
+        if let Some((height, width)) = self.aspect_ratio {
+            entries.push(("RawImageAspectRatio".to_string(), format!("{}:{}", width, height)));
+        }
+        entries.push(("JPEG".to_string(), format!("{} bytes at 0x{:X}", self.jpeg_length, self.jpeg_offset)));
+        entries.push(("CFA".to_string(), format!("{} bytes at 0x{:X}", self.cfa_length, self.cfa_offset)));
+        entries
+    }
+}
+
+// NUL padded ASCII
+fn string(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).trim_end().to_string()
+}
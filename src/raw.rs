@@ -0,0 +1,249 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::Cursor;
+use std::io::SeekFrom;
+use crate::exif::*;
+use crate::exif_edit::*;
+use crate::jfif::*;
+use crate::tiff::*;
+use crate::logger;
+
+/// TIFF based camera raw file, or a plain TIFF.
+pub struct RAW {
+    pub format: RawFormat,
+    pub tiff: TIFF,
+    pub previews: Vec<Preview>,
+    // kept as stored, to carry the metadata over to extracted previews
+    byte_order: ENDIAN,
+    ifd0: Option<Ifd>,
+    exif_ifd: Option<Ifd>,
+    gps_ifd: Option<Ifd>,
+}
+
+/// JPEG preview embedded in the file.
+pub struct Preview {
+    /// IFD that references it, e.g. "IFD1" or "IFD0-SubIFD0"
+    pub ifd: String,
+    pub offset: u64,
+    pub length: u64,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Metadata of the raw file to write into an extracted preview.
+pub enum CarryOver {
+    NONE,
+    /// only the Orientation, so that viewers rotate the preview
+    ORIENTATION,
+    /// IFD0 descriptive tags, the Exif and the GPS IFDs
+    EXIF,
+}
+
+pub enum RawFormat {
+    DNG,
+    CR2,
+    NEF,
+    ARW,
+    ORF,
+    RW2,
+    PEF,
+    SRW,
+    TIFF,
+}
+
+impl RAW {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<RAW> {
+        let mut src = SeekReader::new(f)?;
+        RAW::load_from(&mut src)
+    }
+
+    pub fn load_data(data: &[u8]) -> Option<RAW> {
+        RAW::load_from(&mut &data[..])
+    }
+
+    fn load_from<S: ReadAt>(src: &mut S) -> Option<RAW> {
+        let header = read_header(src)?;
+        let mut tiff = TIFF::load_from(src)?;
+
+        let make = tiff.exif.as_ref().and_then(|e| e.values.get("Make")).cloned().unwrap_or_default();
+        let is_dng = tiff.exif.as_ref().is_some_and(|e| e.values.contains_key("DNGVersion"));
+        let format = match header.magic {
+            0x4F52 | 0x5352 => RawFormat::ORF,
+            0x0055 => RawFormat::RW2,
+            _ if src.read_at(8, 2).is_some_and(|m| m == b"CR") => RawFormat::CR2,
+            _ if is_dng => RawFormat::DNG,
+            _ if make.starts_with("NIKON") => RawFormat::NEF,
+            _ if make.starts_with("SONY") => RawFormat::ARW,
+            _ if make.starts_with("PENTAX") || make.starts_with("RICOH") => RawFormat::PEF,
+            _ if make.starts_with("SAMSUNG") => RawFormat::SRW,
+            _ => RawFormat::TIFF,
+        };
+
+        let ifd0 = read_ifd(src, header.offset, &header.byte_order, header.big_tiff);
+        let sub_ifd = |src: &mut S, tag: u16| {
+            let offset = ifd0.as_ref()?.get(tag)?.u64_value(&header.byte_order)?;
+            read_ifd(src, offset, &header.byte_order, header.big_tiff)
+        };
+        let exif_ifd = sub_ifd(src, 0x8769);
+        let gps_ifd = sub_ifd(src, 0x8825);
+
+        // RW2 keeps the Exif IFD in the JPEG preview (JpgFromRaw, 0x002E) rather than in IFD0
+        if let (RawFormat::RW2, Some(ifd0)) = (&format, &ifd0) {
+            if let Some(exif) = rw2_exif(ifd0) {
+                match &mut tiff.exif {
+                    Some(ifd0) => {
+                        ifd0.merge(exif);
+                    }
+                    None => {
+                        tiff.exif = Some(exif);
+                    }
+                }
+            }
+        }
+
+        let previews = previews(src, &tiff);
+        Some(RAW {
+            format,
+            tiff,
+            previews,
+            byte_order: header.byte_order,
+            ifd0,
+            exif_ifd,
+            gps_ifd,
+        })
+    }
+
+    /// Index of the preview with the most pixels.
+    pub fn largest_preview(&self) -> Option<usize> {
+        (0..self.previews.len()).max_by_key(|&i| {
+            let preview = &self.previews[i];
+            (preview.width as u64 * preview.height as u64, preview.length)
+        })
+    }
+
+    /// Reads a preview out of the file the raw was loaded from.
+    pub fn extract_preview<R: Read + Seek>(&self, f: &mut R, index: usize, carry_over: CarryOver) -> io::Result<Vec<u8>> {
+        let preview = match self.previews.get(index) {
+            Some(preview) => preview,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no such preview"));
+            }
+        };
+        let file_size = f.seek(SeekFrom::End(0))?;
+        if preview.offset.checked_add(preview.length).is_none_or(|end| end > file_size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "preview beyond the end of the file"));
+        }
+        let mut image = vec![0; preview.length as usize];
+        f.seek(SeekFrom::Start(preview.offset))?;
+        f.read_exact(&mut image)?;
+
+        let tiff = match carry_over {
+            CarryOver::NONE => {
+                return Ok(image);
+            }
+            CarryOver::ORIENTATION => {
+                let orientation = self.ifd0.as_ref().and_then(|ifd| ifd.get(0x0112));
+                match orientation {
+                    Some(entry) => {
+                        let mut edit = ExifEdit::new(matches!(self.byte_order, ENDIAN::BIG_ENDIAN));
+                        edit.ifd0.push(entry.clone());
+                        edit.write()
+                    }
+                    None => {
+                        return Ok(image);
+                    }
+                }
+            }
+            CarryOver::EXIF => self.exif_tiff(),
+        };
+        match replace_exif(&image, &tiff) {
+            Some(image) => Ok(image),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, "cant write Exif into preview")),
+        }
+    }
+
+    // tags that describe the shot rather than the layout of the raw image data
+    fn exif_tiff(&self) -> Vec<u8> {
+        let entries = |ifd: &Option<Ifd>, keep: &dyn Fn(u16) -> bool| -> Vec<IfdEntry> {
+            ifd.iter().flat_map(|ifd| ifd.entries.iter()).filter(|e| keep(e.tag)).cloned().collect()
+        };
+        let mut edit = ExifEdit::new(matches!(self.byte_order, ENDIAN::BIG_ENDIAN));
+        edit.ifd0 = entries(&self.ifd0, &|tag| matches!(tag,
+            0x010E | 0x010F | 0x0110 | 0x0112 | 0x011A | 0x011B | 0x0128 | 0x0131 | 0x0132 | 0x013B | 0x8298));
+        // MakerNote offsets are relative to the raw file, Interoperability would need its own IFD
+        edit.exif = entries(&self.exif_ifd, &|tag| tag != 0x927C && tag != 0xA005);
+        edit.gps = entries(&self.gps_ifd, &|_| true);
+        edit.write()
+    }
+}
+
+impl std::fmt::Display for RawFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RawFormat::DNG => write!(f, "DNG"),
+            RawFormat::CR2 => write!(f, "CR2"),
+            RawFormat::NEF => write!(f, "NEF"),
+            RawFormat::ARW => write!(f, "ARW"),
+            RawFormat::ORF => write!(f, "ORF"),
+            RawFormat::RW2 => write!(f, "RW2"),
+            RawFormat::PEF => write!(f, "PEF"),
+            RawFormat::SRW => write!(f, "SRW"),
+            RawFormat::TIFF => write!(f, "TIFF"),
+        }
+    }
+}
+
+fn rw2_exif(ifd0: &Ifd) -> Option<EXIF> {
+    let preview = ifd0.get(0x002E)?;
+    match JFIF::load(&mut Cursor::new(&preview.data)) {
+        Some(jfif) => jfif.exif,
+        None => {
+            logger::debug("broken JpgFromRaw");
+            None
+        }
+    }
+}
+
+// JPEGInterchangeFormat, or a single JPEG compressed strip (old-style or new-style JPEG)
+fn previews<S: ReadAt>(src: &mut S, tiff: &TIFF) -> Vec<Preview> {
+    let mut previews:Vec<Preview> = Vec::new();
+    for image in &tiff.images {
+        let (offset, length) = if image.jpeg_offset != 0 && image.jpeg_length != 0 {
+            (image.jpeg_offset, image.jpeg_length)
+        } else if matches!(image.compression, 6 | 7) && image.strip_offsets.len() == 1 && image.strip_byte_counts.len() == 1 {
+            (image.strip_offsets[0], image.strip_byte_counts[0])
+        } else {
+            continue;
+        };
+        if previews.iter().any(|p| p.offset == offset) {
+            continue;
+        }
+        if offset.checked_add(length).is_none_or(|end| end > src.size()) {
+            logger::debug("preview beyond the end of the file");
+            continue;
+        }
+        // the frame header is near the start, after Exif and the like
+        let head = match src.read_at(offset, length.min(0x20000) as usize) {
+            Some(head) => head,
+            None => {
+                logger::debug("invalid preview offset");
+                continue;
+            }
+        };
+        // lossless JPEG strips are raw sensor data
+        let (width, height) = match jpeg_size(&head) {
+            Some(size) => size,
+            None => {
+                continue;
+            }
+        };
+        previews.push(Preview {
+            ifd: image.name.clone(),
+            offset,
+            length,
+            width,
+            height,
+        });
+    }
+    previews
+}
@@ -0,0 +1,392 @@
+use std::io;
+use std::io::prelude::*;
+use crate::exif_edit::*;
+use crate::jfif::*;
+use crate::xmp::*;
+use crate::logger;
+
+/// What `strip` removes from a JPEG file.
+pub enum StripPolicy {
+    /// every APPn and COM segment but the JFIF header, without its thumbnail, and Adobe APP14,
+    /// which decoders need for the color transform
+    ALL,
+    /// the GPS IFD, and the XMP packets that carry GPS properties of the Exif namespaces
+    /// (or cannot be read to tell)
+    GPS,
+    /// GPS, names (Artist, XPAuthor, CameraOwnerName), XPComment and XPSubject, serial numbers,
+    /// the MakerNote and thumbnails; XMP, the Photoshop
+    /// APP13 segment (IPTC) and other vendor APPn are dropped as a whole since they may hold
+    /// any of them. Comments and the ICC profile are kept.
+    PRIVACY,
+    /// Copyright, Orientation and the ICC profile only
+    ESSENTIAL,
+    /// Exif tags, each in its IFD since GPS and Interoperability reuse small tag numbers: only
+    /// those of `allow` when it is not empty, then none of `deny`. The thumbnail goes with
+    /// JPEGInterchangeFormat (IFD1, 0x0201); other segments are kept.
+    CUSTOM { allow: Vec<(ExifIfd, u16)>, deny: Vec<(ExifIfd, u16)> },
+}
+
+const ORIENTATION: u16 = 0x0112;
+const COPYRIGHT: u16 = 0x8298;
+const THUMBNAIL_OFFSET: u16 = 0x0201;
+const MAKER_NOTE: u16 = 0x927C;
+// Artist, XPComment, XPAuthor, XPSubject, CameraOwnerName, BodySerialNumber,
+// LensSerialNumber, and the DNG CameraSerialNumber
+const PRIVATE_TAGS: [u16; 8] = [0x013B, 0x9C9C, 0x9C9D, 0x9C9F, 0xA430, 0xA431, 0xA435, 0xC62F];
+
+// APPn signatures, as listed by the segment walk
+const EXIF: &str = "Exif";
+const XMP: &str = "http://ns.adobe.com/xap/1.0/";
+const XMP_EXTENSION: &str = "http://ns.adobe.com/xmp/extension/";
+const ICC: &str = "ICC_PROFILE";
+
+// XMP namespaces of the Exif GPS properties
+const EXIF_NAMESPACES: [&str; 2] = ["http://ns.adobe.com/exif/1.0/", "http://cipa.jp/exif/1.0/"];
+
+/// Copies a JPEG stream without the metadata the policy removes. The image data is copied
+/// byte for byte; only APPn and COM segments are dropped or rewritten. Under GPS and CUSTOM,
+/// the other images of a Multi-Picture Format file are kept, stripped of their own metadata
+/// the same way, and the MPF segment is updated for them. The other policies drop whatever
+/// follows the first image, together with the MPF segment.
+pub fn strip<R: Read, W: Write>(f: &mut R, out: &mut W, policy: &StripPolicy) -> io::Result<()> {
+    let edit = |segments: &mut Vec<Vec<u8>>| {
+        let drop_xmp = matches!(policy, StripPolicy::GPS) && xmp_has_gps(segments);
+        let mut stripped = Vec::new();
+        for segment in segments.drain(..) {
+            if let Some(segment) = strip_segment(segment, policy, drop_xmp) {
+                stripped.push(segment);
+            }
+        }
+        *segments = stripped;
+    };
+    match policy {
+        StripPolicy::GPS | StripPolicy::CUSTOM { .. } => rewrite_mp_segments(f, out, edit, edit),
+        _ => rewrite_primary_segments(f, out, edit),
+    }
+}
+
+// the segment as it should be written, None to drop it
+fn strip_segment(segment: Vec<u8>, policy: &StripPolicy, drop_xmp: bool) -> Option<Vec<u8>> {
+    let marker = segment[1];
+    if !matches!(marker, 0xE0..=0xEF | 0xFE) || segment.len() < 4 {
+        return Some(segment);
+    }
+    let signature = match segment_type(marker, &segment[4..]) {
+        SegmentType::APP(_, signature) => signature,
+        _ => String::new(),
+    };
+    let custom = matches!(policy, StripPolicy::CUSTOM { .. });
+    match (marker, signature.as_str()) {
+        (0xE0, "JFIF") if custom || matches!(policy, StripPolicy::GPS) => Some(segment),
+        (0xE0, "JFIF") => jfif_without_thumbnail(&segment),
+        (0xE0, "JFXX") => {
+            if custom || matches!(policy, StripPolicy::GPS) { Some(segment) } else { None }
+        }
+        (0xE1, EXIF) => strip_exif(&segment, policy),
+        (0xE1, XMP) | (0xE1, XMP_EXTENSION) => {
+            match policy {
+                StripPolicy::GPS if !drop_xmp => Some(segment),
+                StripPolicy::CUSTOM { .. } => Some(segment),
+                _ => None,
+            }
+        }
+        (0xE2, ICC) => {
+            if matches!(policy, StripPolicy::ALL) { None } else { Some(segment) }
+        }
+        (0xE2, "MPF") => {
+            if custom || matches!(policy, StripPolicy::GPS) { Some(segment) } else { None }
+        }
+        (0xEE, "Adobe") => Some(segment),
+        (0xFE, _) => {
+            match policy {
+                StripPolicy::ALL | StripPolicy::ESSENTIAL => None,
+                _ => Some(segment),
+            }
+        }
+        _ => {
+            match policy {
+                StripPolicy::GPS | StripPolicy::CUSTOM { .. } => Some(segment),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn strip_exif(segment: &[u8], policy: &StripPolicy) -> Option<Vec<u8>> {
+    if matches!(policy, StripPolicy::ALL) {
+        return None;
+    }
+    let mut exif = match segment.get(4 + 6..).and_then(ExifEdit::load) {
+        Some(exif) => exif,
+        None => {
+            logger::debug("broken Exif dropped");
+            return None;
+        }
+    };
+    match policy {
+        StripPolicy::GPS => {
+            exif.gps.clear();
+        }
+        StripPolicy::PRIVACY => {
+            exif.gps.clear();
+            for ifd in [ExifIfd::IFD0, ExifIfd::EXIF] {
+                exif.remove(ifd, MAKER_NOTE);
+                for tag in PRIVATE_TAGS {
+                    exif.remove(ifd, tag);
+                }
+            }
+            exif.ifd1.clear();
+            exif.thumbnail = None;
+        }
+        StripPolicy::ESSENTIAL => {
+            exif.ifd0.retain(|e| e.tag == ORIENTATION || e.tag == COPYRIGHT);
+            exif.exif.clear();
+            exif.gps.clear();
+            exif.interop.clear();
+            exif.ifd1.clear();
+            exif.thumbnail = None;
+        }
+        StripPolicy::CUSTOM { allow, deny } => {
+            let keep = |ifd: ExifIfd, tag: u16| {
+                (allow.is_empty() || allow.contains(&(ifd, tag))) && !deny.contains(&(ifd, tag))
+            };
+            for ifd in [ExifIfd::IFD0, ExifIfd::EXIF, ExifIfd::GPS, ExifIfd::INTEROP, ExifIfd::IFD1] {
+                let removed:Vec<u16> = exif.entries(ifd).iter().map(|e| e.tag).filter(|&tag| !keep(ifd, tag)).collect();
+                for tag in removed {
+                    exif.remove(ifd, tag);
+                }
+            }
+            if !keep(ExifIfd::IFD1, THUMBNAIL_OFFSET) {
+                exif.thumbnail = None;
+            }
+        }
+        StripPolicy::ALL => {}
+    }
+    let empty = exif.ifd0.is_empty() && exif.exif.is_empty() && exif.gps.is_empty()
+        && exif.interop.is_empty() && exif.ifd1.is_empty() && exif.thumbnail.is_none();
+    if empty {
+        return None;
+    }
+    let mut payload = b"Exif\0\0".to_vec();
+    payload.extend_from_slice(&exif.write());
+    let rewritten = new_segment(0xE1, &payload);
+    if rewritten.is_none() {
+        logger::debug("Exif too large for APP1, dropped");
+    }
+    rewritten
+}
+
+// version, units and densities kept, thumbnail size set to 0x0
+fn jfif_without_thumbnail(segment: &[u8]) -> Option<Vec<u8>> {
+    let header = segment.get(4..4 + 12)?;
+    let mut payload = header.to_vec();
+    payload.extend_from_slice(&[0, 0]);
+    new_segment(0xE0, &payload)
+}
+
+// GPS in the main packet or its extension; packets that cannot be read, extensions that do
+// not belong to the main packet and a second main packet count as GPS
+fn xmp_has_gps(segments: &[Vec<u8>]) -> bool {
+    let payload = |segment: &'static str| {
+        segments.iter()
+            .filter(move |s| is_app(s, 1, segment))
+            .map(move |s| &s[4 + segment.len() + 1..])
+    };
+    let mut packets = payload(XMP);
+    let mut xmp = match packets.next() {
+        Some(packet) => match XMP::load(packet) {
+            Some(xmp) => xmp,
+            None => {
+                logger::debug("unreadable XMP");
+                return true;
+            }
+        },
+        None => {
+            return false;
+        }
+    };
+    let mut extended = ExtendedXMP::new();
+    for chunk in payload(XMP_EXTENSION) {
+        extended.add(chunk);
+    }
+    if !extended.is_empty() {
+        if xmp.get("xmpNote:HasExtendedXMP").is_none() {
+            logger::debug("extended XMP without main packet reference");
+            return true;
+        }
+        xmp.merge_extended(&extended);
+        if xmp.get("xmpNote:HasExtendedXMP").is_some() {
+            logger::debug("unreadable extended XMP");
+            return true;
+        }
+    }
+    packets.next().is_some() || EXIF_NAMESPACES.iter().any(|ns| xmp.has_property(ns, "GPS"))
+}
+
+fn is_app(segment: &[u8], n: u8, signature: &str) -> bool {
+    segment[1] == 0xE0 + n && segment.get(4..).is_some_and(|payload| {
+        payload.starts_with(signature.as_bytes()) && payload.get(signature.len()) == Some(&0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // JFIF with a thumbnail, Exif (IFD0, Exif with a MakerNote, Interoperability, GPS, IFD1
+    // and a thumbnail), MPF, XMP with GPS under its own prefix, ICC, IPTC, COM, Adobe and Ducky,
+    // then a second image
+    const JPEG: &[u8] = include_bytes!("../tests/data/strip.jpg");
+
+    fn stripped(policy: &StripPolicy) -> (Vec<u8>, Vec<String>) {
+        let mut out = Vec::new();
+        strip(&mut Cursor::new(JPEG), &mut out, policy).unwrap();
+        let jfif = JFIF::load(&mut Cursor::new(&out)).unwrap();
+        let segments = jfif.segments().iter().map(|s| s.segment_type.to_string()).collect();
+        (out, segments)
+    }
+
+    fn load_exif(out: &[u8]) -> ExifEdit {
+        ExifEdit::load(&exif_tiff(&mut Cursor::new(out)).unwrap()).unwrap()
+    }
+
+    fn tags(exif: &ExifEdit, ifd: ExifIfd) -> Vec<u16> {
+        exif.entries(ifd).iter().map(|e| e.tag).collect()
+    }
+
+    // no MPF segment, and the stream ends with the EOI of the first image
+    fn no_trailing_image(out: &[u8], segments: &[String]) {
+        assert!(!segments.iter().any(|s| s.contains("MPF")));
+        let jfif = JFIF::load(&mut Cursor::new(out)).unwrap();
+        let eoi = jfif.segments().last().unwrap();
+        assert_eq!(eoi.segment_type.to_string(), "EOI");
+        assert_eq!(eoi.offset as usize + 2, out.len());
+    }
+
+    // the second image through the updated MP Entries, right after the first one
+    fn second_image(out: &[u8]) -> Vec<u8> {
+        let mut f = Cursor::new(out);
+        let jfif = JFIF::load(&mut f).unwrap();
+        let eoi = jfif.segments().iter().find(|s| s.segment_type.to_string() == "EOI").unwrap();
+        let mpf = jfif.mpf.as_ref().unwrap();
+        assert_eq!(mpf.images[0].size as usize, eoi.offset as usize + 2);
+        assert_eq!(mpf.image_offset(1).unwrap() as usize, eoi.offset as usize + 2);
+        let second = mpf.extract(&mut f, 1).unwrap();
+        assert!(out.ends_with(&second) && second.ends_with(&[0xFF, 0xD9]));
+        second
+    }
+
+    #[test]
+    fn all_keeps_only_the_bare_jfif_and_adobe() {
+        let (out, segments) = stripped(&StripPolicy::ALL);
+        no_trailing_image(&out, &segments);
+        let apps:Vec<&String> = segments.iter().filter(|s| s.starts_with("APP") || *s == "COM").collect();
+        assert_eq!(apps, ["APP0 \"JFIF\"", "APP14 \"Adobe\""]);
+        let jfif = JFIF::load(&mut Cursor::new(&out)).unwrap();
+        assert!(jfif.thumbnails.is_empty());
+        assert!(exif_tiff(&mut Cursor::new(&out)).is_none());
+    }
+
+    #[test]
+    fn gps_drops_the_gps_ifd_and_the_xmp_with_gps() {
+        let (out, segments) = stripped(&StripPolicy::GPS);
+        // the packet declares the Exif namespace as "loc"
+        assert!(!segments.iter().any(|s| s.contains("xap")));
+        for kept in ["APP0 \"JFIF\"", "APP2 \"ICC_PROFILE\"", "APP2 \"MPF\"", "APP13 \"Photoshop 3.0\"", "COM", "APP12 \"Ducky\""] {
+            assert!(segments.iter().any(|s| s == kept), "{}", kept);
+        }
+        let exif = load_exif(&out);
+        assert!(exif.gps.is_empty());
+        assert!(tags(&exif, ExifIfd::IFD0).contains(&0x013B));
+        assert!(tags(&exif, ExifIfd::EXIF).contains(&MAKER_NOTE));
+        assert!(exif.thumbnail.is_some());
+
+        let second = load_exif(&second_image(&out));
+        assert!(second.gps.is_empty());
+        assert!(tags(&second, ExifIfd::IFD0).contains(&0x013B));
+    }
+
+    #[test]
+    fn gps_drops_mp_images_it_cannot_find() {
+        let mut out = Vec::new();
+        strip(&mut Cursor::new(&JPEG[..JPEG.len() - 10]), &mut out, &StripPolicy::GPS).unwrap();
+        let jfif = JFIF::load(&mut Cursor::new(&out)).unwrap();
+        let segments:Vec<String> = jfif.segments().iter().map(|s| s.segment_type.to_string()).collect();
+        no_trailing_image(&out, &segments);
+        assert!(load_exif(&out).gps.is_empty());
+    }
+
+    #[test]
+    fn gps_keeps_xmp_without_gps() {
+        let packet = br#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:exif="http://ns.adobe.com/exif/1.0/" exif:ExposureTime="1/125"/></rdf:RDF></x:xmpmeta>"#;
+        let mut payload = format!("{}\0", XMP).into_bytes();
+        payload.extend_from_slice(packet);
+        let segment = new_segment(0xE1, &payload).unwrap();
+        assert!(!xmp_has_gps(&[segment]));
+
+        let mut payload = format!("{}\0", XMP).into_bytes();
+        payload.extend_from_slice(&packet[..packet.len() - 20]);
+        let broken = new_segment(0xE1, &payload).unwrap();
+        assert!(xmp_has_gps(&[broken]));
+    }
+
+    #[test]
+    fn privacy_drops_names_serials_and_thumbnails() {
+        let (out, segments) = stripped(&StripPolicy::PRIVACY);
+        no_trailing_image(&out, &segments);
+        for dropped in ["xap", "Photoshop", "Ducky"] {
+            assert!(!segments.iter().any(|s| s.contains(dropped)), "{}", dropped);
+        }
+        for kept in ["APP0 \"JFIF\"", "APP2 \"ICC_PROFILE\"", "COM", "APP14 \"Adobe\""] {
+            assert!(segments.iter().any(|s| s == kept), "{}", kept);
+        }
+        let jfif = JFIF::load(&mut Cursor::new(&out)).unwrap();
+        assert!(jfif.thumbnails.is_empty());
+
+        let exif = load_exif(&out);
+        assert_eq!(tags(&exif, ExifIfd::IFD0).iter().filter(|t| [0x013B, 0x9C9C, 0x9C9D].contains(t)).count(), 0);
+        for tag in [MAKER_NOTE, 0xA430, 0xA431, 0xA435] {
+            assert!(!tags(&exif, ExifIfd::EXIF).contains(&tag), "{:04X}", tag);
+        }
+        assert!(tags(&exif, ExifIfd::IFD0).contains(&COPYRIGHT));
+        assert!(tags(&exif, ExifIfd::EXIF).contains(&0x829A));
+        assert!(exif.gps.is_empty());
+        assert!(exif.ifd1.is_empty());
+        assert!(exif.thumbnail.is_none());
+    }
+
+    #[test]
+    fn essential_keeps_copyright_orientation_and_icc() {
+        let (out, segments) = stripped(&StripPolicy::ESSENTIAL);
+        no_trailing_image(&out, &segments);
+        let apps:Vec<&String> = segments.iter().filter(|s| s.starts_with("APP") || *s == "COM").collect();
+        assert_eq!(apps, ["APP0 \"JFIF\"", "APP1 \"Exif\"", "APP2 \"ICC_PROFILE\"", "APP14 \"Adobe\""]);
+        let exif = load_exif(&out);
+        assert_eq!(tags(&exif, ExifIfd::IFD0), [ORIENTATION, COPYRIGHT]);
+        assert!(exif.exif.is_empty() && exif.gps.is_empty() && exif.interop.is_empty() && exif.ifd1.is_empty());
+        assert!(exif.thumbnail.is_none());
+    }
+
+    #[test]
+    fn custom_scopes_the_tags_to_their_ifd() {
+        let policy = StripPolicy::CUSTOM { allow: Vec::new(), deny: vec![(ExifIfd::GPS, 0x0002), (ExifIfd::IFD1, THUMBNAIL_OFFSET)] };
+        let (out, segments) = stripped(&policy);
+        for kept in ["xap", "Photoshop", "COM", "Ducky", "ICC_PROFILE", "MPF"] {
+            assert!(segments.iter().any(|s| s.contains(kept)), "{}", kept);
+        }
+        let exif = load_exif(&out);
+        assert!(!tags(&exif, ExifIfd::GPS).contains(&0x0002));
+        assert!(tags(&exif, ExifIfd::GPS).contains(&0x0001));
+        assert!(tags(&exif, ExifIfd::INTEROP).contains(&0x0002));
+        assert!(exif.thumbnail.is_none());
+        let second = load_exif(&second_image(&out));
+        assert!(!tags(&second, ExifIfd::GPS).contains(&0x0002));
+        assert!(tags(&second, ExifIfd::GPS).contains(&0x0001));
+
+        let policy = StripPolicy::CUSTOM { allow: vec![(ExifIfd::IFD0, ORIENTATION)], deny: Vec::new() };
+        let exif = load_exif(&stripped(&policy).0);
+        assert_eq!(tags(&exif, ExifIfd::IFD0), [ORIENTATION]);
+        assert!(exif.exif.is_empty() && exif.gps.is_empty() && exif.ifd1.is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+use std::io::prelude::*;
+use crate::exif::*;
+use crate::logger;
+
+/// TIFF file: the tags of IFD0 and its Exif/GPS sub-IFDs, and one image per IFD of the chain
+/// and per SubIFD.
+pub struct TIFF {
+    pub exif: Option<EXIF>,
+    pub images: Vec<TiffImage>,
+}
+
+/// Image data layout described by an IFD.
+pub struct TiffImage {
+    /// where the IFD was found, e.g. "IFD0" or "IFD0-SubIFD1"
+    pub name: String,
+    pub subfile_type: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_sample: Vec<u32>,
+    pub samples_per_pixel: u32,
+    pub compression: u32,
+    pub photometric: u32,
+    pub planar_configuration: u32,
+    pub rows_per_strip: u32,
+    pub strip_offsets: Vec<u64>,
+    pub strip_byte_counts: Vec<u64>,
+    pub tile_width: u32,
+    pub tile_length: u32,
+    pub tile_offsets: Vec<u64>,
+    pub tile_byte_counts: Vec<u64>,
+    /// JPEGInterchangeFormat and its length, 0 when absent
+    pub jpeg_offset: u64,
+    pub jpeg_length: u64,
+}
+
+impl TIFF {
+    /// Only the IFDs and their values are read from the file.
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<TIFF> {
+        let mut src = SeekReader::new(f)?;
+        TIFF::load_from(&mut src)
+    }
+
+    pub fn load_data(data: &[u8]) -> Option<TIFF> {
+        TIFF::load_from(&mut &data[..])
+    }
+
+    pub(crate) fn load_from<S: ReadAt>(src: &mut S) -> Option<TIFF> {
+        let header = read_header(src)?;
+        if !is_tiff_magic(header.magic) {
+            logger::debug("not TIFF");
+            return None;
+        }
+
+        let mut tiff = TIFF {
+            exif: EXIF::load_from(src),
+            images: Vec::new(),
+        };
+
+        let mut visited:Vec<u64> = Vec::new();
+        let mut offset = header.offset;
+        let mut index = 0;
+        while offset != 0 && !visited.contains(&offset) {
+            visited.push(offset);
+            let ifd = match read_ifd(src, offset, &header.byte_order, header.big_tiff) {
+                Some(ifd) => ifd,
+                None => {
+                    logger::debug("broken IFD");
+                    break;
+                }
+            };
+            tiff.add_image(src, &ifd, format!("IFD{}", index), &header, &mut visited);
+            offset = ifd.next;
+            index += 1;
+        }
+
+        Some(tiff)
+    }
+
+    // the IFD and the SubIFDs (0x014A) it points to, where raw files keep the sensor data
+    fn add_image<S: ReadAt>(&mut self, src: &mut S, ifd: &Ifd, name: String, header: &TiffHeader, visited: &mut Vec<u64>) {
+        self.images.push(TiffImage::from_ifd(ifd, &header.byte_order, name.clone()));
+        let offsets = match ifd.get(0x014A) {
+            Some(entry) => entry.u64_values(&header.byte_order),
+            None => {
+                return;
+            }
+        };
+        for (i, offset) in offsets.into_iter().enumerate() {
+            if visited.contains(&offset) {
+                continue;
+            }
+            visited.push(offset);
+            match read_ifd(src, offset, &header.byte_order, header.big_tiff) {
+                Some(sub) => {
+                    self.add_image(src, &sub, format!("{}-SubIFD{}", name, i), header, visited);
+                }
+                None => {
+                    logger::debug("broken SubIFD");
+                }
+            }
+        }
+    }
+}
+
+/// 42 (TIFF), 43 (BigTIFF) and the raw formats that only change the magic number:
+/// Olympus ORF ("IIRO", "IIRS", "MMOR") and Panasonic RW2 ("IIU\0").
+pub(crate) fn is_tiff_magic(magic: u16) -> bool {
+    matches!(magic, 42 | 43 | 0x4F52 | 0x5352 | 0x0055)
+}
+
+impl TiffImage {
+    pub(crate) fn from_ifd(ifd: &Ifd, endian: &ENDIAN, name: String) -> TiffImage {
+        let values = |tag: u16| ifd.get(tag).map(|e| e.u32_values(endian)).unwrap_or_default();
+        let offsets = |tag: u16| ifd.get(tag).map(|e| e.u64_values(endian)).unwrap_or_default();
+        let value = |tag: u16, default: u32| ifd.get(tag).and_then(|e| e.u32_value(endian)).unwrap_or(default);
+
+        TiffImage {
+            name,
+            subfile_type: value(0x00FE, 0),
+            width: value(0x0100, 0),
+            height: value(0x0101, 0),
+            bits_per_sample: values(0x0102),
+            samples_per_pixel: value(0x0115, 1),
+            compression: value(0x0103, 1),
+            photometric: value(0x0106, 0),
+            planar_configuration: value(0x011C, 1),
+            rows_per_strip: value(0x0116, u32::MAX),
+            strip_offsets: offsets(0x0111),
+            strip_byte_counts: offsets(0x0117),
+            tile_width: value(0x0142, 0),
+            tile_length: value(0x0143, 0),
+            tile_offsets: offsets(0x0144),
+            tile_byte_counts: offsets(0x0145),
+            jpeg_offset: offsets(0x0201).first().copied().unwrap_or(0),
+            jpeg_length: offsets(0x0202).first().copied().unwrap_or(0),
+        }
+    }
+
+    pub fn is_tiled(&self) -> bool {
+        !self.tile_offsets.is_empty()
+    }
+
+    pub fn compression_name(&self) -> &'static str {
+        match self.compression {
+            1 => "Uncompressed",
+            2 => "CCITT 1D",
+            3 => "T4/Group 3 Fax",
+            4 => "T6/Group 4 Fax",
+            5 => "LZW",
+            6 => "JPEG (old-style)",
+            7 => "JPEG",
+            8 | 32946 => "Deflate",
+            32773 => "PackBits",
+            34712 => "JPEG 2000",
+            34892 => "Lossy JPEG",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn photometric_name(&self) -> &'static str {
+        match self.photometric {
+            0 => "WhiteIsZero",
+            1 => "BlackIsZero",
+            2 => "RGB",
+            3 => "RGB Palette",
+            4 => "Transparency Mask",
+            5 => "CMYK",
+            6 => "YCbCr",
+            8 => "CIELab",
+            32803 => "Color Filter Array",
+            34892 => "Linear Raw",
+            _ => "Unknown",
+        }
+    }
+
+    /// One line summary, e.g. "4000x3000 RGB 8,8,8 bits, LZW, 750 strips".
+    pub fn summary(&self) -> String {
+        let bits:Vec<String> = self.bits_per_sample.iter().map(|b| b.to_string()).collect();
+        let layout = if self.is_tiled() {
+            format!("{} tiles of {}x{}", self.tile_offsets.len(), self.tile_width, self.tile_length)
+        } else {
+            format!("{} strips", self.strip_offsets.len())
+        };
+        let bits = if bits.is_empty() { "unknown".to_string() } else { bits.join(",") };
+        format!("{}x{} {} {} bits, {}, {}",
+            self.width, self.height, self.photometric_name(), bits, self.compression_name(), layout)
+    }
+}
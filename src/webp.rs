@@ -0,0 +1,175 @@
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use crate::exif::*;
+use crate::xmp::*;
+use crate::icc::*;
+use crate::logger;
+use crate::bytes::*;
+
+/// WebP image: the RIFF chunks of a simple (VP8, VP8L) or extended (VP8X) file.
+pub struct WebP {
+    /// "VP8 ", "VP8L" or "VP8X", the first chunk
+    pub format: String,
+    /// canvas size for VP8X, else the bitstream size
+    pub width: u32,
+    pub height: u32,
+    /// None when only animation frames hold the bitstreams
+    pub lossless: Option<bool>,
+    pub alpha: bool,
+    pub animation: bool,
+    pub frames: u32,
+    pub exif: Option<EXIF>,
+    pub xmp: Option<XMP>,
+    pub icc: Option<ICC>,
+}
+
+// VP8X flags
+const ALPHA_FLAG: u8 = 0x10;
+const ANIMATION_FLAG: u8 = 0x02;
+
+// larger chunks are not metadata
+const MAX_CHUNK: u64 = 1 << 26;
+
+impl WebP {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<WebP> {
+        let mut head = [0; 12];
+        f.read_exact(&mut head).ok()?;
+        if &head[0..4] != b"RIFF" || &head[8..12] != b"WEBP" {
+            logger::debug("not WebP");
+            return None;
+        }
+        let end = 8 + le32(&head, 4) as u64;
+
+        let mut webp = WebP {
+            format: String::new(),
+            width: 0,
+            height: 0,
+            lossless: None,
+            alpha: false,
+            animation: false,
+            frames: 0,
+            exif: None,
+            xmp: None,
+            icc: None,
+        };
+
+        let mut offset:u64 = 12;
+        while offset + 8 <= end {
+            let mut head = [0; 8];
+            if f.read_exact(&mut head).is_err() {
+                logger::debug("short RIFF");
+                break;
+            }
+            let fourcc = [head[0], head[1], head[2], head[3]];
+            let size = le32(&head, 4) as u64;
+            if offset + 8 + size > end {
+                logger::debug("chunk beyond RIFF end");
+                break;
+            }
+            // bitstreams and frames are large, only their headers are read
+            let wanted = match &fourcc {
+                b"VP8 " => size.min(10),
+                b"VP8L" => size.min(5),
+                b"VP8X" | b"EXIF" | b"XMP " | b"ICCP" if size <= MAX_CHUNK => size,
+                b"EXIF" | b"XMP " | b"ICCP" => {
+                    logger::debug("chunk too large, skipped");
+                    0
+                }
+                // frames, alpha and unknown chunks are skipped
+                _ => 0,
+            };
+            let mut data = vec![0; wanted as usize];
+            if f.read_exact(&mut data).is_err() {
+                logger::debug("short chunk");
+                break;
+            }
+            if webp.format.is_empty() {
+                webp.format = String::from_utf8_lossy(&fourcc).to_string();
+            }
+            webp.chunk(&fourcc, &data);
+
+            // chunks are padded to even sizes
+            offset += 8 + size + (size & 1);
+            if f.seek(SeekFrom::Start(offset)).is_err() {
+                break;
+            }
+        }
+
+        Some(webp)
+    }
+
+    fn chunk(&mut self, fourcc: &[u8; 4], data: &[u8]) {
+        match fourcc {
+            b"VP8X" => {
+                if data.len() >= 10 {
+                    self.alpha = data[0] & ALPHA_FLAG != 0;
+                    self.animation = data[0] & ANIMATION_FLAG != 0;
+                    self.width = le24(data, 4) + 1;
+                    self.height = le24(data, 7) + 1;
+                }
+            }
+            b"VP8 " => {
+                // frame tag, start code 9D 01 2A, 14-bit width and height
+                if data.len() >= 10 && data[3..6] == [0x9D, 0x01, 0x2A] {
+                    if self.width == 0 {
+                        self.width = (le16(data, 6) & 0x3FFF) as u32;
+                        self.height = (le16(data, 8) & 0x3FFF) as u32;
+                    }
+                    self.lossless = Some(false);
+                } else {
+                    logger::debug("broken VP8 header");
+                }
+            }
+            b"VP8L" => {
+                // signature 0x2F, 14-bit width-1 and height-1, alpha hint
+                if data.len() >= 5 && data[0] == 0x2F {
+                    let bits = le32(data, 1);
+                    if self.width == 0 {
+                        self.width = (bits & 0x3FFF) + 1;
+                        self.height = (bits >> 14 & 0x3FFF) + 1;
+                        self.alpha = bits >> 28 & 1 != 0;
+                    }
+                    self.lossless = Some(true);
+                } else {
+                    logger::debug("broken VP8L header");
+                }
+            }
+            b"ANMF" => {
+                self.frames += 1;
+            }
+            b"EXIF" => {
+                let tiff = data.strip_prefix(b"Exif\0\0").unwrap_or(data);
+                self.exif = EXIF::load(tiff);
+            }
+            b"XMP " => {
+                self.xmp = XMP::load(data);
+            }
+            b"ICCP" => {
+                self.icc = ICC::load(data);
+            }
+            _ => {
+                logger::debug(format!("chunk {}", String::from_utf8_lossy(fourcc)).as_str());
+            }
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let yes_no = |b: bool| if b { "Yes" } else { "No" }.to_string();
+        let mut entries = vec![
+            ("Format".to_string(), self.format.trim_end().to_string()),
+            ("ImageWidth".to_string(), self.width.to_string()),
+            ("ImageHeight".to_string(), self.height.to_string()),
+        ];
+        match self.lossless {
+            Some(true) => entries.push(("Compression".to_string(), "Lossless".to_string())),
+            Some(false) => entries.push(("Compression".to_string(), "Lossy".to_string())),
+            None => {}
+        }
+        entries.push(("Alpha".to_string(), yes_no(self.alpha)));
+        entries.push(("Animation".to_string(), yes_no(self.animation)));
+        if self.animation {
+            entries.push(("Frames".to_string(), self.frames.to_string()));
+        }
+        entries
+    }
+}
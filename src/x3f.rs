@@ -0,0 +1,172 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use crate::exif::*;
+use crate::logger;
+use crate::bytes::*;
+
+/// Sigma X3F: the header, the property list and the JPEG preview found through the section directory.
+pub struct X3F {
+    pub version: (u16, u16),
+    pub width: u32,
+    pub height: u32,
+    pub rotation: u32,
+    /// 'PROP' name/value pairs (CAMMANUF, CAMMODEL, ISO, ...)
+    pub properties: Vec<(String, String)>,
+    pub previews: Vec<X3FImage>,
+}
+
+/// A processed 'IMAG' section holding a JPEG.
+pub struct X3FImage {
+    pub offset: u64,
+    pub length: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+const X3F_SIGNATURE: &[u8] = b"FOVb";
+
+// image section: type 2 is processed for preview, format 18 is JPEG
+const IMAGE_PREVIEW: u32 = 2;
+const IMAGE_JPEG: u32 = 18;
+const IMAGE_HEADER: u64 = 28;
+
+// property lists are a few KB
+const MAX_PROPERTIES: u32 = 1 << 20;
+
+impl X3F {
+    pub fn load<R: Read + Seek>(f: &mut R) -> Option<X3F> {
+        let mut head = [0; 40];
+        f.read_exact(&mut head).ok()?;
+        if !head.starts_with(X3F_SIGNATURE) {
+            logger::debug("not X3F");
+            return None;
+        }
+
+        let mut x3f = X3F {
+            version: (le16(&head, 6), le16(&head, 4)),
+            width: le32(&head, 28),
+            height: le32(&head, 32),
+            rotation: le32(&head, 36),
+            properties: Vec::new(),
+            previews: Vec::new(),
+        };
+
+        // the last 4 bytes point to the directory: "SECd", version, count, then offset, length and type
+        let mut pointer = [0; 4];
+        f.seek(SeekFrom::End(-4)).ok()?;
+        f.read_exact(&mut pointer).ok()?;
+        f.seek(SeekFrom::Start(le32(&pointer, 0) as u64)).ok()?;
+        let mut directory = [0; 12];
+        f.read_exact(&mut directory).ok()?;
+        if &directory[0..4] != b"SECd" {
+            logger::debug("no X3F directory");
+            return Some(x3f);
+        }
+        let directory_offset = le32(&pointer, 0) as u64;
+        let count = le32(&directory, 8).min(1024);
+        let mut src = SeekReader::new(&mut *f)?;
+        let entries = match (count as usize).checked_mul(12).and_then(|len| src.read_at(directory_offset + 12, len)) {
+            Some(entries) => entries,
+            None => {
+                logger::debug("short X3F directory");
+                return Some(x3f);
+            }
+        };
+
+        for entry in entries.chunks_exact(12) {
+            let offset = le32(entry, 0) as u64;
+            let length = le32(entry, 4);
+            match &entry[8..12] {
+                b"PROP" => {
+                    if length > MAX_PROPERTIES {
+                        logger::debug("property list too large");
+                        continue;
+                    }
+                    if let Some(data) = src.read_at(offset, length as usize) {
+                        x3f.properties.extend(properties(&data).unwrap_or_default());
+                    }
+                }
+                b"IMAG" | b"IMA2" => {
+                    let head = match src.read_at(offset, IMAGE_HEADER as usize) {
+                        Some(head) => head,
+                        None => {
+                            continue;
+                        }
+                    };
+                    if &head[0..4] == b"SECi" && le32(&head, 8) == IMAGE_PREVIEW && le32(&head, 12) == IMAGE_JPEG {
+                        x3f.previews.push(X3FImage {
+                            offset: offset + IMAGE_HEADER,
+                            length: (length as u64).saturating_sub(IMAGE_HEADER),
+                            width: le32(&head, 16),
+                            height: le32(&head, 20),
+                        });
+                    }
+                }
+                section => {
+                    logger::debug(format!("X3F section {}", String::from_utf8_lossy(section)).as_str());
+                }
+            }
+        }
+
+        Some(x3f)
+    }
+
+    /// The largest JPEG preview, as it is.
+    pub fn extract_preview<R: Read + Seek>(&self, f: &mut R) -> io::Result<Vec<u8>> {
+        let preview = match self.previews.iter().max_by_key(|p| p.width as u64 * p.height as u64) {
+            Some(preview) => preview,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "no preview"));
+            }
+        };
+        // checked against the file size before allocating
+        let mut src = SeekReader::new(f).ok_or_else(|| io::Error::other("cant seek"))?;
+        match src.read_at(preview.offset, preview.length as usize) {
+            Some(image) => Ok(image),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "preview beyond the end of the file")),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![
+            ("Version".to_string(), format!("{}.{}", self.version.0, self.version.1)),
+            ("ImageWidth".to_string(), self.width.to_string()),
+            ("ImageHeight".to_string(), self.height.to_string()),
+            ("Rotation".to_string(), self.rotation.to_string()),
+        ];
+        entries.extend(self.properties.iter().cloned());
+        entries
+    }
+}
+
+// "SECp", version, count, character format, reserved, total length,
+// then count name and value offsets into the NUL terminated UTF-16 characters
+fn properties(data: &[u8]) -> Option<Vec<(String, String)>> {
+    if data.len() < 24 || &data[0..4] != b"SECp" {
+        logger::debug("broken property list");
+        return None;
+    }
+    if le32(data, 12) != 0 {
+        logger::debug("unknown property character format");
+        return None;
+    }
+    let count = le32(data, 8) as usize;
+    let chars_start = 24usize.checked_add(count.checked_mul(8)?)?;
+    let chars:Vec<u16> = data.get(chars_start..)?.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    let text = |offset: u32| -> Option<String> {
+        let start = offset as usize;
+        let rest = chars.get(start..)?;
+        let end = rest.iter().position(|&c| c == 0).unwrap_or(rest.len());
+        Some(String::from_utf16_lossy(&rest[..end]))
+    };
+
+    let mut values = Vec::new();
+    for i in 0..count {
+        let entry = 24 + i * 8;
+        let name = text(le32(data, entry))?;
+        let value = text(le32(data, entry + 4))?;
+        values.push((name, value));
+    }
+    Some(values)
+}
@@ -0,0 +1,696 @@
+use crate::logger;
+use crate::md5::md5;
+
+/// XMP packet parsed into a property tree.
+/// Property names use the usual prefix of well known namespaces
+/// ("dc:subject", "xmp:Rating", ...) whatever prefix the packet declares.
+pub struct XMP {
+    pub properties: Vec<(String, XMPValue)>,
+}
+
+/// Chunks of an extended XMP packet, as stored in JPEG APP1
+/// "http://ns.adobe.com/xmp/extension/" segments.
+pub(crate) struct ExtendedXMP {
+    chunks: Vec<ExtendedXMPChunk>,
+}
+
+struct ExtendedXMPChunk {
+    guid: String,
+    full_length: u32,
+    offset: u32,
+    data: Vec<u8>,
+}
+
+pub enum XMPValue {
+    Text(String),
+    Struct(Vec<(String, XMPValue)>),
+    Seq(Vec<XMPValue>),
+    Bag(Vec<XMPValue>),
+    /// alternatives with their xml:lang ("" if none)
+    Alt(Vec<(String, XMPValue)>),
+}
+
+const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XML_NS: &str = "http://www.w3.org/XML/1998/namespace";
+
+// nesting levels of elements, and of the values built from them; packets nest a dozen at most
+const MAX_DEPTH: usize = 128;
+
+const NAMESPACES: &[(&str, &str)] = &[
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("xmpMM", "http://ns.adobe.com/xap/1.0/mm/"),
+    ("xmpRights", "http://ns.adobe.com/xap/1.0/rights/"),
+    ("xmpNote", "http://ns.adobe.com/xmp/note/"),
+    ("photoshop", "http://ns.adobe.com/photoshop/1.0/"),
+    ("exif", "http://ns.adobe.com/exif/1.0/"),
+    ("exifEX", "http://cipa.jp/exif/1.0/"),
+    ("tiff", "http://ns.adobe.com/tiff/1.0/"),
+    ("aux", "http://ns.adobe.com/exif/1.0/aux/"),
+    ("crs", "http://ns.adobe.com/camera-raw-settings/1.0/"),
+    ("lr", "http://ns.adobe.com/lightroom/1.0/"),
+    ("stEvt", "http://ns.adobe.com/xap/1.0/sType/ResourceEvent#"),
+    ("stRef", "http://ns.adobe.com/xap/1.0/sType/ResourceRef#"),
+    ("Iptc4xmpCore", "http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/"),
+    ("rdf", RDF_NS),
+    ("x", "adobe:ns:meta/"),
+];
+
+impl XMP {
+    pub fn load(data: &[u8]) -> Option<XMP> {
+        let text = String::from_utf8_lossy(data);
+        let mut parser = Parser {
+            s: text.as_bytes(),
+            pos: 0,
+            scopes: Vec::new(),
+        };
+        let elements = parser.document();
+        let rdf = match elements.iter().find_map(|element| find_rdf(element, 0)) {
+            Some(rdf) => rdf,
+            None => {
+                logger::debug("no rdf:RDF");
+                return None;
+            }
+        };
+
+        let mut xmp = XMP {
+            properties: Vec::new(),
+        };
+        for description in &rdf.children {
+            if description.ns != RDF_NS || description.name != "Description" {
+                continue;
+            }
+            xmp.properties.append(&mut struct_fields(description, 0));
+        }
+
+        Some(xmp)
+    }
+
+    /// Adds the properties of the extended packet announced by xmpNote:HasExtendedXMP.
+    pub(crate) fn merge_extended(&mut self, extended: &ExtendedXMP) {
+        let guid = match self.get("xmpNote:HasExtendedXMP").and_then(|v| v.as_text()) {
+            Some(guid) => guid.to_string(),
+            None => {
+                logger::debug("no HasExtendedXMP");
+                return;
+            }
+        };
+        let packet = match extended.assemble(&guid) {
+            Some(packet) => packet,
+            None => {
+                return;
+            }
+        };
+        if let Some(mut ext) = XMP::load(&packet) {
+            self.properties.retain(|(name, _)| name != "xmpNote:HasExtendedXMP");
+            self.properties.append(&mut ext.properties);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&XMPValue> {
+        self.properties.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    /// Whether a top-level property of a well known namespace, given by its URI, has a name
+    /// starting with `local`; e.g. ("http://ns.adobe.com/exif/1.0/", "GPS") for any GPS property.
+    pub fn has_property(&self, namespace: &str, local: &str) -> bool {
+        let prefix = match NAMESPACES.iter().find(|(_, uri)| *uri == namespace) {
+            Some((prefix, _)) => format!("{}:{}", prefix, local),
+            None => {
+                return false;
+            }
+        };
+        self.properties.iter().any(|(name, _)| name.starts_with(&prefix))
+    }
+
+    /// Flat "name: value" view of the tree. Struct fields are joined to their
+    /// parent with '/', array items are numbered from 1 unless they are text.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        for (name, value) in &self.properties {
+            flatten(name, value, &mut entries, 0);
+        }
+        entries
+    }
+}
+
+impl ExtendedXMP {
+    pub(crate) fn new() -> ExtendedXMP {
+        ExtendedXMP {
+            chunks: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// payload: GUID (32 hex digits), full length, offset, data
+    pub(crate) fn add(&mut self, payload: &[u8]) {
+        if payload.len() < 40 {
+            logger::debug("short extended XMP");
+            return;
+        }
+        let be32 = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+        self.chunks.push(ExtendedXMPChunk {
+            guid: String::from_utf8_lossy(&payload[0..32]).to_string(),
+            full_length: be32(&payload[32..36]),
+            offset: be32(&payload[36..40]),
+            data: payload[40..].to_vec(),
+        });
+    }
+
+    // chunks of the GUID put in order, checked to cover the whole packet
+    // and to hash to the GUID
+    fn assemble(&self, guid: &str) -> Option<Vec<u8>> {
+        let mut chunks:Vec<&ExtendedXMPChunk> = self.chunks.iter().filter(|c| c.guid.eq_ignore_ascii_case(guid)).collect();
+        if chunks.is_empty() {
+            logger::debug("no extended XMP for the GUID");
+            return None;
+        }
+        chunks.sort_by_key(|c| c.offset);
+
+        // the chunks found must add up to the announced length before it is trusted
+        let full_length = chunks[0].full_length as usize;
+        if chunks.iter().map(|c| c.data.len()).sum::<usize>() != full_length {
+            logger::debug("extended XMP chunks missing or overlapping");
+            return None;
+        }
+        let mut packet:Vec<u8> = Vec::with_capacity(full_length);
+        for chunk in chunks {
+            if chunk.full_length as usize != full_length || chunk.offset as usize != packet.len() {
+                logger::debug("extended XMP chunks missing or overlapping");
+                return None;
+            }
+            packet.extend_from_slice(&chunk.data);
+        }
+        if packet.len() != full_length {
+            logger::debug("extended XMP is short");
+            return None;
+        }
+
+        let digest:String = md5(&packet).iter().map(|b| format!("{:02X}", b)).collect();
+        if !digest.eq_ignore_ascii_case(guid) {
+            logger::debug("extended XMP MD5 mismatch");
+            return None;
+        }
+        Some(packet)
+    }
+}
+
+impl XMPValue {
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            XMPValue::Text(s) => Some(s),
+            XMPValue::Alt(items) => items.first().and_then(|(_, v)| v.as_text()),
+            _ => None,
+        }
+    }
+}
+
+fn flatten(name: &str, value: &XMPValue, entries: &mut Vec<(String, String)>, depth: usize) {
+    if depth > MAX_DEPTH {
+        logger::debug("XMP value nested too deep");
+        return;
+    }
+    match value {
+        XMPValue::Text(s) => {
+            entries.push((name.to_string(), s.clone()));
+        }
+        XMPValue::Struct(fields) => {
+            for (field, value) in fields {
+                flatten(&format!("{}/{}", name, field), value, entries, depth + 1);
+            }
+        }
+        XMPValue::Seq(items) | XMPValue::Bag(items) => {
+            if items.iter().all(|item| matches!(item, XMPValue::Text(_))) {
+                let texts:Vec<&str> = items.iter().filter_map(|item| item.as_text()).collect();
+                entries.push((name.to_string(), texts.join(", ")));
+            } else {
+                for (i, item) in items.iter().enumerate() {
+                    flatten(&format!("{}[{}]", name, i + 1), item, entries, depth + 1);
+                }
+            }
+        }
+        XMPValue::Alt(items) => {
+            for (lang, item) in items {
+                if lang.is_empty() || lang == "x-default" {
+                    flatten(name, item, entries, depth + 1);
+                } else {
+                    flatten(&format!("{}-{}", name, lang), item, entries, depth + 1);
+                }
+            }
+        }
+    }
+}
+
+fn find_rdf(element: &Element, depth: usize) -> Option<&Element> {
+    if element.ns == RDF_NS && element.name == "RDF" {
+        return Some(element);
+    }
+    if depth >= MAX_DEPTH {
+        return None;
+    }
+    element.children.iter().find_map(|child| find_rdf(child, depth + 1))
+}
+
+fn property_name(ns: &str, prefix: &str, name: &str) -> String {
+    let prefix = NAMESPACES.iter().find(|(_, uri)| *uri == ns).map(|(p, _)| *p).unwrap_or(prefix);
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}:{}", prefix, name)
+    }
+}
+
+// properties given as attributes and child elements of a node
+fn struct_fields(element: &Element, depth: usize) -> Vec<(String, XMPValue)> {
+    let mut fields = Vec::new();
+    if depth > MAX_DEPTH {
+        logger::debug("XMP struct nested too deep");
+        return fields;
+    }
+    for attr in &element.attributes {
+        if !is_property(attr) {
+            continue;
+        }
+        fields.push((property_name(&attr.ns, &attr.prefix, &attr.name), XMPValue::Text(attr.value.clone())));
+    }
+    for child in &element.children {
+        fields.push((property_name(&child.ns, &child.prefix, &child.name), property_value(child, depth + 1)));
+    }
+    fields
+}
+
+fn is_property(attr: &Attribute) -> bool {
+    attr.ns != RDF_NS && attr.ns != XML_NS && !attr.prefix.is_empty()
+}
+
+fn property_value(element: &Element, depth: usize) -> XMPValue {
+    if let Some(resource) = element.attribute(RDF_NS, "resource") {
+        return XMPValue::Text(resource.to_string());
+    }
+    if element.attribute(RDF_NS, "parseType") == Some("Resource") {
+        return XMPValue::Struct(struct_fields(element, depth));
+    }
+
+    if let Some(child) = element.children.first() {
+        if child.ns == RDF_NS {
+            match child.name.as_str() {
+                "Seq" => {
+                    return XMPValue::Seq(child.items().map(|li| property_value(li, depth + 1)).collect());
+                }
+                "Bag" => {
+                    return XMPValue::Bag(child.items().map(|li| property_value(li, depth + 1)).collect());
+                }
+                "Alt" => {
+                    return XMPValue::Alt(child.items().map(|li| {
+                        let lang = li.attribute(XML_NS, "lang").unwrap_or("");
+                        (lang.to_string(), property_value(li, depth + 1))
+                    }).collect());
+                }
+                "Description" => {
+                    return described_value(child, depth + 1);
+                }
+                _ => {}
+            }
+        }
+        return XMPValue::Struct(struct_fields(element, depth));
+    }
+
+    // shorthand struct: fields given as attributes of the property element
+    if element.attributes.iter().any(is_property) {
+        return XMPValue::Struct(struct_fields(element, depth));
+    }
+
+    XMPValue::Text(element.text.clone())
+}
+
+fn described_value(description: &Element, depth: usize) -> XMPValue {
+    // a qualified simple value carries its value in rdf:value
+    if let Some(value) = description.attribute(RDF_NS, "value") {
+        return XMPValue::Text(value.to_string());
+    }
+    if let Some(value) = description.children.iter().find(|c| c.ns == RDF_NS && c.name == "value") {
+        return property_value(value, depth + 1);
+    }
+    XMPValue::Struct(struct_fields(description, depth))
+}
+
+struct Element {
+    ns: String,
+    prefix: String,
+    name: String,
+    attributes: Vec<Attribute>,
+    children: Vec<Element>,
+    text: String,
+}
+
+struct Attribute {
+    ns: String,
+    prefix: String,
+    name: String,
+    value: String,
+}
+
+impl Element {
+    fn attribute(&self, ns: &str, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|a| a.ns == ns && a.name == name).map(|a| a.value.as_str())
+    }
+
+    fn items(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter(|c| c.ns == RDF_NS && c.name == "li")
+    }
+}
+
+// Minimal XML reader: enough for XMP packets (no DTD, no validation).
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+    scopes: Vec<Vec<(String, String)>>,
+}
+
+impl Parser<'_> {
+    fn document(&mut self) -> Vec<Element> {
+        let mut elements = Vec::new();
+        while self.pos < self.s.len() {
+            if self.starts_with("<?") {
+                self.skip_past("?>");
+            } else if self.starts_with("<!--") {
+                self.skip_past("-->");
+            } else if self.starts_with("<!") {
+                self.skip_past(">");
+            } else if self.starts_with("<") {
+                match self.element(0) {
+                    Some(element) => elements.push(element),
+                    None => {
+                        logger::debug("broken XML");
+                        break;
+                    }
+                }
+            } else {
+                self.pos += 1;
+            }
+        }
+        elements
+    }
+
+    fn element(&mut self, depth: usize) -> Option<Element> {
+        if depth >= MAX_DEPTH {
+            logger::debug("XML nested too deep");
+            return None;
+        }
+        self.pos += 1; // '<'
+        let qname = self.name();
+        let mut raw_attributes:Vec<(String, String)> = Vec::new();
+        let mut empty = false;
+        loop {
+            self.skip_spaces();
+            if self.starts_with("/>") {
+                self.pos += 2;
+                empty = true;
+                break;
+            }
+            if self.starts_with(">") {
+                self.pos += 1;
+                break;
+            }
+            if self.pos >= self.s.len() {
+                return None;
+            }
+            let name = self.name();
+            if name.is_empty() {
+                return None;
+            }
+            self.skip_spaces();
+            if !self.starts_with("=") {
+                return None;
+            }
+            self.pos += 1;
+            self.skip_spaces();
+            let quote = *self.s.get(self.pos)?;
+            if quote != b'"' && quote != b'\'' {
+                return None;
+            }
+            self.pos += 1;
+            let start = self.pos;
+            while self.pos < self.s.len() && self.s[self.pos] != quote {
+                self.pos += 1;
+            }
+            let value = decode_entities(&self.s[start..self.pos]);
+            self.pos += 1;
+            raw_attributes.push((name, value));
+        }
+
+        // namespace declarations are in scope for the element itself
+        let scope = raw_attributes.iter().filter_map(|(name, value)| {
+            if name == "xmlns" {
+                Some((String::new(), value.clone()))
+            } else {
+                name.strip_prefix("xmlns:").map(|prefix| (prefix.to_string(), value.clone()))
+            }
+        }).collect();
+        self.scopes.push(scope);
+
+        let (prefix, name) = split_name(&qname);
+        let mut element = Element {
+            ns: self.resolve(prefix, true),
+            prefix: prefix.to_string(),
+            name: name.to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        };
+        for (qname, value) in raw_attributes {
+            if qname == "xmlns" || qname.starts_with("xmlns:") {
+                continue;
+            }
+            let (prefix, name) = split_name(&qname);
+            element.attributes.push(Attribute {
+                ns: self.resolve(prefix, false),
+                prefix: prefix.to_string(),
+                name: name.to_string(),
+                value,
+            });
+        }
+
+        if !empty {
+            loop {
+                if self.pos >= self.s.len() {
+                    self.scopes.pop();
+                    return None;
+                }
+                if self.starts_with("</") {
+                    self.skip_past(">");
+                    break;
+                } else if self.starts_with("<!--") {
+                    self.skip_past("-->");
+                } else if self.starts_with("<![CDATA[") {
+                    let start = self.pos + 9;
+                    self.skip_past("]]>");
+                    let end = self.pos.saturating_sub(3).max(start);
+                    element.text.push_str(&String::from_utf8_lossy(&self.s[start..end]));
+                } else if self.starts_with("<?") {
+                    self.skip_past("?>");
+                } else if self.starts_with("<") {
+                    let child = self.element(depth + 1)?;
+                    element.children.push(child);
+                } else {
+                    let start = self.pos;
+                    while self.pos < self.s.len() && self.s[self.pos] != b'<' {
+                        self.pos += 1;
+                    }
+                    element.text.push_str(&decode_entities(&self.s[start..self.pos]));
+                }
+            }
+        }
+        self.scopes.pop();
+
+        if !element.children.is_empty() {
+            element.text.clear();
+        }
+        Some(element)
+    }
+
+    fn resolve(&self, prefix: &str, element: bool) -> String {
+        if prefix == "xml" {
+            return XML_NS.to_string();
+        }
+        if prefix.is_empty() && !element {
+            return String::new();
+        }
+        for scope in self.scopes.iter().rev() {
+            if let Some((_, uri)) = scope.iter().find(|(p, _)| p == prefix) {
+                return uri.clone();
+            }
+        }
+        String::new()
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.pos;
+        while self.pos < self.s.len() {
+            let c = self.s[self.pos];
+            if c.is_ascii_whitespace() || c == b'=' || c == b'>' || c == b'/' {
+                break;
+            }
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.s[start..self.pos]).to_string()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.pos < self.s.len() && self.s[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn starts_with(&self, token: &str) -> bool {
+        self.s[self.pos.min(self.s.len())..].starts_with(token.as_bytes())
+    }
+
+    fn skip_past(&mut self, token: &str) {
+        while self.pos < self.s.len() && !self.starts_with(token) {
+            self.pos += 1;
+        }
+        self.pos = (self.pos + token.len()).min(self.s.len());
+    }
+}
+
+fn split_name(qname: &str) -> (&str, &str) {
+    match qname.split_once(':') {
+        Some((prefix, name)) => (prefix, name),
+        None => ("", qname),
+    }
+}
+
+fn decode_entities(raw: &[u8]) -> String {
+    let raw = String::from_utf8_lossy(raw);
+    if !raw.contains('&') {
+        return raw.to_string();
+    }
+
+    let mut out = String::with_capacity(raw.len());
+    let mut rest = raw.as_ref();
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse::<u32>().ok().and_then(char::from_u32)
+                } else {
+                    None
+                }
+            }
+        };
+        match c {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end+1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:e="http://ns.adobe.com/exif/1.0/" e:ExposureTime="1/125"><e:ISOSpeedRatings><rdf:Seq><rdf:li>200</rdf:li></rdf:Seq></e:ISOSpeedRatings></rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+    #[test]
+    fn properties_use_the_well_known_prefix() {
+        let xmp = XMP::load(PACKET.as_bytes()).unwrap();
+        assert_eq!(xmp.get("exif:ExposureTime").and_then(|v| v.as_text()), Some("1/125"));
+        assert!(xmp.has_property("http://ns.adobe.com/exif/1.0/", "ISO"));
+        assert_eq!(xmp.entries(), [
+            ("exif:ExposureTime".to_string(), "1/125".to_string()),
+            ("exif:ISOSpeedRatings".to_string(), "200".to_string()),
+        ]);
+    }
+
+    // GUID, full length, offset and data of each chunk
+    fn chunks(packet: &[u8], sizes: &[usize]) -> ExtendedXMP {
+        let guid:String = md5(packet).iter().map(|b| format!("{:02X}", b)).collect();
+        let mut extended = ExtendedXMP::new();
+        let mut offset = 0;
+        for &size in sizes {
+            let mut payload = guid.clone().into_bytes();
+            payload.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+            payload.extend_from_slice(&(offset as u32).to_be_bytes());
+            payload.extend_from_slice(&packet[offset..offset + size]);
+            extended.add(&payload);
+            offset += size;
+        }
+        extended
+    }
+
+    #[test]
+    fn extended_packet_is_merged() {
+        let guid:String = md5(PACKET.as_bytes()).iter().map(|b| format!("{:02X}", b)).collect();
+        let main = format!(r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"><rdf:Description xmlns:xmpNote="http://ns.adobe.com/xmp/note/" xmpNote:HasExtendedXMP="{}"/></rdf:RDF></x:xmpmeta>"#, guid);
+        let mut xmp = XMP::load(main.as_bytes()).unwrap();
+        let mut extended = chunks(PACKET.as_bytes(), &[100, PACKET.len() - 100]);
+        // out of order
+        extended.chunks.reverse();
+        xmp.merge_extended(&extended);
+        assert!(xmp.get("xmpNote:HasExtendedXMP").is_none());
+        assert_eq!(xmp.get("exif:ExposureTime").and_then(|v| v.as_text()), Some("1/125"));
+    }
+
+    #[test]
+    fn extended_packet_is_checked() {
+        let packet = PACKET.as_bytes();
+        let guid:String = md5(packet).iter().map(|b| format!("{:02X}", b)).collect();
+        assert!(chunks(packet, &[100, packet.len() - 100]).assemble(&guid).is_some());
+        // missing chunk
+        assert!(chunks(packet, &[100]).assemble(&guid).is_none());
+        // a huge announced length with a single short chunk
+        let mut payload = guid.clone().into_bytes();
+        payload.extend_from_slice(&u32::MAX.to_be_bytes());
+        payload.extend_from_slice(&0u32.to_be_bytes());
+        payload.extend_from_slice(&packet[..40]);
+        let mut extended = ExtendedXMP::new();
+        extended.add(&payload);
+        assert!(extended.assemble(&guid).is_none());
+        // altered data
+        let mut altered = packet.to_vec();
+        altered[10] ^= 1;
+        let mut extended = chunks(&altered, &[altered.len()]);
+        extended.chunks[0].guid = guid.clone();
+        assert!(extended.assemble(&guid).is_none());
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let depth = 200_000;
+        let mut packet = "<a>".repeat(depth);
+        packet.push_str(&"</a>".repeat(depth));
+        assert!(XMP::load(packet.as_bytes()).is_none());
+
+        // within the limit, around the packet
+        let mut packet = "<a>".repeat(MAX_DEPTH - 6);
+        packet.push_str(PACKET);
+        packet.push_str(&"</a>".repeat(MAX_DEPTH - 6));
+        assert!(XMP::load(packet.as_bytes()).is_some());
+    }
+}